@@ -19,9 +19,11 @@ use x25519_dalek::StaticSecret;
 
 use fc_client::{
     channel::CorrespondentId,
-    combined::CombinedMessageStream,
     group::Group,
+    message_repository::MessageRepository,
     messenger::{DecryptedMessage, Messenger},
+    prover::Prover,
+    sync::NotificationSync,
     wallet::Wallet,
 };
 
@@ -36,6 +38,8 @@ struct Environment {
     messenger_secret_key: String,
     key_registry_account_id: AccountId,
     message_repository_account_id: AccountId,
+    message_directory_account_id: Option<AccountId>,
+    proving_key_path: Option<PathBuf>,
 }
 
 fn network_rpc_url(network: Option<String>) -> String {
@@ -49,10 +53,11 @@ fn network_rpc_url(network: Option<String>) -> String {
 }
 
 fn monitor_conversation(
+    message_repository: Arc<MessageRepository>,
     group: Arc<Group>,
 ) -> (
     impl Fn(),
-    tokio::sync::mpsc::Receiver<(CorrespondentId, DecryptedMessage)>,
+    tokio::sync::mpsc::Receiver<anyhow::Result<(CorrespondentId, DecryptedMessage)>>,
 ) {
     let alive = Arc::new(AtomicBool::new(true));
     let (send, recv) = tokio::sync::mpsc::channel(1);
@@ -66,12 +71,29 @@ fn monitor_conversation(
 
     tokio::spawn({
         async move {
-            let mut messages = CombinedMessageStream::new(group.streams());
+            let mut sync = NotificationSync::new(message_repository);
 
             while alive.load(Ordering::SeqCst) {
-                if let Some((sender, message)) = messages.next().await.unwrap() {
-                    send.send((sender.clone(), message)).await.unwrap();
-                } else {
+                // a failed sync is retried on the next round
+                let mut received = match sync.sync(&[&group]).await {
+                    Ok(synced) => synced
+                        .into_iter()
+                        .map(|synced| Ok((synced.sender, synced.message)))
+                        .collect(),
+                    Err(error) => vec![Err(error)],
+                };
+                received.extend(group.take_rejected().await.into_iter().map(|rejected| {
+                    Err(rejected
+                        .error
+                        .context("Skipped a message that could not be read"))
+                }));
+
+                for received in received {
+                    if send.send(received).await.is_err() {
+                        return;
+                    }
+                }
+                if sync.is_caught_up() {
                     sleep(Duration::from_millis(500)).await;
                 }
             }
@@ -113,12 +135,24 @@ async fn main() -> anyhow::Result<()> {
         .try_into()
         .unwrap();
 
-    let messenger = Arc::new(Messenger::new(
+    let mut messenger = Messenger::new(
         Arc::clone(&wallet),
         StaticSecret::from(messenger_secret_key),
         &env.key_registry_account_id,
         &env.message_repository_account_id,
-    ));
+    );
+
+    if let Some(directory_id) = &env.message_directory_account_id {
+        messenger = messenger.with_message_repository(
+            MessageRepository::from_directory(Arc::clone(&wallet), directory_id).await?,
+        );
+    }
+
+    if let Some(path) = &env.proving_key_path {
+        messenger = messenger.with_prover(Arc::new(Prover::from_bytes(&std::fs::read(path)?)?));
+    }
+
+    let messenger = Arc::new(messenger);
 
     let stdout = console::Term::stdout();
 
@@ -166,7 +200,10 @@ async fn main() -> anyhow::Result<()> {
 
         let group = Arc::new(messenger.direct_message(&correspondent).await.unwrap());
 
-        let (kill, mut recv) = monitor_conversation(Arc::clone(&group));
+        let (kill, mut recv) = monitor_conversation(
+            Arc::clone(&messenger.message_repository),
+            Arc::clone(&group),
+        );
 
         line_editor.set_prompt(format!("{}> ", highlight::account::me(&wallet.account_id)));
 
@@ -199,20 +236,26 @@ async fn main() -> anyhow::Result<()> {
                     }
                 },
                 recv_message = recv.recv() => {
-                    if let Some((sender_id, recv_message)) = recv_message {
-                        let sender_id = messenger.resolve_correspondent_id(&sender_id).await.unwrap();
-                        let sender_styled = if sender_id == wallet.account_id {
-                            highlight::account::me(&sender_id)
-                        } else {
-                            highlight::account::other(&sender_id)
-                        };
-                        let time_styled = highlight::text::dim(format_time(recv_message.block_timestamp_ms as i64));
-                        let message_string = String::from_utf8_lossy(&recv_message.message);
-                        writeln!(&stdout, "\r[{time_styled}] {sender_styled}: {message_string}").unwrap();
-                    } else {
-                        writeln!(&stdout, "{}", highlight::text::error("Error connecting to message repository.")).unwrap();
-                        kill();
-                        break;
+                    match recv_message {
+                        Some(Ok((sender_id, recv_message))) => {
+                            let sender_id = messenger.resolve_correspondent_id(&sender_id).await.unwrap();
+                            let sender_styled = if sender_id == wallet.account_id {
+                                highlight::account::me(&sender_id)
+                            } else {
+                                highlight::account::other(&sender_id)
+                            };
+                            let time_styled = highlight::text::dim(format_time(recv_message.block_timestamp_ms as i64));
+                            let message_string = String::from_utf8_lossy(&recv_message.message);
+                            writeln!(&stdout, "\r[{time_styled}] {sender_styled}: {message_string}").unwrap();
+                        }
+                        Some(Err(error)) => {
+                            writeln!(&stdout, "\r{}", highlight::text::error(format!("{error:#}"))).unwrap();
+                        }
+                        None => {
+                            writeln!(&stdout, "{}", highlight::text::error("Error connecting to message repository.")).unwrap();
+                            kill();
+                            break;
+                        }
                     }
                 },
             };
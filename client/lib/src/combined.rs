@@ -1,5 +1,6 @@
 use crate::{
     channel::CorrespondentId,
+    fragment::FragmentProgress,
     group::GroupStream,
     messenger::{DecryptedMessage, MessageStream},
 };
@@ -26,6 +27,19 @@ impl<'a> CombinedMessageStream<'a> {
         }
     }
 
+    /// Partially received fragmented messages, by sender.
+    pub async fn progress(&self) -> Vec<(&CorrespondentId, FragmentProgress)> {
+        let mut progress = vec![];
+
+        for stream in self.streams.iter() {
+            if let Some(p) = stream.stream.receive_progress().await {
+                progress.push((stream.stream.correspondent_id(), p));
+            }
+        }
+
+        progress
+    }
+
     pub async fn next(
         &mut self,
     ) -> anyhow::Result<Option<(&CorrespondentId, DecryptedMessage)>> {
@@ -0,0 +1,228 @@
+//! Decodes the NEP-297 events logged by the message repository and key
+//! registry, so their state can be followed from transaction outcomes (or an
+//! indexer) instead of by polling views.
+
+use data_encoding::BASE64;
+use near_primitives::{types::AccountId, views::FinalExecutionOutcomeView};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use serde_json::json;
+
+pub const MESSAGE_REPOSITORY_STANDARD: &str = "x-message-repository";
+pub const KEY_REGISTRY_STANDARD: &str = "x-public-key-manager";
+
+#[derive(Deserialize)]
+struct EventLog {
+    standard: String,
+    event: String,
+    data: serde_json::Value,
+}
+
+fn base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    BASE64
+        .decode(encoded.as_bytes())
+        .map_err(serde::de::Error::custom)
+}
+
+fn optional_base64<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<u8>>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|encoded| {
+            BASE64
+                .decode(encoded.as_bytes())
+                .map_err(serde::de::Error::custom)
+        })
+        .transpose()
+}
+
+/// Decodes a single log line of `standard`. Other standards, events this
+/// client does not know about, and events from before their current version
+/// are skipped.
+fn parse_log<T: DeserializeOwned>(standard: &str, log: &str) -> Option<T> {
+    let log: EventLog = serde_json::from_str(log.strip_prefix("EVENT_JSON:")?).ok()?;
+    if log.standard != standard {
+        return None;
+    }
+
+    serde_json::from_value(json!({ "event": log.event, "data": log.data })).ok()
+}
+
+/// Decodes the events of `standard` logged by `contract_id` anywhere in
+/// `outcome`. Events logged by any other account are ignored, since anyone
+/// can log an event of any standard.
+fn parse_outcome<T: DeserializeOwned>(
+    standard: &str,
+    outcome: &FinalExecutionOutcomeView,
+    contract_id: &AccountId,
+) -> Vec<T> {
+    outcome
+        .receipts_outcome
+        .iter()
+        .filter(|receipt| &receipt.outcome.executor_id == contract_id)
+        .flat_map(|receipt| &receipt.outcome.logs)
+        .filter_map(|log| parse_log(standard, log))
+        .collect()
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum MessageRepositoryEvent {
+    Publish {
+        #[serde(deserialize_with = "base64")]
+        sequence_hash: Vec<u8>,
+        block_height: u64,
+        message_length: u32,
+        /// The aggregator that the sequence hash was inserted into.
+        aggregator_index: u64,
+        /// `None` for the default namespace, and before version 1.5.0.
+        #[serde(default)]
+        namespace: Option<String>,
+    },
+    AggregatorSealed {
+        index: u64,
+        end_block_timestamp_ms: u64,
+        /// The root that [`crate::merkle::MerkleProof`]s of the aggregator
+        /// are checked against. Missing before version 1.3.0.
+        #[serde(default, deserialize_with = "optional_base64")]
+        merkle_root: Option<Vec<u8>>,
+        #[serde(default)]
+        namespace: Option<String>,
+    },
+    Commit {
+        #[serde(deserialize_with = "base64")]
+        commitment: Vec<u8>,
+    },
+    Delete {
+        #[serde(deserialize_with = "base64")]
+        sequence_hash: Vec<u8>,
+    },
+}
+
+impl MessageRepositoryEvent {
+    pub fn from_log(log: &str) -> Option<Self> {
+        parse_log(MESSAGE_REPOSITORY_STANDARD, log)
+    }
+
+    pub fn from_outcome(
+        outcome: &FinalExecutionOutcomeView,
+        message_repository_id: &AccountId,
+    ) -> Vec<Self> {
+        parse_outcome(MESSAGE_REPOSITORY_STANDARD, outcome, message_repository_id)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum KeyRegistryEvent {
+    PublicKeyChange {
+        account_id: AccountId,
+        #[serde(deserialize_with = "optional_base64")]
+        public_key: Option<Vec<u8>>,
+        #[serde(deserialize_with = "optional_base64")]
+        previous_public_key: Option<Vec<u8>>,
+    },
+}
+
+impl KeyRegistryEvent {
+    pub fn from_log(log: &str) -> Option<Self> {
+        parse_log(KEY_REGISTRY_STANDARD, log)
+    }
+
+    pub fn from_outcome(
+        outcome: &FinalExecutionOutcomeView,
+        key_registry_id: &AccountId,
+    ) -> Vec<Self> {
+        parse_outcome(KEY_REGISTRY_STANDARD, outcome, key_registry_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_message_repository_events() {
+        assert_eq!(
+            MessageRepositoryEvent::from_log(
+                r#"EVENT_JSON:{"standard":"x-message-repository","version":"1.1.0","event":"publish","data":{"sequence_hash":"AQID","block_height":12,"message_length":16,"aggregator_index":1}}"#,
+            ),
+            Some(MessageRepositoryEvent::Publish {
+                sequence_hash: vec![1, 2, 3],
+                block_height: 12,
+                message_length: 16,
+                aggregator_index: 1,
+                namespace: None,
+            }),
+        );
+        assert_eq!(
+            MessageRepositoryEvent::from_log(
+                r#"EVENT_JSON:{"standard":"x-message-repository","version":"1.1.0","event":"aggregator_sealed","data":{"index":0,"end_block_timestamp_ms":999}}"#,
+            ),
+            Some(MessageRepositoryEvent::AggregatorSealed {
+                index: 0,
+                end_block_timestamp_ms: 999,
+                merkle_root: None,
+                namespace: None,
+            }),
+        );
+        assert_eq!(
+            MessageRepositoryEvent::from_log(
+                r#"EVENT_JSON:{"standard":"x-message-repository","version":"1.3.0","event":"aggregator_sealed","data":{"index":1,"end_block_timestamp_ms":1999,"merkle_root":"AQID"}}"#,
+            ),
+            Some(MessageRepositoryEvent::AggregatorSealed {
+                index: 1,
+                end_block_timestamp_ms: 1999,
+                merkle_root: Some(vec![1, 2, 3]),
+                namespace: None,
+            }),
+        );
+        assert_eq!(
+            MessageRepositoryEvent::from_log(
+                r#"EVENT_JSON:{"standard":"x-message-repository","version":"1.5.0","event":"publish","data":{"sequence_hash":"AQID","block_height":12,"message_length":16,"aggregator_index":0,"namespace":"tenant"}}"#,
+            ),
+            Some(MessageRepositoryEvent::Publish {
+                sequence_hash: vec![1, 2, 3],
+                block_height: 12,
+                message_length: 16,
+                aggregator_index: 0,
+                namespace: Some("tenant".to_string()),
+            }),
+        );
+        assert_eq!(
+            MessageRepositoryEvent::from_log(
+                r#"EVENT_JSON:{"standard":"x-message-repository","version":"1.4.0","event":"delete","data":{"sequence_hash":"AQID"}}"#,
+            ),
+            Some(MessageRepositoryEvent::Delete {
+                sequence_hash: vec![1, 2, 3],
+            }),
+        );
+    }
+
+    #[test]
+    fn decode_key_registry_event() {
+        assert_eq!(
+            KeyRegistryEvent::from_log(
+                r#"EVENT_JSON:{"standard":"x-public-key-manager","version":"1.1.0","event":"public_key_change","data":{"account_id":"alice.near","public_key":null,"previous_public_key":"AQID"}}"#,
+            ),
+            Some(KeyRegistryEvent::PublicKeyChange {
+                account_id: "alice.near".parse().unwrap(),
+                public_key: None,
+                previous_public_key: Some(vec![1, 2, 3]),
+            }),
+        );
+    }
+
+    #[test]
+    fn skip_other_logs() {
+        for log in [
+            "not an event",
+            // an older version, without the fields added since
+            r#"EVENT_JSON:{"standard":"x-message-repository","version":"1.0.0","event":"publish","data":{"sequence_hash":"AQID"}}"#,
+            r#"EVENT_JSON:{"standard":"x-message-repository","version":"1.1.0","event":"pause","data":{"method":null}}"#,
+            r#"EVENT_JSON:{"standard":"x-own","version":"1.0.0","event":"transfer","data":{"old":null,"new":"alice.near"}}"#,
+        ] {
+            assert_eq!(MessageRepositoryEvent::from_log(log), None);
+        }
+    }
+}
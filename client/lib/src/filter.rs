@@ -0,0 +1,300 @@
+use std::{hash::Hasher, ops::BitXor};
+
+use anyhow::bail;
+use cuckoofilter::{CuckooFilter, ExportedCuckooFilter};
+use near_primitives::borsh::{self, BorshDeserialize};
+use siphasher::sip::{SipHasher, SipHasher13};
+
+/// The message repository builds its filters on `wasm32`, where `usize` is 4
+/// bytes wide. Hashing a slice writes its length as a `usize`, so this hasher
+/// narrows length prefixes to 32 bits in order to reproduce the contract's
+/// hashes on 64-bit hosts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Wasm32SipHasher(SipHasher);
+
+impl Hasher for Wasm32SipHasher {
+    fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.0.write_u32(i as u32);
+    }
+}
+
+/// The encoding version that [`NotificationFilter::from_bytes`] understands.
+const FILTER_ENCODING_VERSION: u8 = 1;
+
+/// Mirrors the borsh layout of `BorshCuckooFilter` in the message repository
+/// contract.
+#[derive(BorshDeserialize)]
+#[borsh(crate = "near_primitives::borsh")]
+struct BorshExportedCuckooFilter {
+    length: u32,
+    values: Vec<u8>,
+}
+
+#[derive(BorshDeserialize)]
+#[borsh(crate = "near_primitives::borsh")]
+struct BloomFilter {
+    seed: u64,
+    hash_count: u8,
+    len: u32,
+    bits: Vec<u8>,
+}
+
+#[derive(BorshDeserialize)]
+#[borsh(crate = "near_primitives::borsh")]
+struct KeySet {
+    seed: u64,
+    keys: Vec<u64>,
+}
+
+/// The fingerprint widths that the contract builds xor filters with.
+trait Fingerprint: Copy + PartialEq + BitXor<Output = Self> {
+    fn from_hash(hash: u64) -> Self;
+}
+
+impl Fingerprint for u8 {
+    fn from_hash(hash: u64) -> Self {
+        (hash ^ (hash >> 32)) as u8
+    }
+}
+
+impl Fingerprint for u16 {
+    fn from_hash(hash: u64) -> Self {
+        (hash ^ (hash >> 32)) as u16
+    }
+}
+
+#[derive(BorshDeserialize)]
+#[borsh(crate = "near_primitives::borsh")]
+struct XorFilter<F> {
+    key_seed: u64,
+    seed: u64,
+    len: u32,
+    block_length: u32,
+    fingerprints: Vec<F>,
+}
+
+/// Mirrors the borsh layout of `Filter` in the message repository contract.
+#[derive(BorshDeserialize)]
+#[borsh(crate = "near_primitives::borsh")]
+enum EncodedFilter {
+    Cuckoo(BorshExportedCuckooFilter),
+    Bloom(BloomFilter),
+    KeySet(KeySet),
+    Xor(XorFilter<u8>),
+    Xor16(XorFilter<u16>),
+}
+
+enum Filter {
+    Cuckoo(CuckooFilter<Wasm32SipHasher>),
+    Bloom(BloomFilter),
+    KeySet(KeySet),
+    Xor(XorFilter<u8>),
+    Xor16(XorFilter<u16>),
+}
+
+/// Keyed SipHash-1-3 over the raw bytes, as the contract computes it for
+/// Bloom and xor filters.
+fn keyed_hash(seed: u64, bytes: &[u8]) -> u64 {
+    let mut hasher = SipHasher13::new_with_keys(seed, 0);
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// The MurmurHash3 64-bit finalizer.
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+impl BloomFilter {
+    fn contains(&self, bytes: &[u8]) -> bool {
+        let hash = keyed_hash(self.seed, bytes);
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let bit_count = self.bits.len() as u64 * 8;
+
+        (0..self.hash_count as u64).all(|i| {
+            let bit = ((h1 + i * h2) % bit_count) as usize;
+            self.bits[bit / 8] & (1 << (bit % 8)) != 0
+        })
+    }
+}
+
+impl<F: Fingerprint> XorFilter<F> {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.fingerprints.len() != 3 * self.block_length as usize {
+            bail!("Xor filter has the wrong number of fingerprints");
+        }
+        Ok(())
+    }
+
+    fn contains(&self, bytes: &[u8]) -> bool {
+        let hash = mix(keyed_hash(self.key_seed, bytes).wrapping_add(self.seed));
+        let block_length = self.block_length as usize;
+        let slot = |rotation: u32, block: usize| {
+            let offset =
+                ((hash.rotate_left(rotation) as u32 as u64 * block_length as u64) >> 32) as usize;
+            self.fingerprints[offset + block * block_length]
+        };
+
+        F::from_hash(hash) == slot(0, 0) ^ slot(21, 1) ^ slot(42, 2)
+    }
+}
+
+/// A notification filter (aggregator) downloaded from the message repository.
+/// Membership tests are local, so the repository never learns which sequence
+/// hashes the client is interested in.
+pub struct NotificationFilter(Filter);
+
+impl NotificationFilter {
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let Some((&version, encoded)) = bytes.split_first() else {
+            bail!("Empty filter");
+        };
+
+        if version != FILTER_ENCODING_VERSION {
+            bail!("Unsupported filter encoding version {version}");
+        }
+
+        let filter = match borsh::from_slice(encoded)? {
+            EncodedFilter::Cuckoo(exported) => Filter::Cuckoo(
+                ExportedCuckooFilter {
+                    length: exported.length as usize,
+                    values: exported.values,
+                }
+                .into(),
+            ),
+            EncodedFilter::Bloom(bloom) => {
+                if bloom.bits.is_empty() {
+                    bail!("Bloom filter has no bits");
+                }
+                Filter::Bloom(bloom)
+            }
+            EncodedFilter::KeySet(key_set) => Filter::KeySet(key_set),
+            EncodedFilter::Xor(xor) => {
+                xor.validate()?;
+                Filter::Xor(xor)
+            }
+            EncodedFilter::Xor16(xor) => {
+                xor.validate()?;
+                Filter::Xor16(xor)
+            }
+        };
+
+        Ok(Self(filter))
+    }
+
+    /// Returns `true` if a message with this sequence hash has probably been
+    /// published. False positives are possible; false negatives are not.
+    pub fn contains(&self, sequence_hash: &[u8]) -> bool {
+        match &self.0 {
+            Filter::Cuckoo(filter) => filter.contains(sequence_hash),
+            Filter::Bloom(filter) => filter.contains(sequence_hash),
+            Filter::KeySet(filter) => filter
+                .keys
+                .contains(&keyed_hash(filter.seed, sequence_hash)),
+            Filter::Xor(filter) => filter.contains(sequence_hash),
+            Filter::Xor16(filter) => filter.contains(sequence_hash),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Filter::Cuckoo(filter) => filter.len(),
+            Filter::Bloom(filter) => filter.len as usize,
+            Filter::KeySet(filter) => filter.keys.len(),
+            Filter::Xor(filter) => filter.len as usize,
+            Filter::Xor16(filter) => filter.len as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::Hash;
+
+    use super::*;
+
+    #[test]
+    fn wasm32_length_prefix() {
+        let data: &[u8] = b"sequence hash";
+
+        let mut wasm32 = Wasm32SipHasher::default();
+        data.hash(&mut wasm32);
+
+        let mut expected = SipHasher::default();
+        expected.write(&(data.len() as u32).to_le_bytes());
+        expected.write(data);
+
+        assert_eq!(wasm32.finish(), expected.finish());
+    }
+
+    #[test]
+    fn decode_exported_filter() {
+        let mut filter = CuckooFilter::<Wasm32SipHasher>::with_capacity(1023);
+        filter.add(&b"alpha"[..]).unwrap();
+        filter.add(&b"beta"[..]).unwrap();
+
+        let exported = filter.export();
+        let mut bytes = vec![FILTER_ENCODING_VERSION, 0];
+        bytes.extend((exported.length as u32).to_le_bytes());
+        bytes.extend((exported.values.len() as u32).to_le_bytes());
+        bytes.extend(&exported.values);
+
+        let decoded = NotificationFilter::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded.len(), 2);
+        assert!(decoded.contains(b"alpha"));
+        assert!(decoded.contains(b"beta"));
+        assert!(!decoded.contains(b"gamma"));
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // Encoded by the message repository contract's filter tests. Cuckoo
+    // filters are left out: the contract's tests run on a 64-bit host.
+    #[test]
+    fn decode_contract_filters() {
+        for hex in [
+            "010101000000000000000703000000060000000875e84215a6",
+            "01030200000000000000e7830665202abf3a030000000c000000240000000000001500000000000000\
+             000000000000000000000000000000000000000000dd0000b4",
+            "01040200000000000000e7830665202abf3a030000000c000000240000000000000000001569000000\
+             0000000000000000000000000000000000000000000000000000000000000000000000000000000000\
+             000000000000000000000000ddb400000000b414",
+        ] {
+            let filter = NotificationFilter::from_bytes(&from_hex(hex)).unwrap();
+
+            assert_eq!(filter.len(), 3);
+            assert!(filter.contains(b"red"));
+            assert!(filter.contains(b"green"));
+            assert!(filter.contains(b"blue"));
+        }
+    }
+
+    #[test]
+    fn reject_unknown_encoding() {
+        assert!(NotificationFilter::from_bytes(&[]).is_err());
+        assert!(NotificationFilter::from_bytes(&from_hex("02000300000004000000384a0864")).is_err());
+        assert!(NotificationFilter::from_bytes(&from_hex("0105")).is_err());
+    }
+}
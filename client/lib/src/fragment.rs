@@ -0,0 +1,218 @@
+use anyhow::bail;
+use sha2::{Digest, Sha256};
+
+// Every payload starts with a kind byte. Fragments additionally carry
+// `index: u32 LE | count: u32 LE | digest: [u8; 32]`, where `digest` is the
+// SHA-256 of the whole reassembled message.
+const KIND_WHOLE: u8 = 0;
+const KIND_FRAGMENT: u8 = 1;
+
+pub const FRAGMENT_HEADER_LEN: usize = 1 + 4 + 4 + 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentHeader {
+    pub index: u32,
+    pub count: u32,
+    pub digest: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload<'a> {
+    Whole(&'a [u8]),
+    Fragment {
+        header: FragmentHeader,
+        chunk: &'a [u8],
+    },
+}
+
+impl<'a> Payload<'a> {
+    pub fn parse(payload: &'a [u8]) -> anyhow::Result<Self> {
+        match payload.first() {
+            Some(&KIND_WHOLE) => Ok(Self::Whole(&payload[1..])),
+            Some(&KIND_FRAGMENT) if payload.len() >= FRAGMENT_HEADER_LEN => Ok(Self::Fragment {
+                header: FragmentHeader {
+                    index: u32::from_le_bytes(payload[1..5].try_into().unwrap()),
+                    count: u32::from_le_bytes(payload[5..9].try_into().unwrap()),
+                    digest: payload[9..FRAGMENT_HEADER_LEN].try_into().unwrap(),
+                },
+                chunk: &payload[FRAGMENT_HEADER_LEN..],
+            }),
+            Some(&KIND_FRAGMENT) => bail!("Truncated fragment header"),
+            Some(kind) => bail!("Unknown payload kind {kind}"),
+            None => bail!("Empty payload"),
+        }
+    }
+}
+
+/// Encodes a message as one or more payloads of at most `max_payload_len`
+/// bytes each.
+pub fn split(message: &[u8], max_payload_len: usize) -> anyhow::Result<Vec<Vec<u8>>> {
+    if message.len() < max_payload_len {
+        return Ok(vec![[&[KIND_WHOLE], message].concat()]);
+    }
+
+    if max_payload_len <= FRAGMENT_HEADER_LEN {
+        bail!("Payloads of {max_payload_len} bytes are too small to carry fragments");
+    }
+
+    let chunk_len = max_payload_len - FRAGMENT_HEADER_LEN;
+    let count: u32 = message.len().div_ceil(chunk_len).try_into()?;
+    let digest: [u8; 32] = Sha256::digest(message).into();
+
+    Ok(message
+        .chunks(chunk_len)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut payload = Vec::with_capacity(FRAGMENT_HEADER_LEN + chunk.len());
+            payload.push(KIND_FRAGMENT);
+            payload.extend((index as u32).to_le_bytes());
+            payload.extend(count.to_le_bytes());
+            payload.extend(digest);
+            payload.extend(chunk);
+            payload
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentProgress {
+    pub completed: u32,
+    pub total: u32,
+}
+
+struct Reassembly {
+    count: u32,
+    digest: [u8; 32],
+    received: u32,
+    buffer: Vec<u8>,
+}
+
+/// Reassembles the payloads of a single sender, which arrive in sequence
+/// number order.
+#[derive(Default)]
+pub struct Reassembler(Option<Reassembly>);
+
+impl Reassembler {
+    /// Feeds the next payload from the sender, returning the message once it
+    /// is complete.
+    pub fn push(&mut self, payload: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let (header, chunk) = match Payload::parse(payload)? {
+            Payload::Whole(message) => {
+                // a sender that gave up halfway through a message moves on
+                self.0 = None;
+                return Ok(Some(message.to_vec()));
+            }
+            Payload::Fragment { header, chunk } => (header, chunk),
+        };
+
+        if header.index == 0 {
+            self.0 = Some(Reassembly {
+                count: header.count,
+                digest: header.digest,
+                received: 0,
+                buffer: vec![],
+            });
+        }
+
+        let Some(reassembly) = self
+            .0
+            .as_mut()
+            .filter(|r| r.count == header.count && r.digest == header.digest)
+            .filter(|r| r.received == header.index)
+        else {
+            self.0 = None;
+            bail!(
+                "Unexpected fragment {} of {}",
+                header.index + 1,
+                header.count,
+            );
+        };
+
+        reassembly.buffer.extend_from_slice(chunk);
+        reassembly.received += 1;
+
+        if reassembly.received < reassembly.count {
+            return Ok(None);
+        }
+
+        let Reassembly { digest, buffer, .. } = self.0.take().unwrap();
+
+        if <[u8; 32]>::from(Sha256::digest(&buffer)) != digest {
+            bail!("Reassembled message does not match its digest");
+        }
+
+        Ok(Some(buffer))
+    }
+
+    /// Progress of a partially received message, if any.
+    pub fn progress(&self) -> Option<FragmentProgress> {
+        self.0.as_ref().map(|r| FragmentProgress {
+            completed: r.received,
+            total: r.count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_message_is_not_fragmented() {
+        let payloads = split(b"hello", 64).unwrap();
+        assert_eq!(payloads.len(), 1);
+
+        let mut reassembler = Reassembler::default();
+        assert_eq!(
+            reassembler.push(&payloads[0]).unwrap().as_deref(),
+            Some(&b"hello"[..]),
+        );
+    }
+
+    #[test]
+    fn split_and_reassemble() {
+        let message = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        let payloads = split(&message, 100).unwrap();
+
+        assert_eq!(payloads.len(), 17);
+        assert!(payloads.iter().all(|p| p.len() <= 100));
+
+        let mut reassembler = Reassembler::default();
+        for (i, payload) in payloads[..16].iter().enumerate() {
+            assert_eq!(reassembler.push(payload).unwrap(), None);
+            assert_eq!(
+                reassembler.progress(),
+                Some(FragmentProgress {
+                    completed: i as u32 + 1,
+                    total: 17,
+                }),
+            );
+        }
+
+        assert_eq!(reassembler.push(&payloads[16]).unwrap(), Some(message));
+        assert_eq!(reassembler.progress(), None);
+    }
+
+    #[test]
+    fn reject_out_of_order_fragment() {
+        let payloads = split(&[7u8; 500], 100).unwrap();
+
+        let mut reassembler = Reassembler::default();
+        reassembler.push(&payloads[0]).unwrap();
+        assert!(reassembler.push(&payloads[2]).is_err());
+        assert_eq!(reassembler.progress(), None);
+    }
+
+    #[test]
+    fn reject_corrupted_message() {
+        let mut payloads = split(&[7u8; 150], 100).unwrap();
+        *payloads.last_mut().unwrap().last_mut().unwrap() ^= 1;
+        let (last, rest) = payloads.split_last().unwrap();
+
+        let mut reassembler = Reassembler::default();
+        for payload in rest {
+            assert_eq!(reassembler.push(payload).unwrap(), None);
+        }
+        assert!(reassembler.push(last).is_err());
+    }
+}
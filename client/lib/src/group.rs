@@ -1,21 +1,50 @@
 use std::sync::Arc;
 
+use anyhow::bail;
+use rand::rngs::OsRng;
 use sha2::{Digest, Sha256};
 use tokio::sync::RwLock;
 
 use crate::{
-    channel::{Channel, CorrespondentId, SequenceHashProducer},
-    message_repository::MessageRepository,
+    channel::{Channel, CorrespondentId, SequenceHash, SequenceHashProducer, SequenceNumber},
+    fragment::{self, FragmentProgress, Reassembler},
+    message_repository::{MessageRepository, OutgoingMessage, ViewEncoding},
     messenger::{DecryptedMessage, MessageStream},
+    padding::{unpad, PayloadSizeClasses},
+    prover::Prover,
 };
 
+/// How many messages per member [`Group::receive_pending`] requests in each
+/// round-trip.
+pub const RECEIVE_WINDOW: u32 = 8;
+
+/// Most payloads published in a single transaction when a message is split
+/// into fragments.
+pub const FRAGMENTS_PER_TRANSACTION: usize = 8;
+
+/// A payload that was skipped because it could not be decrypted or
+/// reassembled, such as one published by someone outside of the group under
+/// a sequence hash they learned from a pending publish.
+#[derive(Debug)]
+pub struct RejectedPayload {
+    pub correspondent_index: u32,
+    pub nonce: u32,
+    pub error: anyhow::Error,
+}
+
 pub struct Group {
     message_repository: Arc<MessageRepository>,
     send_messages_from_member_index: usize,
     members: Vec<CorrespondentId>,
     next_message_index: RwLock<Vec<u32>>,
+    reassemblers: RwLock<Vec<Reassembler>>,
+    rejected: RwLock<Vec<RejectedPayload>>,
     shared_secret: [u8; 32],
     identifier: [u8; 256],
+    payload_size_classes: PayloadSizeClasses,
+    prover: Option<Arc<Prover>>,
+    ordered_publish: bool,
+    deletion_secret: Option<[u8; 32]>,
 }
 
 impl Group {
@@ -47,19 +76,66 @@ impl Group {
         identifier[64..96].copy_from_slice(&shared_secret);
         identifier[96..128].copy_from_slice(&context_hash);
 
-        let next_message_index =
-            RwLock::new(members.iter().enumerate().map(|(i, _)| i as u32).collect());
+        let next_message_index = RwLock::new(
+            (0..members.len() as u32)
+                .map(Self::first_message_index)
+                .collect(),
+        );
+        let reassemblers = RwLock::new(members.iter().map(|_| Reassembler::default()).collect());
 
         Self {
             message_repository,
             members,
             send_messages_from_member_index,
             next_message_index,
+            reassemblers,
+            rejected: RwLock::new(vec![]),
             shared_secret,
             identifier,
+            payload_size_classes: PayloadSizeClasses::default(),
+            prover: None,
+            ordered_publish: false,
+            deletion_secret: None,
         }
     }
 
+    pub fn with_payload_size_classes(mut self, payload_size_classes: PayloadSizeClasses) -> Self {
+        self.payload_size_classes = payload_size_classes;
+        self
+    }
+
+    /// Attaches a proof of knowledge of the channel secret to every publish,
+    /// for repositories that require one.
+    pub fn with_prover(mut self, prover: Arc<Prover>) -> Self {
+        self.prover = Some(prover);
+        self
+    }
+
+    /// Lets [`Group::send_deletable`] publish messages that this member can
+    /// later delete. `deletion_secret` must be known to this member only.
+    pub fn with_deletion_secret(mut self, deletion_secret: [u8; 32]) -> Self {
+        self.deletion_secret = Some(deletion_secret);
+        self
+    }
+
+    /// Publishes every message but a member's first after the one before it,
+    /// so that a failed publish cannot leave a gap at which receivers would
+    /// stall. Not supported with proof of work or commit-reveal.
+    pub fn with_ordered_publish(mut self) -> Self {
+        self.ordered_publish = true;
+        self
+    }
+
+    /// Pads the cleartext into a payload size class and encrypts it.
+    fn seal(&self, nonce: u32, cleartext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.encrypt(nonce, &self.payload_size_classes.pad(cleartext)?)
+    }
+
+    /// Decrypts the ciphertext and strips its padding.
+    fn open(&self, nonce: u32, ciphertext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(unpad(&self.decrypt(nonce, ciphertext)?)?.to_vec())
+    }
+
     pub fn get_correspondent_index(&self, correspondent_id: &CorrespondentId) -> Option<u32> {
         self.members
             .iter()
@@ -71,31 +147,289 @@ impl Group {
         self.members.len() as u32 * message_index + correspondent_index
     }
 
+    /// The index of a member's first message.
+    fn first_message_index(correspondent_index: u32) -> u32 {
+        correspondent_index
+    }
+
+    /// The sequence hash of the message that a member sends before the one
+    /// at `message_index`, or `None` for its first message.
+    pub fn previous_sequence_hash(
+        &self,
+        message_index: u32,
+        correspondent_index: u32,
+    ) -> Option<SequenceHash> {
+        let previous_index = message_index
+            .checked_sub(1)
+            .filter(|&i| i >= Self::first_message_index(correspondent_index))?;
+        Some(self.sequence_hash(self.get_nonce_for_message(previous_index, correspondent_index)))
+    }
+
+    pub fn member_count(&self) -> u32 {
+        self.members.len() as u32
+    }
+
+    pub fn get_correspondent_id(&self, correspondent_index: u32) -> &CorrespondentId {
+        &self.members[correspondent_index as usize]
+    }
+
+    pub async fn next_message_index_for(&self, correspondent_index: u32) -> u32 {
+        self.next_message_index.read().await[correspondent_index as usize]
+    }
+
+    /// The key that deletes the message this member published with
+    /// [`Group::send_deletable`] under `sequence_number`. Only this member can
+    /// derive it, or `None` without [`Group::with_deletion_secret`].
+    pub fn deletion_key(&self, sequence_number: SequenceNumber) -> Option<[u8; 32]> {
+        let deletion_secret = self.deletion_secret?;
+        Some(
+            <Sha256 as Digest>::new()
+                .chain_update(b"deletion key")
+                .chain_update(sequence_number.to_le_bytes())
+                .chain_update(self.identifier)
+                .chain_update(deletion_secret)
+                .finalize()
+                .into(),
+        )
+    }
+
+    /// Deletes a message that this member published with
+    /// [`Group::send_deletable`] under `sequence_number`, refunding its
+    /// storage to whoever paid for it. Members who have not received it yet
+    /// skip its sequence number.
+    pub async fn delete(&self, sequence_number: SequenceNumber) -> anyhow::Result<()> {
+        let Some(deletion_key) = self.deletion_key(sequence_number) else {
+            bail!("Deleting messages requires a deletion secret");
+        };
+        self.message_repository
+            .delete_message(&*self.sequence_hash(sequence_number), &deletion_key)
+            .await
+    }
+
+    /// Nonce and sequence hash of the next message expected from a member.
+    pub async fn next_sequence_hash_for(&self, correspondent_index: u32) -> (u32, SequenceHash) {
+        let message_index = self.next_message_index_for(correspondent_index).await;
+        let nonce = self.get_nonce_for_message(message_index, correspondent_index);
+        (nonce, self.sequence_hash(nonce))
+    }
+
+    /// Feeds a received payload to the member's reassembler, returning the
+    /// message once all of its fragments have arrived. A payload that could
+    /// not be opened or reassembled is skipped and recorded for
+    /// [`Group::take_rejected`].
+    async fn reassemble(
+        &self,
+        correspondent_index: u32,
+        nonce: u32,
+        payload: anyhow::Result<DecryptedMessage>,
+    ) -> Option<DecryptedMessage> {
+        let message = match payload {
+            Ok(payload) => self.reassemblers.write().await[correspondent_index as usize]
+                .push(&payload.message)
+                .map(|message| {
+                    message.map(|message| DecryptedMessage {
+                        message,
+                        block_timestamp_ms: payload.block_timestamp_ms,
+                    })
+                }),
+            Err(error) => Err(error),
+        };
+
+        match message {
+            Ok(message) => message,
+            Err(error) => {
+                self.rejected.write().await.push(RejectedPayload {
+                    correspondent_index,
+                    nonce,
+                    error,
+                });
+                None
+            }
+        }
+    }
+
+    /// Takes the payloads skipped since the last call.
+    pub async fn take_rejected(&self) -> Vec<RejectedPayload> {
+        std::mem::take(&mut *self.rejected.write().await)
+    }
+
+    /// Progress of a partially received fragmented message from a member.
+    pub async fn receive_progress_for(&self, correspondent_index: u32) -> Option<FragmentProgress> {
+        self.reassemblers.read().await[correspondent_index as usize].progress()
+    }
+
     pub async fn receive_next_for(
         &self,
         correspondent_index: u32,
     ) -> anyhow::Result<Option<DecryptedMessage>> {
-        let message_index = self.next_message_index.read().await[correspondent_index as usize];
-        let nonce = self.get_nonce_for_message(message_index, correspondent_index);
-        let sequence_hash = self.sequence_hash(nonce);
+        loop {
+            let (nonce, sequence_hash) = self.next_sequence_hash_for(correspondent_index).await;
 
-        let response = self.message_repository.get_message(&*sequence_hash).await?;
+            let response = self
+                .message_repository
+                .get_message(&*sequence_hash, ViewEncoding::Borsh)
+                .await?;
 
-        let Some(ciphertext) = response else {
-            return Ok(None);
-        };
+            let Some(ciphertext) = response else {
+                // a deleted message leaves a tombstone, which is skipped
+                if self
+                    .message_repository
+                    .are_deleted(&[&*sequence_hash])
+                    .await?[0]
+                {
+                    self.next_message_index.write().await[correspondent_index as usize] += 1;
+                    continue;
+                }
+                return Ok(None);
+            };
+
+            let payload = self
+                .open(nonce, &ciphertext.message)
+                .map(|cleartext| DecryptedMessage {
+                    message: cleartext,
+                    block_timestamp_ms: ciphertext.block_timestamp_ms,
+                });
+
+            self.next_message_index.write().await[correspondent_index as usize] += 1;
+
+            if let Some(message) = self.reassemble(correspondent_index, nonce, payload).await {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// Fetches the raw payloads of each `(correspondent_index, count)` window
+    /// in a single round-trip, accepting them in order until the first gap in
+    /// each window. The sequence numbers of deleted messages are skipped
+    /// rather than treated as gaps. Each payload comes with its nonce, and
+    /// fails if it could not be opened. Also returns which members stalled
+    /// at a gap.
+    async fn fetch_batch(
+        &self,
+        windows: &[(u32, u32)],
+    ) -> anyhow::Result<(Vec<(u32, u32, anyhow::Result<DecryptedMessage>)>, Vec<bool>)> {
+        let mut next_message_index = self.next_message_index.write().await;
+
+        let requested = windows
+            .iter()
+            .flat_map(|&(correspondent_index, count)| {
+                let start = next_message_index[correspondent_index as usize];
+                (start..start + count).map(move |message_index| {
+                    let nonce = self.get_nonce_for_message(message_index, correspondent_index);
+                    (correspondent_index, nonce, self.sequence_hash(nonce))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let responses = self
+            .message_repository
+            .get_messages(
+                &requested
+                    .iter()
+                    .map(|(_, _, sequence_hash)| &sequence_hash[..])
+                    .collect::<Vec<_>>(),
+                ViewEncoding::Borsh,
+            )
+            .await?;
+
+        let gaps = requested
+            .iter()
+            .zip(&responses)
+            .filter(|(_, response)| response.is_none())
+            .map(|((_, _, sequence_hash), _)| &sequence_hash[..])
+            .collect::<Vec<_>>();
+        let mut deleted = if gaps.is_empty() {
+            vec![]
+        } else {
+            self.message_repository.are_deleted(&gaps).await?
+        }
+        .into_iter();
+
+        let mut stalled = vec![false; self.members.len()];
+        let mut received = vec![];
+
+        for ((correspondent_index, nonce, _), response) in requested.into_iter().zip(responses) {
+            let i = correspondent_index as usize;
+            let Some(ciphertext) = response else {
+                let is_deleted = deleted.next() == Some(true);
+                if !stalled[i] {
+                    if is_deleted {
+                        next_message_index[i] += 1;
+                    } else {
+                        stalled[i] = true;
+                    }
+                }
+                continue;
+            };
+            if stalled[i] {
+                continue;
+            }
+
+            next_message_index[i] += 1;
+
+            received.push((
+                correspondent_index,
+                nonce,
+                self.open(nonce, &ciphertext.message)
+                    .map(|cleartext| DecryptedMessage {
+                        message: cleartext,
+                        block_timestamp_ms: ciphertext.block_timestamp_ms,
+                    }),
+            ));
+        }
+
+        Ok((received, stalled))
+    }
+
+    /// Looks up the next `count` sequence numbers of each
+    /// `(correspondent_index, count)` window in a single round-trip, returning
+    /// the messages they complete.
+    pub async fn receive_batch(
+        &self,
+        windows: &[(u32, u32)],
+    ) -> anyhow::Result<Vec<(u32, DecryptedMessage)>> {
+        let mut received = vec![];
+
+        for (correspondent_index, nonce, payload) in self.fetch_batch(windows).await?.0 {
+            if let Some(message) = self.reassemble(correspondent_index, nonce, payload).await {
+                received.push((correspondent_index, message));
+            }
+        }
+
+        Ok(received)
+    }
+
+    /// Catches up on every member's messages, fetching up to
+    /// [`RECEIVE_WINDOW`] sequence numbers per member per round-trip.
+    ///
+    /// Like every receive, this trusts the RPC node's answers: a node can
+    /// hide a message, which stalls the member until it is served, or claim
+    /// it was deleted, which skips it. Callers
+    /// that need more must check the messages they are waiting for with
+    /// [`MessageRepository::get_verified_message`] against roots they pinned.
+    pub async fn receive_pending(&self) -> anyhow::Result<Vec<(u32, DecryptedMessage)>> {
+        let mut windows = (0..self.member_count())
+            .map(|i| (i, RECEIVE_WINDOW))
+            .collect::<Vec<_>>();
+        let mut received = vec![];
 
-        let cleartext = self.decrypt(nonce, &ciphertext.message)?;
+        while !windows.is_empty() {
+            let (batch, stalled) = self.fetch_batch(&windows).await?;
 
-        self.next_message_index.write().await[correspondent_index as usize] += 1;
+            // only members whose whole window arrived may have more waiting
+            windows.retain(|&(i, _)| !stalled[i as usize]);
+
+            for (correspondent_index, nonce, payload) in batch {
+                if let Some(message) = self.reassemble(correspondent_index, nonce, payload).await {
+                    received.push((correspondent_index, message));
+                }
+            }
+        }
 
-        Ok(Some(DecryptedMessage {
-            message: cleartext,
-            block_timestamp_ms: ciphertext.block_timestamp_ms,
-        }))
+        Ok(received)
     }
 
-    pub fn streams(&self) -> Vec<GroupStream> {
+    pub fn streams(&self) -> Vec<GroupStream<'_>> {
         self.members
             .iter()
             .enumerate()
@@ -106,19 +440,127 @@ impl Group {
             .collect()
     }
 
-    pub async fn send(
+    pub async fn send(&self, cleartext: impl AsRef<[u8]>) -> anyhow::Result<()> {
+        self.send_with_progress(cleartext, |_| {}).await
+    }
+
+    /// Sends a message, splitting it into fragments over consecutive sequence
+    /// numbers if it does not fit into the largest payload size class.
+    /// `on_progress` is called after each transaction.
+    pub async fn send_with_progress(
         &self,
         cleartext: impl AsRef<[u8]>,
+        mut on_progress: impl FnMut(FragmentProgress),
     ) -> anyhow::Result<()> {
-        let message_index = self.next_message_index.read().await[self.send_messages_from_member_index];
-        let nonce = self.get_nonce_for_message(message_index, self.send_messages_from_member_index as u32);
-        let sequence_hash = self.sequence_hash(nonce);
-        let ciphertext = self.encrypt(nonce, cleartext.as_ref())?;
-        self.message_repository
-            .publish_message(&*sequence_hash, &ciphertext)
+        let payloads = fragment::split(
+            cleartext.as_ref(),
+            self.payload_size_classes.max_payload_len(),
+        )?;
+
+        self.publish_payloads(payloads, false, &mut on_progress)
             .await?;
         Ok(())
     }
+
+    /// Like [`Group::send`], but the message can later be deleted with
+    /// [`Group::delete`], at the cost of storing a deletion key hash with
+    /// each payload. Returns the sequence numbers of its payloads.
+    pub async fn send_deletable(
+        &self,
+        cleartext: impl AsRef<[u8]>,
+    ) -> anyhow::Result<Vec<SequenceNumber>> {
+        if self.deletion_secret.is_none() {
+            bail!("Deletable messages require a deletion secret");
+        }
+        if let Some(namespace) = self.message_repository.namespace() {
+            bail!("Messages in namespace {namespace} cannot be deleted");
+        }
+        let payloads = fragment::split(
+            cleartext.as_ref(),
+            self.payload_size_classes.max_payload_len(),
+        )?;
+
+        self.publish_payloads(payloads, true, &mut |_| {}).await
+    }
+
+    /// Publishes several messages under consecutive sequence numbers, in as few
+    /// transactions as possible.
+    pub async fn send_batch(
+        &self,
+        cleartexts: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> anyhow::Result<()> {
+        let max_payload_len = self.payload_size_classes.max_payload_len();
+
+        let payloads = cleartexts
+            .into_iter()
+            .map(|cleartext| fragment::split(cleartext.as_ref(), max_payload_len))
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        self.publish_payloads(payloads, false, &mut |_| {}).await?;
+        Ok(())
+    }
+
+    /// Returns the sequence numbers that the payloads were published under.
+    async fn publish_payloads(
+        &self,
+        payloads: Vec<Vec<u8>>,
+        deletable: bool,
+        on_progress: &mut impl FnMut(FragmentProgress),
+    ) -> anyhow::Result<Vec<SequenceNumber>> {
+        let correspondent_index = self.send_messages_from_member_index as u32;
+        let first_message_index = self.next_message_index_for(correspondent_index).await;
+
+        let messages = payloads
+            .iter()
+            .enumerate()
+            .map(|(i, payload)| {
+                let message_index = first_message_index + i as u32;
+                let nonce = self.get_nonce_for_message(message_index, correspondent_index);
+                let ciphertext = self.seal(nonce, payload)?;
+                let mut message = OutgoingMessage::new(&*self.sequence_hash(nonce), &ciphertext);
+                if let Some(deletion_key) = self.deletion_key(nonce).filter(|_| deletable) {
+                    message = message.with_deletion_key(&deletion_key);
+                }
+                if self.ordered_publish {
+                    if let Some(previous) =
+                        self.previous_sequence_hash(message_index, correspondent_index)
+                    {
+                        message = message.with_previous(&*previous);
+                    }
+                }
+
+                Ok(match &self.prover {
+                    Some(prover) => {
+                        message.with_proof(prover.prove(self, nonce, &ciphertext, &mut OsRng)?)
+                    }
+                    None => message,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut progress = FragmentProgress {
+            completed: 0,
+            total: messages.len() as u32,
+        };
+
+        for chunk in messages.chunks(FRAGMENTS_PER_TRANSACTION) {
+            if let [message] = chunk {
+                self.message_repository.publish_outgoing(message).await?;
+            } else {
+                self.message_repository.publish_messages(chunk).await?;
+            }
+
+            progress.completed += chunk.len() as u32;
+            on_progress(progress);
+        }
+
+        Ok((0..messages.len() as u32)
+            .map(|i| self.get_nonce_for_message(first_message_index + i, correspondent_index))
+            .collect())
+    }
 }
 
 impl Channel for Group {
@@ -148,4 +590,10 @@ impl<'a> GroupStream<'a> {
     pub fn correspondent_id(&self) -> &CorrespondentId {
         &self.group.members[self.target_correspondent_index as usize]
     }
+
+    pub async fn receive_progress(&self) -> Option<FragmentProgress> {
+        self.group
+            .receive_progress_for(self.target_correspondent_index)
+            .await
+    }
 }
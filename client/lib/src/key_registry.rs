@@ -7,6 +7,7 @@ use near_primitives::{
     types::AccountId,
 };
 use serde_json::json;
+use tokio::sync::OnceCell;
 
 use crate::wallet::{Wallet, ONE_NEAR, ONE_TERAGAS};
 
@@ -17,6 +18,7 @@ fn public_key_to_string(public_key: &x25519_dalek::PublicKey) -> String {
 pub struct KeyRegistry {
     wallet: Arc<Wallet>,
     account_id: AccountId,
+    has_storage_balance: OnceCell<bool>,
 }
 
 impl KeyRegistry {
@@ -24,9 +26,27 @@ impl KeyRegistry {
         Self {
             wallet,
             account_id: account_id.clone(),
+            has_storage_balance: OnceCell::new(),
         }
     }
 
+    /// `amount`, or nothing if the account pays for storage from its
+    /// storage balance on the registry. Registration is only checked once
+    /// per registry handle.
+    async fn deposit(&self, amount: u128) -> anyhow::Result<u128> {
+        let has_storage_balance = self
+            .has_storage_balance
+            .get_or_try_init(|| async {
+                self.wallet
+                    .storage_balance_of(self.account_id.clone(), &self.wallet.account_id)
+                    .await
+                    .map(|balance| balance.is_some())
+            })
+            .await?;
+
+        Ok(if *has_storage_balance { 0 } else { amount })
+    }
+
     pub async fn get_my_key(&self) -> anyhow::Result<Vec<u8>> {
         self.get_key_for(&self.wallet.account_id).await
     }
@@ -61,7 +81,7 @@ impl KeyRegistry {
                     .to_string()
                     .into_bytes(),
                     gas: 5 * ONE_TERAGAS,
-                    deposit: ONE_NEAR / 2,
+                    deposit: self.deposit(ONE_NEAR / 2).await?,
                 }))],
             )
             .await?;
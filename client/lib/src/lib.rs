@@ -1,9 +1,18 @@
 pub mod channel;
 pub mod combined;
+pub mod events;
+pub mod filter;
+pub mod fragment;
 pub mod group;
 pub mod key_registry;
+pub mod merkle;
 pub mod message_repository;
 pub mod messenger;
+pub mod padding;
+pub mod pow;
+pub mod prover;
+pub mod relayer;
+pub mod sync;
 pub mod wallet;
 
 #[cfg(test)]
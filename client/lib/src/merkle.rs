@@ -0,0 +1,309 @@
+//! Verifies the Merkle proofs that the message repository serves for its
+//! sealed aggregators, so that an RPC node cannot drop or forge messages
+//! without being caught. See the contract's `merkle` module for the tree
+//! format.
+//!
+//! A proof is only as good as the root and the aggregator it is checked
+//! against, and these are not verified here:
+//!
+//! - A root from [`MessageRepository::get_merkle_root`] comes from the same
+//!   RPC node as the proof. Pin roots from `AggregatorSealed` events in
+//!   transaction outcomes, or compare them across independent nodes.
+//! - The current aggregator has no root yet, so its messages cannot be
+//!   proven until it is sealed.
+//! - The caller picks the aggregator, usually from filters and indices that
+//!   the RPC node served. A node that lies about which aggregator holds a
+//!   message can prove that it is absent from another one.
+//! - Deleting a message leaves its leaf in the tree, so a deleted message
+//!   fails verification just like one that the node hides.
+//!
+//! [`MessageRepository::get_merkle_root`]: crate::message_repository::MessageRepository::get_merkle_root
+
+use anyhow::{bail, ensure, Context};
+use data_encoding::BASE64;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    parts
+        .iter()
+        .fold(Sha256::new(), |hasher, part| hasher.chain_update(part))
+        .finalize()
+        .into()
+}
+
+fn hash<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+    let bytes = BASE64
+        .decode(String::deserialize(deserializer)?.as_bytes())
+        .map_err(serde::de::Error::custom)?;
+    bytes
+        .try_into()
+        .map_err(|_| serde::de::Error::custom("expected a 32-byte hash"))
+}
+
+fn hashes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error> {
+    #[derive(Deserialize)]
+    struct Hash(#[serde(deserialize_with = "hash")] [u8; 32]);
+
+    Ok(Vec::<Hash>::deserialize(deserializer)?
+        .into_iter()
+        .map(|Hash(hash)| hash)
+        .collect())
+}
+
+/// The key that orders `sequence_hash` among the leaves.
+pub fn leaf_key(sequence_hash: &[u8]) -> [u8; 32] {
+    sha256(&[sequence_hash])
+}
+
+pub fn message_hash(message: &[u8]) -> [u8; 32] {
+    sha256(&[message])
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleLeafProof {
+    /// The position of the leaf in key order.
+    pub position: u32,
+    #[serde(deserialize_with = "hash")]
+    pub key: [u8; 32],
+    #[serde(deserialize_with = "hash")]
+    pub message_hash: [u8; 32],
+    /// Bottom up, skipping the levels at which the path has no sibling.
+    #[serde(deserialize_with = "hashes")]
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleLeafProof {
+    /// The root of a tree of `leaf_count` leaves that this leaf is in.
+    fn root(&self, leaf_count: u32) -> anyhow::Result<[u8; 32]> {
+        ensure!(self.position < leaf_count, "Leaf position is out of range");
+
+        let mut hash = sha256(&[&[0], &self.key, &self.message_hash]);
+        let mut siblings = self.siblings.iter();
+        let (mut index, mut len) = (self.position, leaf_count);
+        while len > 1 {
+            if index ^ 1 < len {
+                let sibling = siblings.next().context("Merkle path is too short")?;
+                hash = if index % 2 == 0 {
+                    sha256(&[&[1], &hash, sibling])
+                } else {
+                    sha256(&[&[1], sibling, &hash])
+                };
+            }
+            index /= 2;
+            len = len.div_ceil(2);
+        }
+        ensure!(siblings.next().is_none(), "Merkle path is too long");
+
+        Ok(root_hash(leaf_count, &hash))
+    }
+}
+
+fn root_hash(leaf_count: u32, tree_root: &[u8; 32]) -> [u8; 32] {
+    sha256(&[&[2], &leaf_count.to_le_bytes(), tree_root])
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MerkleProof {
+    Inclusion {
+        leaf_count: u32,
+        leaf: MerkleLeafProof,
+    },
+    /// The leaves on either side of the missing key.
+    NonInclusion {
+        leaf_count: u32,
+        predecessor: Option<MerkleLeafProof>,
+        successor: Option<MerkleLeafProof>,
+    },
+}
+
+impl MerkleProof {
+    /// Checks the proof against an aggregator's Merkle root. Returns the
+    /// hash of the message published under `sequence_hash`, or `None` if the
+    /// proof shows that the aggregator does not contain it.
+    pub fn verify(
+        &self,
+        root: &[u8; 32],
+        sequence_hash: &[u8],
+    ) -> anyhow::Result<Option<[u8; 32]>> {
+        let key = leaf_key(sequence_hash);
+
+        match self {
+            Self::Inclusion { leaf_count, leaf } => {
+                ensure!(leaf.key == key, "Inclusion proof is for another key");
+                ensure!(
+                    &leaf.root(*leaf_count)? == root,
+                    "Inclusion proof does not match the Merkle root"
+                );
+
+                Ok(Some(leaf.message_hash))
+            }
+            Self::NonInclusion {
+                leaf_count,
+                predecessor,
+                successor,
+            } => {
+                for leaf in predecessor.iter().chain(successor) {
+                    ensure!(
+                        &leaf.root(*leaf_count)? == root,
+                        "Non-inclusion proof does not match the Merkle root"
+                    );
+                }
+
+                match (predecessor, successor) {
+                    (Some(p), Some(s)) => {
+                        ensure!(p.position + 1 == s.position, "Leaves are not adjacent");
+                        ensure!(p.key < key && key < s.key, "Key is not between the leaves");
+                    }
+                    (Some(p), None) => {
+                        ensure!(p.position + 1 == *leaf_count, "Leaf is not the last");
+                        ensure!(p.key < key, "Key is not after the last leaf");
+                    }
+                    (None, Some(s)) => {
+                        ensure!(s.position == 0, "Leaf is not the first");
+                        ensure!(key < s.key, "Key is not before the first leaf");
+                    }
+                    (None, None) => {
+                        if *leaf_count != 0 || &root_hash(0, &[0; 32]) != root {
+                            bail!("Non-inclusion proof does not match the Merkle root");
+                        }
+                    }
+                }
+
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The contract's `merkle::tests::root_vector` builds the same tree, of
+    // leaves `([i; 32], [i; 64])` for `i` in `0..5`.
+    const ROOT: &str = "564e52baca2ebecc444e683283c8ea594556c501fbf517eadd9340f061fb95c4";
+
+    fn root() -> [u8; 32] {
+        data_encoding::HEXLOWER
+            .decode(ROOT.as_bytes())
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    fn leaves() -> Vec<([u8; 32], [u8; 32])> {
+        let mut leaves = (0..5u8)
+            .map(|i| (leaf_key(&[i; 32]), message_hash(&[i; 64])))
+            .collect::<Vec<_>>();
+        leaves.sort_unstable();
+        leaves
+    }
+
+    /// The sequence hash of the leaf at `position`.
+    fn sequence_hash_at(position: usize) -> [u8; 32] {
+        let key = leaves()[position].0;
+        [(0..5u8).find(|i| leaf_key(&[*i; 32]) == key).unwrap(); 32]
+    }
+
+    /// Builds the proof of the leaf at `position` the way the contract does.
+    fn leaf_proof(position: usize) -> MerkleLeafProof {
+        let mut level = leaves()
+            .iter()
+            .map(|(key, message_hash)| sha256(&[&[0], key, message_hash]))
+            .collect::<Vec<_>>();
+        let mut siblings = vec![];
+        let mut index = position;
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(index ^ 1) {
+                siblings.push(*sibling);
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => sha256(&[&[1], left, right]),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            index /= 2;
+        }
+
+        let (key, message_hash) = leaves()[position];
+        MerkleLeafProof {
+            position: position as u32,
+            key,
+            message_hash,
+            siblings,
+        }
+    }
+
+    #[test]
+    fn verify_inclusion() {
+        for position in 0..5 {
+            let sequence_hash = sequence_hash_at(position);
+            let proof = MerkleProof::Inclusion {
+                leaf_count: 5,
+                leaf: leaf_proof(position),
+            };
+
+            assert_eq!(
+                proof.verify(&root(), &sequence_hash).unwrap(),
+                Some(message_hash(&[sequence_hash[0]; 64])),
+            );
+            assert!(proof.verify(&root(), &[9; 32]).is_err());
+        }
+    }
+
+    #[test]
+    fn reject_forged_inclusion() {
+        let mut leaf = leaf_proof(2);
+        leaf.message_hash = message_hash(b"forged");
+        let proof = MerkleProof::Inclusion {
+            leaf_count: 5,
+            leaf,
+        };
+
+        assert!(proof.verify(&root(), &sequence_hash_at(2)).is_err());
+    }
+
+    #[test]
+    fn verify_non_inclusion() {
+        let missing = [100u8; 32];
+        let key = leaf_key(&missing);
+        let position = leaves().iter().filter(|(k, _)| *k < key).count();
+        let proof = MerkleProof::NonInclusion {
+            leaf_count: 5,
+            predecessor: position.checked_sub(1).map(leaf_proof),
+            successor: (position < 5).then(|| leaf_proof(position)),
+        };
+
+        assert_eq!(proof.verify(&root(), &missing).unwrap(), None);
+    }
+
+    #[test]
+    fn reject_non_inclusion_of_a_leaf() {
+        // claims the second leaf is missing by skipping over it
+        let proof = MerkleProof::NonInclusion {
+            leaf_count: 5,
+            predecessor: Some(leaf_proof(0)),
+            successor: Some(leaf_proof(2)),
+        };
+
+        assert!(proof.verify(&root(), &sequence_hash_at(1)).is_err());
+    }
+
+    #[test]
+    fn decode_proof() {
+        let proof: MerkleProof = serde_json::from_str(
+            r#"{"kind":"non_inclusion","leaf_count":0,"predecessor":null,"successor":null}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            proof.verify(&root_hash(0, &[0; 32]), b"anything").unwrap(),
+            None
+        );
+    }
+}
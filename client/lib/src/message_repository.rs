@@ -1,17 +1,35 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::Arc,
+};
 
-use anyhow::bail;
+use anyhow::{bail, Context};
+use borsh::BorshDeserialize;
 use data_encoding::BASE64;
 use near_primitives::{
+    action::delegate::SignedDelegateAction,
+    serialize::dec_format,
     transaction::{Action, FunctionCallAction},
-    types::AccountId,
+    types::{AccountId, Balance},
+    views::{FinalExecutionOutcomeView, FinalExecutionStatus},
 };
+use rand::{rngs::OsRng, RngCore};
 use serde::Deserialize;
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
 
-use crate::wallet::{Wallet, ONE_NEAR, ONE_TERAGAS};
+use crate::{
+    filter::NotificationFilter,
+    merkle::{self, MerkleProof},
+    padding::PayloadSizeClasses,
+    pow,
+    prover::PublishProof,
+    wallet::{Wallet, ONE_NEAR, ONE_TERAGAS},
+};
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, BorshDeserialize, Debug, Clone, PartialEq)]
 pub struct EncryptedMessage {
     pub message: Vec<u8>,
     pub block_timestamp_ms: u64,
@@ -23,10 +41,511 @@ pub struct EncryptedMessageBase64 {
     pub block_timestamp_ms: u64,
 }
 
+impl TryFrom<EncryptedMessageBase64> for EncryptedMessage {
+    type Error = anyhow::Error;
+
+    fn try_from(value: EncryptedMessageBase64) -> Result<Self, Self::Error> {
+        let message = match BASE64.decode(value.message.as_bytes()) {
+            Ok(d) => d,
+            Err(e) => bail!("Error decoding from base64: {}", e),
+        };
+
+        Ok(EncryptedMessage {
+            message,
+            block_timestamp_ms: value.block_timestamp_ms,
+        })
+    }
+}
+
+/// The kind of notification filter that a message repository builds.
+#[derive(Deserialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FilterKind {
+    Cuckoo,
+    Bloom {
+        false_positive_rate: f64,
+    },
+    /// Exact while current, sealed into an xor filter.
+    Xor,
+}
+
+#[derive(Deserialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FilterParameters {
+    #[serde(flatten)]
+    pub kind: FilterKind,
+    pub capacity: u64,
+    /// Bits per fingerprint of cuckoo and xor filters.
+    pub fingerprint_bits: u8,
+}
+
+/// The parameters of every aggregator a repository creates, as set when it
+/// was initialized.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct AggregatorConfig {
+    /// Items per aggregator before it is sealed.
+    pub capacity: u64,
+    /// Bits per fingerprint of cuckoo and xor filters.
+    pub fingerprint_bits: u8,
+    pub filter_kind: FilterKind,
+}
+
+/// An aggregator downloaded from the message repository.
+pub struct AggregatorRecord {
+    pub index: u64,
+    /// `None` for the current aggregator, which is still accepting messages.
+    pub end_block_timestamp_ms: Option<u64>,
+    pub item_count: u64,
+    pub filter_parameters: FilterParameters,
+    pub filter: NotificationFilter,
+}
+
+#[derive(Deserialize)]
+struct AggregatorRecordBase64 {
+    index: u64,
+    end_block_timestamp_ms: Option<u64>,
+    item_count: u64,
+    filter_parameters: FilterParameters,
+    filter: String,
+}
+
+impl TryFrom<AggregatorRecordBase64> for AggregatorRecord {
+    type Error = anyhow::Error;
+
+    fn try_from(value: AggregatorRecordBase64) -> Result<Self, Self::Error> {
+        let bytes = match BASE64.decode(value.filter.as_bytes()) {
+            Ok(d) => d,
+            Err(e) => bail!("Error decoding from base64: {}", e),
+        };
+
+        AggregatorRecordBorsh {
+            index: value.index,
+            end_block_timestamp_ms: value.end_block_timestamp_ms,
+            item_count: value.item_count,
+            filter_parameters: value.filter_parameters,
+            filter: bytes,
+        }
+        .try_into()
+    }
+}
+
+#[derive(BorshDeserialize)]
+struct AggregatorRecordBorsh {
+    index: u64,
+    end_block_timestamp_ms: Option<u64>,
+    item_count: u64,
+    filter_parameters: FilterParameters,
+    filter: Vec<u8>,
+}
+
+impl TryFrom<AggregatorRecordBorsh> for AggregatorRecord {
+    type Error = anyhow::Error;
+
+    fn try_from(value: AggregatorRecordBorsh) -> Result<Self, Self::Error> {
+        Ok(AggregatorRecord {
+            index: value.index,
+            end_block_timestamp_ms: value.end_block_timestamp_ms,
+            item_count: value.item_count,
+            filter_parameters: value.filter_parameters,
+            filter: NotificationFilter::from_bytes(&value.filter)?,
+        })
+    }
+}
+
+pub struct AggregatorPage {
+    pub aggregators: Vec<AggregatorRecord>,
+    /// Where the next page starts, or `None` after the current aggregator.
+    pub next_index: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct AggregatorPageBase64 {
+    aggregators: Vec<AggregatorRecordBase64>,
+    next_index: Option<u64>,
+}
+
+#[derive(BorshDeserialize)]
+struct AggregatorPageBorsh {
+    aggregators: Vec<AggregatorRecordBorsh>,
+    next_index: Option<u64>,
+}
+
+/// How the repository encodes the result of a message or aggregator view.
+/// Borsh carries ciphertexts and filters as raw bytes, where JSON
+/// base64-encodes them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ViewEncoding {
+    #[default]
+    Json,
+    Borsh,
+}
+
+/// What the repository charges to publish one message.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublishQuote {
+    /// The message's own records, refunded if it is deleted.
+    #[serde(with = "dec_format")]
+    pub storage_fee: Balance,
+    /// The account's rate limit window, charged once per call to the first
+    /// message the account publishes.
+    #[serde(with = "dec_format")]
+    pub rate_limit_storage_fee: Balance,
+    /// The message's share of its aggregator.
+    #[serde(with = "dec_format")]
+    pub aggregator_fee: Balance,
+    /// The namespace's flat fee, which is zero in the default namespace.
+    #[serde(with = "dec_format")]
+    pub publish_fee: Balance,
+    /// The repository's per-message and per-byte fee, which is zero unless
+    /// its owner set one.
+    #[serde(with = "dec_format")]
+    pub service_fee: Balance,
+    #[serde(with = "dec_format")]
+    pub total: Balance,
+}
+
+/// The settings of a namespace, which the repository's owner sets when it
+/// creates it.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct NamespaceConfig {
+    pub aggregator_config: AggregatorConfig,
+    /// Empty means any length is accepted.
+    pub payload_size_classes: Vec<u32>,
+    #[serde(with = "dec_format")]
+    pub publish_fee: Balance,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepositoryStats {
+    /// Messages stored, less those deleted. `None` for repositories upgraded
+    /// before the repository counted them.
+    pub message_count: Option<u64>,
+    /// Sealed aggregators plus the current one.
+    pub aggregator_count: u64,
+    pub current_aggregator_item_count: u64,
+    pub aggregator_capacity: u64,
+    pub aggregator_storage_usage: u64,
+}
+
+/// What a private repository panics with when an account without the
+/// publisher role publishes or commits.
+const NOT_ALLOWED_TO_PUBLISH: &str = "Account is not allowed to publish.";
+
+/// The most gas a transaction can attach to its function calls.
+const MAX_TRANSACTION_GAS: u64 = 300 * ONE_TERAGAS;
+/// Any publish can seal a full aggregator, which reads every one of its
+/// Merkle leaves, so each publishing call attaches enough gas for that.
+const PUBLISH_CALL_GAS: u64 = 150 * ONE_TERAGAS;
+/// How many publishing function calls fit in one transaction.
+const PUBLISH_CALLS_PER_TRANSACTION: usize = (MAX_TRANSACTION_GAS / PUBLISH_CALL_GAS) as usize;
+
+/// Why a repository rejected a publish. Returned through [`anyhow::Error`],
+/// from which it can be downcast.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublishError {
+    /// The repository is private, and the account has not been granted the
+    /// publisher role.
+    NotAllowed {
+        account_id: AccountId,
+        repository_id: AccountId,
+    },
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAllowed {
+                account_id,
+                repository_id,
+            } => write!(
+                f,
+                "{account_id} is not allowed to publish to {repository_id}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PublishError {}
+
+/// The challenge that [`MessageRepository::publish_with_pow`] solves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowChallenge {
+    pub challenge: Vec<u8>,
+    /// Leading zero bits required of the proof of work.
+    pub difficulty: u8,
+}
+
+#[derive(Deserialize)]
+struct PowChallengeBase64 {
+    challenge: String,
+    difficulty: u8,
+}
+
+impl TryFrom<PowChallengeBase64> for PowChallenge {
+    type Error = anyhow::Error;
+
+    fn try_from(value: PowChallengeBase64) -> Result<Self, Self::Error> {
+        let challenge = match BASE64.decode(value.challenge.as_bytes()) {
+            Ok(d) => d,
+            Err(e) => bail!("Error decoding from base64: {}", e),
+        };
+
+        Ok(PowChallenge {
+            challenge,
+            difficulty: value.difficulty,
+        })
+    }
+}
+
+/// The first four bytes of a sequence hash as a big-endian integer, with
+/// shorter hashes padded with zeros.
+pub fn sequence_hash_prefix(sequence_hash: &[u8]) -> u32 {
+    let mut prefix = [0; 4];
+    let len = sequence_hash.len().min(4);
+    prefix[..len].copy_from_slice(&sequence_hash[..len]);
+    u32::from_be_bytes(prefix)
+}
+
+/// An inclusive range of [`sequence_hash_prefix`]es.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl PrefixRange {
+    pub fn contains(&self, sequence_hash: &[u8]) -> bool {
+        (self.start..=self.end).contains(&sequence_hash_prefix(sequence_hash))
+    }
+}
+
+/// One entry of a message directory's shard map.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Shard {
+    pub range: PrefixRange,
+    /// The message repository that stores the range.
+    pub account_id: AccountId,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpochInfo {
+    pub epoch_duration_ms: u64,
+    /// The epoch of the oldest aggregator.
+    pub first_epoch: u64,
+    /// The epoch of the latest block.
+    pub current_epoch: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AggregatorQuery {
+    Since(u64),
+    Epoch(u64),
+}
+
+/// The index of the first aggregator that may still receive messages, per
+/// repository account: the one that was current when it was last paged
+/// through.
+pub type AggregatorCursor = HashMap<AccountId, u64>;
+
+/// Pages through aggregators as they are consumed, one shard after another.
+/// See [`MessageRepository::aggregators_since`],
+/// [`MessageRepository::aggregators_from`] and
+/// [`MessageRepository::epoch_aggregators`].
+pub struct Aggregators<'a> {
+    /// The repositories still to page through, starting with the current one.
+    message_repositories: VecDeque<&'a MessageRepository>,
+    query: AggregatorQuery,
+    buffered: VecDeque<AggregatorRecord>,
+    next_index: Option<u64>,
+    encoding: ViewEncoding,
+    cursor: AggregatorCursor,
+}
+
+impl<'a> Aggregators<'a> {
+    fn new(message_repositories: Vec<&'a MessageRepository>, query: AggregatorQuery) -> Self {
+        Self {
+            message_repositories: message_repositories.into(),
+            query,
+            buffered: VecDeque::new(),
+            next_index: Some(0),
+            encoding: ViewEncoding::Json,
+            cursor: AggregatorCursor::new(),
+        }
+    }
+
+    /// Fetches pages in `encoding` rather than JSON.
+    pub fn with_encoding(mut self, encoding: ViewEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Where each repository paged through so far would resume. Pass it to
+    /// [`MessageRepository::aggregators_from`] once every aggregator has been
+    /// consumed.
+    pub fn cursor(&self) -> &AggregatorCursor {
+        &self.cursor
+    }
+
+    pub async fn next(&mut self) -> anyhow::Result<Option<AggregatorRecord>> {
+        while self.buffered.is_empty() {
+            let Some(&message_repository) = self.message_repositories.front() else {
+                return Ok(None);
+            };
+            let Some(from_index) = self.next_index else {
+                self.message_repositories.pop_front();
+                self.next_index = self
+                    .message_repositories
+                    .front()
+                    .map(|next| self.cursor.get(&next.account_id).copied().unwrap_or(0));
+                continue;
+            };
+
+            let page = match self.query {
+                AggregatorQuery::Since(since_block_timestamp_ms) => {
+                    message_repository
+                        .get_aggregators(since_block_timestamp_ms, from_index, None, self.encoding)
+                        .await?
+                }
+                AggregatorQuery::Epoch(epoch) => {
+                    message_repository
+                        .get_epoch_aggregators(epoch, from_index, None, self.encoding)
+                        .await?
+                }
+            };
+
+            // the current aggregator, or else the one after the last sealed
+            if let Some(last) = page.aggregators.last() {
+                let resume_index = last.index + u64::from(last.end_block_timestamp_ms.is_some());
+                self.cursor
+                    .insert(message_repository.account_id.clone(), resume_index);
+            }
+            self.buffered.extend(page.aggregators);
+            self.next_index = page.next_index;
+        }
+
+        Ok(self.buffered.pop_front())
+    }
+}
+
+/// A message to publish, along with anything the repository's publish mode
+/// requires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingMessage {
+    pub sequence_hash: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub proof: Option<PublishProof>,
+    /// `sha256` of the key that can later delete the message with
+    /// [`MessageRepository::delete_message`].
+    pub deletion_key_hash: Option<[u8; 32]>,
+    /// The sequence hash that must already be published for this message to
+    /// be accepted.
+    pub previous_sequence_hash: Option<Vec<u8>>,
+}
+
+impl OutgoingMessage {
+    pub fn new(sequence_hash: &[u8], ciphertext: &[u8]) -> Self {
+        Self {
+            sequence_hash: sequence_hash.to_vec(),
+            ciphertext: ciphertext.to_vec(),
+            proof: None,
+            deletion_key_hash: None,
+            previous_sequence_hash: None,
+        }
+    }
+
+    pub fn with_proof(mut self, proof: PublishProof) -> Self {
+        self.proof = Some(proof);
+        self
+    }
+
+    /// Lets whoever knows `deletion_key` delete the message and refund its
+    /// storage to the publisher.
+    pub fn with_deletion_key(mut self, deletion_key: &[u8]) -> Self {
+        self.deletion_key_hash = Some(Sha256::digest(deletion_key).into());
+        self
+    }
+
+    /// Only publishes the message once `previous_sequence_hash` has been
+    /// published, in the same transaction or before, so that a failed
+    /// publish cannot leave a gap in the sender's chain.
+    pub fn with_previous(mut self, previous_sequence_hash: &[u8]) -> Self {
+        self.previous_sequence_hash = Some(previous_sequence_hash.to_vec());
+        self
+    }
+
+    /// The repository method and arguments that publish the message on its
+    /// own.
+    fn publish_call(&self) -> (&'static str, serde_json::Value) {
+        if self.previous_sequence_hash.is_some() {
+            ("publish_after", json!({ "message": self.to_json() }))
+        } else {
+            ("publish", self.to_json())
+        }
+    }
+
+    /// The value to [`MessageRepository::commit`] before revealing the
+    /// message with `salt`.
+    pub fn commitment(&self, salt: &[u8]) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(&self.sequence_hash)
+            .chain_update(&self.ciphertext)
+            .chain_update(salt)
+            .finalize()
+            .into()
+    }
+
+    /// The key that the repository stores the message's commitment under,
+    /// which does not disclose the sequence hash.
+    pub fn sequence_key(&self) -> [u8; 32] {
+        Sha256::digest(&self.sequence_hash).into()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut args = json!({
+            "sequence_hash": BASE64.encode(&self.sequence_hash),
+            "message": BASE64.encode(&self.ciphertext),
+        });
+
+        if let Some(proof) = &self.proof {
+            args["proof"] = json!(proof);
+        }
+        if let Some(deletion_key_hash) = &self.deletion_key_hash {
+            args["deletion_key_hash"] = json!(BASE64.encode(deletion_key_hash));
+        }
+        if let Some(previous_sequence_hash) = &self.previous_sequence_hash {
+            args["previous_sequence_hash"] = json!(BASE64.encode(previous_sequence_hash));
+        }
+
+        args
+    }
+}
+
+/// Fails if any of `messages` must follow another, which only plain and
+/// sponsored publishes check.
+fn require_unordered<'a>(
+    messages: impl IntoIterator<Item = &'a OutgoingMessage>,
+    publishes: &str,
+) -> anyhow::Result<()> {
+    if messages
+        .into_iter()
+        .any(|message| message.previous_sequence_hash.is_some())
+    {
+        bail!("{publishes} cannot be ordered");
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct MessageRepository {
     wallet: Arc<Wallet>,
     account_id: AccountId,
+    require_commitments: OnceCell<bool>,
+    config: OnceCell<AggregatorConfig>,
+    proof_of_work: bool,
+    /// `None` for the default namespace.
+    namespace: Option<String>,
+    /// Empty unless the handle was created with
+    /// [`MessageRepository::from_directory`].
+    shards: Vec<(PrefixRange, MessageRepository)>,
 }
 
 impl MessageRepository {
@@ -34,36 +553,700 @@ impl MessageRepository {
         Self {
             wallet,
             account_id: account_id.clone(),
+            require_commitments: OnceCell::new(),
+            config: OnceCell::new(),
+            proof_of_work: false,
+            namespace: None,
+            shards: vec![],
+        }
+    }
+
+    /// A handle to a repository sharded across the contracts listed by the
+    /// message directory `directory_id`. Messages are published to and
+    /// looked up on the shard that stores their sequence hash, and syncing
+    /// pages through the aggregators of every shard.
+    pub async fn from_directory(
+        wallet: Arc<Wallet>,
+        directory_id: &'_ AccountId,
+    ) -> anyhow::Result<Self> {
+        let shards: Vec<Shard> = wallet
+            .view(directory_id.clone(), "get_shards", json!({}))
+            .await?;
+        if shards.is_empty() {
+            bail!("Directory {directory_id} lists no shards");
         }
+
+        let mut message_repository = Self::new(Arc::clone(&wallet), directory_id);
+        message_repository.shards = shards
+            .into_iter()
+            .map(|shard| {
+                (
+                    shard.range,
+                    Self::new(Arc::clone(&wallet), &shard.account_id),
+                )
+            })
+            .collect();
+
+        Ok(message_repository)
+    }
+
+    /// Publishes with [`MessageRepository::publish_with_pow`] instead of
+    /// paying for storage, so the account needs no NEAR beyond gas.
+    pub fn with_proof_of_work(mut self) -> Self {
+        self.proof_of_work = true;
+        self.shards = self
+            .shards
+            .into_iter()
+            .map(|(range, shard)| (range, shard.with_proof_of_work()))
+            .collect();
+        self
+    }
+
+    /// Publishes to and reads from `namespace` instead of the default
+    /// namespace. Namespaces only support plain and sponsored publishes, so
+    /// proof of work, commit-reveal, deletion, Merkle proofs and epochs are
+    /// unavailable.
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self.shards = self
+            .shards
+            .into_iter()
+            .map(|(range, shard)| (range, shard.with_namespace(namespace)))
+            .collect();
+        self
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Adds the namespace, if any, to the arguments of a call.
+    fn namespaced(&self, mut args: serde_json::Value) -> serde_json::Value {
+        if let Some(namespace) = &self.namespace {
+            args["namespace"] = json!(namespace);
+        }
+        args
+    }
+
+    fn require_default_namespace(&self, method_name: &str) -> anyhow::Result<()> {
+        if let Some(namespace) = &self.namespace {
+            bail!("{method_name} is not supported in namespace {namespace}");
+        }
+        Ok(())
+    }
+
+    pub fn is_sharded(&self) -> bool {
+        !self.shards.is_empty()
+    }
+
+    /// The repository that stores `sequence_hash`: its shard if this
+    /// repository is sharded, or else the repository itself.
+    pub fn shard_for(&self, sequence_hash: &[u8]) -> anyhow::Result<&MessageRepository> {
+        if !self.is_sharded() {
+            return Ok(self);
+        }
+
+        self.shards
+            .iter()
+            .find(|(range, _)| range.contains(sequence_hash))
+            .map(|(_, shard)| shard)
+            .context("No shard covers the sequence hash")
+    }
+
+    /// [`MessageRepository::shard_for`] the message, which must be on the
+    /// same shard as the message it follows, if any.
+    fn shard_for_message(&self, message: &OutgoingMessage) -> anyhow::Result<&MessageRepository> {
+        let shard = self.shard_for(&message.sequence_hash)?;
+        if let Some(previous_sequence_hash) = &message.previous_sequence_hash {
+            if !std::ptr::eq(self.shard_for(previous_sequence_hash)?, shard) {
+                bail!("A message and the one it follows must be on the same shard");
+            }
+        }
+        Ok(shard)
+    }
+
+    /// The repositories that store messages: every shard, or just this one.
+    fn stores(&self) -> Vec<&MessageRepository> {
+        if self.is_sharded() {
+            self.shards.iter().map(|(_, shard)| shard).collect()
+        } else {
+            vec![self]
+        }
+    }
+
+    /// Groups `messages` by the repository that stores them, keeping their
+    /// order within each group.
+    fn group_by_shard(
+        &self,
+        messages: &[OutgoingMessage],
+    ) -> anyhow::Result<Vec<(&MessageRepository, Vec<OutgoingMessage>)>> {
+        let mut groups: Vec<(&MessageRepository, Vec<OutgoingMessage>)> = vec![];
+        for message in messages {
+            let shard = self.shard_for_message(message)?;
+            match groups.iter_mut().find(|(s, _)| std::ptr::eq(*s, shard)) {
+                Some((_, group)) => group.push(message.clone()),
+                None => groups.push((shard, vec![message.clone()])),
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// The shards of one deployment share their configuration, so a sharded
+    /// repository reads it from its first shard.
+    fn config_account_id(&self) -> AccountId {
+        self.stores()[0].account_id.clone()
+    }
+
+    /// Fails with a [`PublishError`] if `outcome` is a publish or commit that
+    /// the repository rejected for one. Other failures are not checked.
+    fn check_publish_outcome(&self, outcome: &FinalExecutionOutcomeView) -> anyhow::Result<()> {
+        if let FinalExecutionStatus::Failure(error) = &outcome.status {
+            if error.to_string().contains(NOT_ALLOWED_TO_PUBLISH) {
+                return Err(PublishError::NotAllowed {
+                    account_id: self.wallet.account_id.clone(),
+                    repository_id: self.account_id.clone(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn require_unsharded(&self, method_name: &str) -> anyhow::Result<()> {
+        if self.is_sharded() {
+            bail!("Call {method_name} on a shard (see MessageRepository::shard_for)");
+        }
+        Ok(())
+    }
+
+    /// Whether the account's storage balance on the repository has `amount`
+    /// available. Checked anew for every call, since each one spends from it.
+    async fn storage_balance_covers(&self, amount: u128) -> anyhow::Result<bool> {
+        Ok(self
+            .wallet
+            .storage_balance_of(self.account_id.clone(), &self.wallet.account_id)
+            .await?
+            .is_some_and(|balance| balance.available >= amount))
+    }
+
+    /// What publishing `messages` in a single call costs this account.
+    async fn publish_cost(&self, messages: &[OutgoingMessage]) -> anyhow::Result<u128> {
+        let mut cost = 0;
+        let mut rate_limit_storage_fee = 0;
+        for message in messages {
+            let quote = self.quote_publish(message).await?;
+            cost +=
+                quote.storage_fee + quote.aggregator_fee + quote.publish_fee + quote.service_fee;
+            rate_limit_storage_fee = rate_limit_storage_fee.max(quote.rate_limit_storage_fee);
+        }
+
+        Ok(cost + rate_limit_storage_fee)
+    }
+
+    /// The exact deposit to publish `messages` in a single call, or nothing
+    /// if the account's storage balance covers it.
+    async fn publish_deposit(&self, messages: &[OutgoingMessage]) -> anyhow::Result<u128> {
+        let cost = self.publish_cost(messages).await?;
+        Ok(if self.storage_balance_covers(cost).await? {
+            0
+        } else {
+            cost
+        })
+    }
+
+    /// What the repository that stores `message` charges this account to
+    /// publish it.
+    pub async fn quote_publish(&self, message: &OutgoingMessage) -> anyhow::Result<PublishQuote> {
+        self.wallet
+            .view(
+                self.shard_for(&message.sequence_hash)?.account_id.clone(),
+                "quote_publish",
+                self.namespaced(json!({
+                    "ciphertext_len": message.ciphertext.len(),
+                    "account_id": self.wallet.account_id,
+                    "deletable": message.deletion_key_hash.is_some(),
+                })),
+            )
+            .await
+    }
+
+    pub async fn get_stats(&self) -> anyhow::Result<RepositoryStats> {
+        self.require_unsharded("get_stats")?;
+        self.wallet
+            .view(
+                self.account_id.clone(),
+                "get_stats",
+                self.namespaced(json!({})),
+            )
+            .await
+    }
+
+    /// Whether the account can publish, which only a private repository
+    /// restricts. A sharded repository must allow it on every shard.
+    pub async fn can_publish(&self) -> anyhow::Result<bool> {
+        for store in self.stores() {
+            let allowed: bool = self
+                .wallet
+                .view(
+                    store.account_id.clone(),
+                    "can_publish",
+                    json!({ "account_id": self.wallet.account_id }),
+                )
+                .await?;
+            if !allowed {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Whether the repository only accepts messages through commit-reveal.
+    /// The answer is cached for the lifetime of the repository handle.
+    pub async fn requires_commitments(&self) -> anyhow::Result<bool> {
+        self.require_commitments
+            .get_or_try_init(|| async {
+                self.wallet
+                    .view(
+                        self.config_account_id(),
+                        "get_require_commitments",
+                        json!({}),
+                    )
+                    .await
+            })
+            .await
+            .copied()
+    }
+
+    /// The settings of the namespace, which must exist.
+    pub async fn get_namespace_config(&self) -> anyhow::Result<NamespaceConfig> {
+        let Some(namespace) = &self.namespace else {
+            bail!("Repository handle is for the default namespace");
+        };
+        let config: Option<NamespaceConfig> = self
+            .wallet
+            .view(
+                self.config_account_id(),
+                "get_namespace_config",
+                json!({ "namespace": namespace }),
+            )
+            .await?;
+
+        config.with_context(|| format!("Namespace {namespace} not found"))
+    }
+
+    /// The payload size classes enforced by the repository or namespace, or
+    /// the default classes if it accepts any length.
+    pub async fn get_payload_size_classes(&self) -> anyhow::Result<PayloadSizeClasses> {
+        let classes: Vec<u32> = if self.namespace.is_some() {
+            self.get_namespace_config().await?.payload_size_classes
+        } else {
+            self.wallet
+                .view(
+                    self.config_account_id(),
+                    "get_payload_size_classes",
+                    json!({}),
+                )
+                .await?
+        };
+
+        if classes.is_empty() {
+            Ok(PayloadSizeClasses::default())
+        } else {
+            PayloadSizeClasses::new(classes)
+        }
+    }
+
+    /// The aggregator parameters of the repository or namespace. The answer
+    /// is cached for the lifetime of the repository handle.
+    pub async fn get_config(&self) -> anyhow::Result<AggregatorConfig> {
+        self.config
+            .get_or_try_init(|| async {
+                if self.namespace.is_some() {
+                    return Ok(self.get_namespace_config().await?.aggregator_config);
+                }
+                self.wallet
+                    .view(self.config_account_id(), "get_config", json!({}))
+                    .await
+            })
+            .await
+            .copied()
+    }
+
+    /// The current proof-of-work challenge, or `None` if the repository does
+    /// not accept proof-of-work publishes.
+    pub async fn get_pow_challenge(&self) -> anyhow::Result<Option<PowChallenge>> {
+        self.require_default_namespace("get_pow_challenge")?;
+        let challenge: Option<PowChallengeBase64> = self
+            .wallet
+            .view(self.config_account_id(), "get_pow_challenge", json!({}))
+            .await?;
+
+        challenge.map(TryInto::try_into).transpose()
     }
 
     pub async fn get_message(
         &self,
         sequence_hash: &[u8],
+        encoding: ViewEncoding,
     ) -> anyhow::Result<Option<EncryptedMessage>> {
-        let base64_encoded_message: Option<EncryptedMessageBase64> = self
+        let account_id = self.shard_for(sequence_hash)?.account_id.clone();
+        let args = self.namespaced(json!({ "sequence_hash": BASE64.encode(sequence_hash) }));
+
+        match encoding {
+            ViewEncoding::Json => {
+                let base64_encoded_message: Option<EncryptedMessageBase64> =
+                    self.wallet.view(account_id, "get_message", args).await?;
+
+                base64_encoded_message.map(TryInto::try_into).transpose()
+            }
+            ViewEncoding::Borsh => {
+                self.wallet
+                    .view_borsh(account_id, "get_message_borsh", args)
+                    .await
+            }
+        }
+    }
+
+    /// Looks up many sequence hashes in a single view call. The result has
+    /// one entry per requested hash, in the same order.
+    pub async fn get_messages(
+        &self,
+        sequence_hashes: &[&[u8]],
+        encoding: ViewEncoding,
+    ) -> anyhow::Result<Vec<Option<EncryptedMessage>>> {
+        if !self.is_sharded() {
+            return self
+                .view_messages(&self.account_id, sequence_hashes, encoding)
+                .await;
+        }
+
+        let mut messages = vec![None; sequence_hashes.len()];
+        for store in self.stores() {
+            let indices = sequence_hashes
+                .iter()
+                .enumerate()
+                .filter(|(_, h)| self.shard_for(h).is_ok_and(|s| std::ptr::eq(s, store)))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            let found = self
+                .view_messages(
+                    &store.account_id,
+                    &indices
+                        .iter()
+                        .map(|&i| sequence_hashes[i])
+                        .collect::<Vec<_>>(),
+                    encoding,
+                )
+                .await?;
+            for (i, message) in indices.into_iter().zip(found) {
+                messages[i] = message;
+            }
+        }
+
+        Ok(messages)
+    }
+
+    async fn view_messages(
+        &self,
+        account_id: &AccountId,
+        sequence_hashes: &[&[u8]],
+        encoding: ViewEncoding,
+    ) -> anyhow::Result<Vec<Option<EncryptedMessage>>> {
+        if sequence_hashes.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let args = self.namespaced(json!({
+            "sequence_hashes": sequence_hashes
+                .iter()
+                .map(|h| BASE64.encode(h))
+                .collect::<Vec<_>>(),
+        }));
+        let messages: Vec<Option<EncryptedMessage>> = match encoding {
+            ViewEncoding::Json => {
+                let base64_encoded_messages: Vec<Option<EncryptedMessageBase64>> = self
+                    .wallet
+                    .view(account_id.clone(), "get_messages", args)
+                    .await?;
+
+                base64_encoded_messages
+                    .into_iter()
+                    .map(|m| m.map(TryInto::try_into).transpose())
+                    .collect::<anyhow::Result<_>>()?
+            }
+            ViewEncoding::Borsh => {
+                self.wallet
+                    .view_borsh(account_id.clone(), "get_messages_borsh", args)
+                    .await?
+            }
+        };
+
+        if messages.len() != sequence_hashes.len() {
+            bail!(
+                "Expected {} messages, got {}",
+                sequence_hashes.len(),
+                messages.len(),
+            );
+        }
+
+        Ok(messages)
+    }
+
+    /// Whether each sequence hash belonged to a message that was deleted, in
+    /// the same order. Namespaced messages cannot be deleted.
+    pub async fn are_deleted(&self, sequence_hashes: &[&[u8]]) -> anyhow::Result<Vec<bool>> {
+        let mut deleted = vec![false; sequence_hashes.len()];
+        if self.namespace.is_some() {
+            return Ok(deleted);
+        }
+
+        for store in self.stores() {
+            let indices = sequence_hashes
+                .iter()
+                .enumerate()
+                .filter(|(_, h)| self.shard_for(h).is_ok_and(|s| std::ptr::eq(s, store)))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            if indices.is_empty() {
+                continue;
+            }
+
+            let found: Vec<bool> = self
+                .wallet
+                .view(
+                    store.account_id.clone(),
+                    "are_deleted",
+                    json!({
+                        "sequence_hashes": indices
+                            .iter()
+                            .map(|&i| BASE64.encode(sequence_hashes[i]))
+                            .collect::<Vec<_>>(),
+                    }),
+                )
+                .await?;
+            if found.len() != indices.len() {
+                bail!("Expected {} results, got {}", indices.len(), found.len());
+            }
+            for (i, is_deleted) in indices.into_iter().zip(found) {
+                deleted[i] = is_deleted;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// The Merkle root of a sealed aggregator, or `None` if it is still
+    /// current or was sealed before the repository kept roots. Roots are also
+    /// logged by `AggregatorSealed` events, so they can be pinned from
+    /// transaction outcomes instead of trusting a view.
+    pub async fn get_merkle_root(&self, aggregator_index: u64) -> anyhow::Result<Option<[u8; 32]>> {
+        self.require_unsharded("get_merkle_root")?;
+        self.require_default_namespace("get_merkle_root")?;
+        let root: Option<String> = self
             .wallet
             .view(
                 self.account_id.clone(),
-                "get_message",
-                json!({ "sequence_hash": BASE64.encode(sequence_hash) }),
+                "get_merkle_root",
+                json!({ "aggregator_index": aggregator_index }),
             )
             .await?;
 
-        let base64_encoded_message = match base64_encoded_message {
-            Some(r) => r,
-            _ => return Ok(None),
-        };
+        root.map(|root| {
+            BASE64
+                .decode(root.as_bytes())?
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("Merkle root must be 32 bytes"))
+        })
+        .transpose()
+    }
 
-        let message = match BASE64.decode(base64_encoded_message.message.as_bytes()) {
-            Ok(d) => d,
-            Err(e) => bail!("Error decoding from base64: {}", e),
-        };
+    /// Looks up a message and proves the answer against `merkle_root`, the
+    /// root of the aggregator that would hold it. Fails if the repository's
+    /// answer disagrees with the proof, rather than returning a message that
+    /// was forged or hiding one that was published. `None` only means the
+    /// message is not in that aggregator, and a deleted message is an error,
+    /// see [`merkle`] for what else the proof does not cover.
+    pub async fn get_verified_message(
+        &self,
+        sequence_hash: &[u8],
+        aggregator_index: u64,
+        merkle_root: &[u8; 32],
+        encoding: ViewEncoding,
+    ) -> anyhow::Result<Option<EncryptedMessage>> {
+        self.require_default_namespace("get_verified_message")?;
+        let shard = self.shard_for(sequence_hash)?;
+        let proof: Option<MerkleProof> = self
+            .wallet
+            .view(
+                shard.account_id.clone(),
+                "get_merkle_proof",
+                json!({
+                    "aggregator_index": aggregator_index,
+                    "sequence_hash": BASE64.encode(sequence_hash),
+                }),
+            )
+            .await?;
+        let proof =
+            proof.with_context(|| format!("Aggregator {aggregator_index} has no Merkle root"))?;
+        let message_hash = proof.verify(merkle_root, sequence_hash)?;
 
-        Ok(Some(EncryptedMessage {
-            message,
-            block_timestamp_ms: base64_encoded_message.block_timestamp_ms,
-        }))
+        let message = shard.get_message(sequence_hash, encoding).await?;
+        match (&message, message_hash) {
+            (Some(m), Some(hash)) if merkle::message_hash(&m.message) == hash => {}
+            (None, None) => {}
+            (Some(_), Some(_)) => bail!("Message does not match its Merkle proof"),
+            (Some(_), None) => bail!("Message is not in the aggregator's Merkle tree"),
+            // the tree cannot tell a deleted message from a hidden one
+            (None, Some(_)) => {
+                bail!("Message in the aggregator's Merkle tree is missing or was deleted")
+            }
+        }
+
+        Ok(message)
+    }
+
+    /// Fetches one page of the aggregators that can contain messages
+    /// published at or after `since_block_timestamp_ms`.
+    pub async fn get_aggregators(
+        &self,
+        since_block_timestamp_ms: u64,
+        from_index: u64,
+        limit: Option<u32>,
+        encoding: ViewEncoding,
+    ) -> anyhow::Result<AggregatorPage> {
+        self.require_unsharded("get_aggregators")?;
+        self.view_aggregator_page(
+            "get_aggregators",
+            self.namespaced(json!({
+                "since_block_timestamp_ms": since_block_timestamp_ms,
+                "from_index": from_index,
+                "limit": limit,
+            })),
+            encoding,
+        )
+        .await
+    }
+
+    /// The repository's epoch schedule, or `None` if its aggregators are not
+    /// sealed on epochs, as a namespace's never are. The epochs of a sharded
+    /// repository span those of all of its shards.
+    pub async fn get_epochs(&self) -> anyhow::Result<Option<EpochInfo>> {
+        if self.namespace.is_some() {
+            return Ok(None);
+        }
+
+        let mut combined: Option<EpochInfo> = None;
+        for store in self.stores() {
+            let epochs: Option<EpochInfo> = self
+                .wallet
+                .view(store.account_id.clone(), "get_epochs", json!({}))
+                .await?;
+            let Some(epochs) = epochs else {
+                return Ok(None);
+            };
+
+            combined = Some(match combined {
+                None => epochs,
+                Some(c) if c.epoch_duration_ms == epochs.epoch_duration_ms => EpochInfo {
+                    epoch_duration_ms: c.epoch_duration_ms,
+                    first_epoch: c.first_epoch.min(epochs.first_epoch),
+                    current_epoch: c.current_epoch.max(epochs.current_epoch),
+                },
+                Some(_) => bail!("Shards have different epoch durations"),
+            });
+        }
+
+        Ok(combined)
+    }
+
+    /// Fetches one page of the aggregators sealed for `epoch`.
+    pub async fn get_epoch_aggregators(
+        &self,
+        epoch: u64,
+        from_index: u64,
+        limit: Option<u32>,
+        encoding: ViewEncoding,
+    ) -> anyhow::Result<AggregatorPage> {
+        self.require_unsharded("get_epoch_aggregators")?;
+        self.require_default_namespace("get_epoch_aggregators")?;
+        self.view_aggregator_page(
+            "get_epoch_aggregators",
+            json!({
+                "epoch": epoch,
+                "from_index": from_index,
+                "limit": limit,
+            }),
+            encoding,
+        )
+        .await
+    }
+
+    async fn view_aggregator_page(
+        &self,
+        method_name: &str,
+        args: serde_json::Value,
+        encoding: ViewEncoding,
+    ) -> anyhow::Result<AggregatorPage> {
+        let account_id = self.account_id.clone();
+
+        match encoding {
+            ViewEncoding::Json => {
+                let page: AggregatorPageBase64 =
+                    self.wallet.view(account_id, method_name, args).await?;
+
+                Ok(AggregatorPage {
+                    aggregators: page
+                        .aggregators
+                        .into_iter()
+                        .map(TryInto::try_into)
+                        .collect::<anyhow::Result<_>>()?,
+                    next_index: page.next_index,
+                })
+            }
+            ViewEncoding::Borsh => {
+                let page: AggregatorPageBorsh = self
+                    .wallet
+                    .view_borsh(account_id, format!("{method_name}_borsh"), args)
+                    .await?;
+
+                Ok(AggregatorPage {
+                    aggregators: page
+                        .aggregators
+                        .into_iter()
+                        .map(TryInto::try_into)
+                        .collect::<anyhow::Result<_>>()?,
+                    next_index: page.next_index,
+                })
+            }
+        }
+    }
+
+    /// Lazily pages through the aggregators that can contain messages
+    /// published at or after `since_block_timestamp_ms`, oldest first.
+    pub fn aggregators_since(&self, since_block_timestamp_ms: u64) -> Aggregators<'_> {
+        Aggregators::new(
+            self.stores(),
+            AggregatorQuery::Since(since_block_timestamp_ms),
+        )
+    }
+
+    /// Lazily pages through the aggregators from where `cursor` left off,
+    /// oldest first. Unlike a timestamp, the cursor comes from the chain, so
+    /// a later call resumes exactly where an earlier one stopped.
+    pub fn aggregators_from(&self, cursor: &AggregatorCursor) -> Aggregators<'_> {
+        let mut aggregators = Aggregators::new(self.stores(), AggregatorQuery::Since(0));
+        aggregators.next_index = aggregators
+            .message_repositories
+            .front()
+            .map(|first| cursor.get(&first.account_id).copied().unwrap_or(0));
+        aggregators.cursor = cursor.clone();
+        aggregators
+    }
+
+    /// Lazily pages through the aggregators of `epoch`.
+    pub fn epoch_aggregators(&self, epoch: u64) -> Aggregators<'_> {
+        Aggregators::new(self.stores(), AggregatorQuery::Epoch(epoch))
     }
 
     pub async fn publish_message(
@@ -71,23 +1254,311 @@ impl MessageRepository {
         sequence_hash: &[u8],
         ciphertext: &[u8],
     ) -> anyhow::Result<()> {
-        self.wallet
+        self.publish_outgoing(&OutgoingMessage::new(sequence_hash, ciphertext))
+            .await
+    }
+
+    pub async fn publish_outgoing(&self, message: &OutgoingMessage) -> anyhow::Result<()> {
+        if self.is_sharded() {
+            let shard = self.shard_for_message(message)?;
+            return Box::pin(shard.publish_outgoing(message)).await;
+        }
+        if self.requires_commitments().await? {
+            return self.commit_and_reveal(std::slice::from_ref(message)).await;
+        }
+        if self.proof_of_work {
+            return self.publish_with_pow(std::slice::from_ref(message)).await;
+        }
+
+        let (method_name, args) = message.publish_call();
+        let outcome = self
+            .wallet
             .transact(
                 self.account_id.clone(),
                 vec![Action::FunctionCall(Box::new(FunctionCallAction {
-                    method_name: "publish".to_string(),
+                    method_name: method_name.to_string(),
+                    args: self.namespaced(args).to_string().into_bytes(),
+                    gas: MAX_TRANSACTION_GAS,
+                    deposit: self.publish_deposit(std::slice::from_ref(message)).await?,
+                }))],
+            )
+            .await?;
+
+        self.check_publish_outcome(&outcome)
+    }
+
+    /// Publishes many messages in a single transaction, or one per shard.
+    pub async fn publish_messages(&self, messages: &[OutgoingMessage]) -> anyhow::Result<()> {
+        if self.is_sharded() {
+            for (shard, messages) in self.group_by_shard(messages)? {
+                Box::pin(shard.publish_messages(&messages)).await?;
+            }
+            return Ok(());
+        }
+        if self.requires_commitments().await? {
+            return self.commit_and_reveal(messages).await;
+        }
+        if self.proof_of_work {
+            return self.publish_with_pow(messages).await;
+        }
+
+        let outcome = self
+            .wallet
+            .transact(
+                self.account_id.clone(),
+                vec![Action::FunctionCall(Box::new(FunctionCallAction {
+                    method_name: "publish_batch".to_string(),
+                    args: self
+                        .namespaced(json!({
+                            "messages": messages.iter().map(OutgoingMessage::to_json).collect::<Vec<_>>(),
+                        }))
+                        .to_string()
+                        .into_bytes(),
+                    gas: MAX_TRANSACTION_GAS,
+                    deposit: self.publish_deposit(messages).await?,
+                }))],
+            )
+            .await?;
+
+        self.check_publish_outcome(&outcome)
+    }
+
+    /// Solves the current proof-of-work challenge for each message, then
+    /// publishes them, one function call each, in as few transactions as
+    /// their gas allows. The repository's sponsor pool pays for their
+    /// storage.
+    pub async fn publish_with_pow(&self, messages: &[OutgoingMessage]) -> anyhow::Result<()> {
+        if self.is_sharded() {
+            for (shard, messages) in self.group_by_shard(messages)? {
+                Box::pin(shard.publish_with_pow(&messages)).await?;
+            }
+            return Ok(());
+        }
+        self.require_default_namespace("publish_with_pow")?;
+        require_unordered(messages, "Proof-of-work publishes")?;
+
+        let Some(challenge) = self.get_pow_challenge().await? else {
+            bail!("Repository does not accept proof-of-work publishes");
+        };
+
+        let solved = messages.to_vec();
+        let nonces = tokio::task::spawn_blocking(move || {
+            solved
+                .iter()
+                .map(|message| {
+                    pow::solve(
+                        &challenge.challenge,
+                        challenge.difficulty,
+                        &message.sequence_hash,
+                        &message.ciphertext,
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .await?;
+
+        let actions = messages
+            .iter()
+            .zip(nonces)
+            .map(|(message, nonce)| {
+                let mut args = message.to_json();
+                args["pow_nonce"] = json!(nonce.to_string());
+
+                Action::FunctionCall(Box::new(FunctionCallAction {
+                    method_name: "publish_with_pow".to_string(),
+                    args: args.to_string().into_bytes(),
+                    gas: PUBLISH_CALL_GAS,
+                    deposit: 0,
+                }))
+            })
+            .collect::<Vec<_>>();
+
+        self.transact_publish_calls(actions).await
+    }
+
+    /// Sends publishing function calls, each with [`PUBLISH_CALL_GAS`], in
+    /// as many transactions as that takes.
+    async fn transact_publish_calls(&self, actions: Vec<Action>) -> anyhow::Result<()> {
+        for actions in actions.chunks(PUBLISH_CALLS_PER_TRANSACTION) {
+            let outcome = self
+                .wallet
+                .transact(self.account_id.clone(), actions.to_vec())
+                .await?;
+            self.check_publish_outcome(&outcome)?;
+        }
+
+        Ok(())
+    }
+
+    /// Signs a publish of `messages` for a relayer to submit as a NEP-366
+    /// meta transaction, with the relayer `sponsor` paying for the gas and,
+    /// from its storage balance, for the storage.
+    pub async fn sign_sponsored_publish(
+        &self,
+        messages: &[OutgoingMessage],
+        sponsor: &AccountId,
+    ) -> anyhow::Result<SignedDelegateAction> {
+        if self.is_sharded() {
+            let shards = self.group_by_shard(messages)?;
+            let [(shard, messages)] = &shards[..] else {
+                bail!("A sponsored publish can only go to one shard");
+            };
+            return Box::pin(shard.sign_sponsored_publish(messages, sponsor)).await;
+        }
+        if self.requires_commitments().await? {
+            bail!("Repository requires messages to be committed before they are published");
+        }
+
+        let (method_name, mut args) = match messages {
+            [] => bail!("No messages to publish"),
+            [message] => message.publish_call(),
+            _ => (
+                "publish_batch",
+                json!({
+                    "messages": messages.iter().map(OutgoingMessage::to_json).collect::<Vec<_>>(),
+                }),
+            ),
+        };
+        args["sponsor"] = json!(sponsor);
+        let args = self.namespaced(args);
+
+        self.wallet
+            .sign_delegate_action(
+                self.account_id.clone(),
+                vec![Action::FunctionCall(Box::new(FunctionCallAction {
+                    method_name: method_name.to_string(),
+                    args: args.to_string().into_bytes(),
+                    gas: MAX_TRANSACTION_GAS,
+                    deposit: 0,
+                }))],
+            )
+            .await
+    }
+
+    /// Deletes a message published with a deletion key, refunding its storage
+    /// to whoever paid for it. The sequence hash cannot be published again.
+    pub async fn delete_message(
+        &self,
+        sequence_hash: &[u8],
+        deletion_key: &[u8],
+    ) -> anyhow::Result<()> {
+        self.require_default_namespace("delete_message")?;
+        self.wallet
+            .transact(
+                self.shard_for(sequence_hash)?.account_id.clone(),
+                vec![Action::FunctionCall(Box::new(FunctionCallAction {
+                    method_name: "delete".to_string(),
                     args: json!({
                         "sequence_hash": BASE64.encode(sequence_hash),
-                        "message": BASE64.encode(ciphertext),
+                        "deletion_key": BASE64.encode(deletion_key),
                     })
                     .to_string()
                     .into_bytes(),
-                    gas: 300 * ONE_TERAGAS,
-                    deposit: ONE_NEAR,
+                    gas: 30 * ONE_TERAGAS,
+                    deposit: 0,
                 }))],
             )
             .await?;
 
         Ok(())
     }
+
+    /// Reserves the slots for messages to be revealed with their salts, one
+    /// function call each, in a single transaction. A reveal is accepted for
+    /// the earliest live commitment to the same message.
+    pub async fn commit(&self, messages: &[(&OutgoingMessage, &[u8])]) -> anyhow::Result<()> {
+        self.require_unsharded("commit")?;
+        self.require_default_namespace("commit")?;
+        let gas = MAX_TRANSACTION_GAS / messages.len().max(1) as u64;
+        let mut deposit = ONE_NEAR / 100;
+        if self
+            .storage_balance_covers(deposit * messages.len() as u128)
+            .await?
+        {
+            deposit = 0;
+        }
+
+        let outcome = self
+            .wallet
+            .transact(
+                self.account_id.clone(),
+                messages
+                    .iter()
+                    .map(|(message, salt)| {
+                        Action::FunctionCall(Box::new(FunctionCallAction {
+                            method_name: "commit".to_string(),
+                            args: json!({
+                                "commitment": BASE64.encode(&message.commitment(salt)),
+                                "sequence_key": BASE64.encode(&message.sequence_key()),
+                            })
+                            .to_string()
+                            .into_bytes(),
+                            gas,
+                            deposit,
+                        }))
+                    })
+                    .collect(),
+            )
+            .await?;
+
+        self.check_publish_outcome(&outcome)
+    }
+
+    /// Reveals messages committed to with [`MessageRepository::commit`],
+    /// one function call each, in as few transactions as their gas allows.
+    pub async fn reveal(&self, messages: &[(&OutgoingMessage, &[u8])]) -> anyhow::Result<()> {
+        self.require_unsharded("reveal")?;
+        self.require_default_namespace("reveal")?;
+        require_unordered(
+            messages.iter().map(|(message, _)| *message),
+            "Revealed messages",
+        )?;
+        let mut costs = vec![];
+        for (message, _) in messages {
+            costs.push(self.publish_cost(std::slice::from_ref(*message)).await?);
+        }
+        if self.storage_balance_covers(costs.iter().sum()).await? {
+            costs.fill(0);
+        }
+
+        let mut actions = vec![];
+        for ((message, salt), deposit) in messages.iter().zip(costs) {
+            let mut args = message.to_json();
+            args["salt"] = json!(BASE64.encode(salt));
+
+            actions.push(Action::FunctionCall(Box::new(FunctionCallAction {
+                method_name: "reveal".to_string(),
+                args: args.to_string().into_bytes(),
+                gas: PUBLISH_CALL_GAS,
+                deposit,
+            })));
+        }
+
+        self.transact_publish_calls(actions).await
+    }
+
+    /// Commits to the messages under fresh salts, then reveals them. The
+    /// reveal is only sent once the commit transaction has executed, so it
+    /// always lands in a later block.
+    async fn commit_and_reveal(&self, messages: &[OutgoingMessage]) -> anyhow::Result<()> {
+        self.require_default_namespace("reveal")?;
+        require_unordered(messages, "Revealed messages")?;
+        let salts = messages
+            .iter()
+            .map(|_| {
+                let mut salt = [0u8; 32];
+                OsRng.fill_bytes(&mut salt);
+                salt
+            })
+            .collect::<Vec<_>>();
+
+        let salted = messages
+            .iter()
+            .zip(&salts)
+            .map(|(message, salt)| (message, &salt[..]))
+            .collect::<Vec<_>>();
+        self.commit(&salted).await?;
+
+        self.reveal(&salted).await
+    }
 }
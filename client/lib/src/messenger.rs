@@ -2,12 +2,13 @@ use std::{collections::HashMap, sync::Arc};
 
 use anyhow::bail;
 use near_primitives::types::AccountId;
+use sha2::{Digest, Sha256};
 use tokio::sync::RwLock; // TODO: can we remove?
 use x25519_dalek::{PublicKey, StaticSecret};
 
 use crate::{
     channel::CorrespondentId, group::Group, key_registry::KeyRegistry,
-    message_repository::MessageRepository, wallet::Wallet,
+    message_repository::MessageRepository, prover::Prover, wallet::Wallet,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -72,6 +73,8 @@ pub struct Messenger {
     key_registry: KeyRegistry,
     correspondent_map: Arc<RwLock<HashMap<CorrespondentId, AccountId>>>,
     pub message_repository: Arc<MessageRepository>,
+    prover: Option<Arc<Prover>>,
+    ordered_publish: bool,
 }
 
 impl Messenger {
@@ -95,9 +98,31 @@ impl Messenger {
                 Arc::clone(&wallet),
                 message_repository_account_id,
             )),
+            prover: None,
+            ordered_publish: false,
         }
     }
 
+    /// Replaces the message repository given to [`Messenger::new`], e.g.
+    /// with one created by [`MessageRepository::from_directory`].
+    pub fn with_message_repository(mut self, message_repository: MessageRepository) -> Self {
+        self.message_repository = Arc::new(message_repository);
+        self
+    }
+
+    /// Proves knowledge of the channel secret on every publish, for message
+    /// repositories that require it.
+    pub fn with_prover(mut self, prover: Arc<Prover>) -> Self {
+        self.prover = Some(prover);
+        self
+    }
+
+    /// Sends through groups with [`Group::with_ordered_publish`].
+    pub fn with_ordered_publish(mut self) -> Self {
+        self.ordered_publish = true;
+        self
+    }
+
     pub async fn resolve_correspondent_id(
         &self,
         correspondent_id: &CorrespondentId,
@@ -134,14 +159,30 @@ impl Messenger {
             .secret_key
             .diffie_hellman(&correspondent_public_key.into())
             .to_bytes();
-        let group = Group::new(
+        let mut group = Group::new(
             Arc::clone(&self.message_repository),
             self.public_key().to_bytes().into(),
             vec![correspondent_public_key.into()],
             shared_secret,
             &[], // no context for direct message (?)
+        )
+        .with_payload_size_classes(self.message_repository.get_payload_size_classes().await?)
+        .with_deletion_secret(
+            Sha256::new()
+                .chain_update(b"deletion secret")
+                .chain_update(self.secret_key.to_bytes())
+                .chain_update(correspondent_public_key)
+                .finalize()
+                .into(),
         );
 
+        if let Some(prover) = &self.prover {
+            group = group.with_prover(Arc::clone(prover));
+        }
+        if self.ordered_publish {
+            group = group.with_ordered_publish();
+        }
+
         Ok(group)
     }
 }
@@ -0,0 +1,122 @@
+use anyhow::bail;
+
+/// Length of the Poly1305 tag that encryption appends to every payload.
+pub const AEAD_TAG_LEN: usize = 16;
+
+/// Marks the end of the payload inside the padded plaintext (ISO/IEC 7816-4).
+const PADDING_MARKER: u8 = 0x80;
+
+/// The ciphertext lengths that published messages are padded to, so that the
+/// length of a message only reveals which class it falls into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadSizeClasses(Vec<u32>);
+
+impl Default for PayloadSizeClasses {
+    fn default() -> Self {
+        Self(vec![1 << 10, 1 << 12, 1 << 14, 1 << 16])
+    }
+}
+
+impl PayloadSizeClasses {
+    pub fn new(mut classes: Vec<u32>) -> anyhow::Result<Self> {
+        classes.sort_unstable();
+        classes.dedup();
+
+        let Some(&smallest) = classes.first() else {
+            bail!("At least one payload size class is required");
+        };
+
+        if (smallest as usize) <= AEAD_TAG_LEN {
+            bail!("Payload size class {smallest} cannot fit any payload");
+        }
+
+        Ok(Self(classes))
+    }
+
+    pub fn classes(&self) -> &[u32] {
+        &self.0
+    }
+
+    /// The largest payload that fits into the largest class.
+    pub fn max_payload_len(&self) -> usize {
+        self.0.last().map_or(0, |&c| c as usize - AEAD_TAG_LEN - 1)
+    }
+
+    /// Pads `payload` so that its ciphertext fills the smallest class that
+    /// can hold it.
+    pub fn pad(&self, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let Some(&class) = self
+            .0
+            .iter()
+            .find(|&&c| c as usize > payload.len() + AEAD_TAG_LEN)
+        else {
+            bail!(
+                "Payload of {} bytes exceeds the maximum of {} bytes",
+                payload.len(),
+                self.max_payload_len(),
+            );
+        };
+
+        let mut padded = Vec::with_capacity(class as usize - AEAD_TAG_LEN);
+        padded.extend_from_slice(payload);
+        padded.push(PADDING_MARKER);
+        padded.resize(class as usize - AEAD_TAG_LEN, 0);
+
+        Ok(padded)
+    }
+}
+
+/// Strips the padding added by [`PayloadSizeClasses::pad`].
+pub fn unpad(padded: &[u8]) -> anyhow::Result<&[u8]> {
+    let Some(marker) = padded.iter().rposition(|&b| b != 0) else {
+        bail!("Missing padding marker");
+    };
+
+    if padded[marker] != PADDING_MARKER {
+        bail!("Invalid padding marker");
+    }
+
+    Ok(&padded[..marker])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pad_to_smallest_fitting_class() {
+        let classes = PayloadSizeClasses::new(vec![256, 64]).unwrap();
+
+        let padded = classes.pad(b"hello").unwrap();
+        assert_eq!(padded.len() + AEAD_TAG_LEN, 64);
+        assert_eq!(unpad(&padded).unwrap(), b"hello");
+
+        let payload = [0u8; 64];
+        let padded = classes.pad(&payload).unwrap();
+        assert_eq!(padded.len() + AEAD_TAG_LEN, 256);
+        assert_eq!(unpad(&padded).unwrap(), payload);
+    }
+
+    #[test]
+    fn pad_boundaries() {
+        let classes = PayloadSizeClasses::new(vec![64]).unwrap();
+        assert_eq!(classes.max_payload_len(), 47);
+
+        let payload = [0xffu8; 47];
+        let padded = classes.pad(&payload).unwrap();
+        assert_eq!(padded.len() + AEAD_TAG_LEN, 64);
+        assert_eq!(unpad(&padded).unwrap(), payload);
+
+        assert!(classes.pad(&[0xffu8; 48]).is_err());
+        assert_eq!(unpad(&classes.pad(b"").unwrap()).unwrap(), b"");
+    }
+
+    #[test]
+    fn reject_invalid_padding() {
+        assert!(unpad(&[]).is_err());
+        assert!(unpad(&[0, 0, 0]).is_err());
+        assert!(unpad(&[1, 2, 3, 0]).is_err());
+        assert!(PayloadSizeClasses::new(vec![]).is_err());
+        assert!(PayloadSizeClasses::new(vec![16]).is_err());
+    }
+}
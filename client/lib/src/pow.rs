@@ -0,0 +1,59 @@
+//! Solves the proof-of-work challenges of the message repository, which
+//! admit messages without a deposit. See the contract's `pow` module for the
+//! hash format.
+
+use sha2::{Digest, Sha256};
+
+pub fn pow_hash(challenge: &[u8], sequence_hash: &[u8], message: &[u8], nonce: u64) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(challenge)
+        .chain_update((sequence_hash.len() as u32).to_le_bytes())
+        .chain_update(sequence_hash)
+        .chain_update(message)
+        .chain_update(nonce.to_le_bytes())
+        .finalize()
+        .into()
+}
+
+pub fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Finds the first nonce whose hash has at least `difficulty` leading zero
+/// bits. Expect around `2^difficulty` hashes.
+pub fn solve(challenge: &[u8], difficulty: u8, sequence_hash: &[u8], message: &[u8]) -> u64 {
+    (0..)
+        .find(|nonce| {
+            leading_zero_bits(&pow_hash(challenge, sequence_hash, message, *nonce))
+                >= difficulty as u32
+        })
+        .expect("nonce space exhausted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Must match the contract's `pow::tests::hash_vector`.
+    #[test]
+    fn hash_vector() {
+        assert_eq!(
+            data_encoding::HEXLOWER.encode(&pow_hash(&[7; 32], &[1; 32], b"ciphertext", 42)),
+            "8a333572adf22a77d56dc4e7f9a08d1170cd5126eb3c30691f7a85007409ef6a",
+        );
+    }
+
+    #[test]
+    fn solve_meets_difficulty() {
+        let nonce = solve(&[7; 32], 12, &[1; 32], b"ciphertext");
+
+        assert!(leading_zero_bits(&pow_hash(&[7; 32], &[1; 32], b"ciphertext", nonce)) >= 12);
+    }
+}
@@ -0,0 +1,335 @@
+use anyhow::bail;
+use ark_bn254::{Bn254, Fq, Fr, G1Affine, G2Affine};
+use ark_crypto_primitives::{crh::sha256::constraints::Sha256Gadget, snark::SNARK};
+use ark_ec::AffineRepr;
+use ark_ff::{BigInteger, Field, PrimeField};
+use ark_groth16::{Groth16, ProvingKey, VerifyingKey};
+use ark_r1cs_std::{
+    alloc::AllocVar, boolean::Boolean, eq::EqGadget, fields::fp::FpVar, fields::FieldVar,
+    uint8::UInt8, R1CSVar, ToBitsGadget,
+};
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use data_encoding::BASE64;
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::channel::{Channel, SequenceHashProducer};
+
+/// Proves knowledge of a channel's secret identifier and the nonce that
+/// produce a sequence hash.
+///
+/// Public inputs are the sequence hash and the SHA-256 digest of the
+/// ciphertext, each split into two little-endian 128-bit halves. The digest
+/// is only constrained so that a proof does not verify for any other
+/// ciphertext: the circuit proves nothing about the ciphertext itself, and
+/// it is the repository that computes the digest of the published message.
+#[derive(Clone)]
+pub struct SequenceHashCircuit {
+    nonce: Option<u32>,
+    secret_identifier: Option<[u8; 256]>,
+    sequence_hash: Option<[u8; 32]>,
+    ciphertext_digest: Option<[u8; 32]>,
+}
+
+impl SequenceHashCircuit {
+    fn blank() -> Self {
+        Self {
+            nonce: None,
+            secret_identifier: None,
+            sequence_hash: None,
+            ciphertext_digest: None,
+        }
+    }
+}
+
+fn halves(bytes: &[u8; 32]) -> [Fr; 2] {
+    [
+        Fr::from_le_bytes_mod_order(&bytes[..16]),
+        Fr::from_le_bytes_mod_order(&bytes[16..]),
+    ]
+}
+
+/// The public inputs that the message repository derives for a publish.
+pub fn public_inputs(sequence_hash: &[u8; 32], ciphertext: &[u8]) -> [Fr; 4] {
+    let [h0, h1] = halves(sequence_hash);
+    let [c0, c1] = halves(&Sha256::digest(ciphertext).into());
+    [h0, h1, c0, c1]
+}
+
+impl ConstraintSynthesizer<Fr> for SequenceHashCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        let preimage = match (self.nonce, self.secret_identifier) {
+            (Some(nonce), Some(secret_identifier)) => {
+                [&nonce.to_le_bytes()[..], &secret_identifier]
+                    .concat()
+                    .into_iter()
+                    .map(Some)
+                    .collect()
+            }
+            _ => vec![None; 4 + 256],
+        };
+        let preimage = UInt8::new_witness_vec(cs.clone(), &preimage)?;
+        let digest_bits = Sha256Gadget::digest(&preimage)?.0.to_bits_le()?;
+
+        let sequence_hash = self.sequence_hash.map(|h| halves(&h));
+        for (i, bits) in digest_bits.chunks(128).enumerate() {
+            let input = FpVar::new_input(cs.clone(), || {
+                sequence_hash
+                    .map(|h| h[i])
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            Boolean::le_bits_to_fp_var(bits)?.enforce_equal(&input)?;
+        }
+
+        let ciphertext_digest = self.ciphertext_digest.map(|d| halves(&d));
+        for i in 0..2 {
+            let input = FpVar::new_input(cs.clone(), || {
+                ciphertext_digest
+                    .map(|d| d[i])
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            // Groth16 does not bind inputs that appear in no constraint.
+            let square = FpVar::new_witness(cs.clone(), || input.value().map(|v| v.square()))?;
+            input.mul_equals(&input, &square)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn fq_to_bytes(f: &Fq) -> Vec<u8> {
+    f.into_bigint().to_bytes_le()
+}
+
+/// Encodes a point in the layout of NEAR's `alt_bn128` host functions:
+/// `x | y`, little-endian.
+fn g1_to_bytes(p: &G1Affine) -> Vec<u8> {
+    match p.xy() {
+        Some((x, y)) => [fq_to_bytes(x), fq_to_bytes(y)].concat(),
+        None => vec![0; 64],
+    }
+}
+
+/// Encodes a point in the layout of NEAR's `alt_bn128` host functions:
+/// `x.re | x.im | y.re | y.im`, little-endian.
+fn g2_to_bytes(p: &G2Affine) -> Vec<u8> {
+    match p.xy() {
+        Some((x, y)) => [
+            fq_to_bytes(&x.c0),
+            fq_to_bytes(&x.c1),
+            fq_to_bytes(&y.c0),
+            fq_to_bytes(&y.c1),
+        ]
+        .concat(),
+        None => vec![0; 128],
+    }
+}
+
+/// A Groth16 proof encoded for the message repository's `publish` methods.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PublishProof {
+    pub a: String,
+    pub b: String,
+    pub c: String,
+}
+
+/// Encodes a verifying key as the message repository's
+/// `publish_verifying_key` init argument.
+pub fn verifying_key_to_json(vk: &VerifyingKey<Bn254>) -> serde_json::Value {
+    json!({
+        "alpha_g1": BASE64.encode(&g1_to_bytes(&vk.alpha_g1)),
+        "beta_g2": BASE64.encode(&g2_to_bytes(&vk.beta_g2)),
+        "gamma_g2": BASE64.encode(&g2_to_bytes(&vk.gamma_g2)),
+        "delta_g2": BASE64.encode(&g2_to_bytes(&vk.delta_g2)),
+        "ic": vk
+            .gamma_abc_g1
+            .iter()
+            .map(|p| BASE64.encode(&g1_to_bytes(p)))
+            .collect::<Vec<_>>(),
+    })
+}
+
+pub struct Prover {
+    proving_key: ProvingKey<Bn254>,
+}
+
+impl Prover {
+    /// Runs the circuit-specific trusted setup. The verifying key configures
+    /// the message repository; the proving key is shared with every client.
+    pub fn setup<R: RngCore + CryptoRng>(
+        rng: &mut R,
+    ) -> anyhow::Result<(Self, VerifyingKey<Bn254>)> {
+        let (proving_key, verifying_key) =
+            Groth16::<Bn254>::circuit_specific_setup(SequenceHashCircuit::blank(), rng)?;
+
+        Ok((Self { proving_key }, verifying_key))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            proving_key: ProvingKey::deserialize_compressed(bytes)?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.proving_key.serialize_compressed(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn verifying_key(&self) -> &VerifyingKey<Bn254> {
+        &self.proving_key.vk
+    }
+
+    /// Proves that the channel produced the sequence hash for `nonce`, in a
+    /// proof that only verifies for `ciphertext`.
+    pub fn prove<R: RngCore + CryptoRng>(
+        &self,
+        channel: &impl Channel,
+        nonce: u32,
+        ciphertext: &[u8],
+        rng: &mut R,
+    ) -> anyhow::Result<PublishProof> {
+        let sequence_hash = *channel.sequence_hash(nonce);
+
+        let circuit = SequenceHashCircuit {
+            nonce: Some(nonce),
+            secret_identifier: Some(*channel.secret_identifier()),
+            sequence_hash: Some(sequence_hash),
+            ciphertext_digest: Some(Sha256::digest(ciphertext).into()),
+        };
+
+        let proof = Groth16::<Bn254>::prove(&self.proving_key, circuit, rng)?;
+
+        if !Groth16::<Bn254>::verify(
+            self.verifying_key(),
+            &public_inputs(&sequence_hash, ciphertext),
+            &proof,
+        )? {
+            bail!("Generated proof does not verify");
+        }
+
+        Ok(PublishProof {
+            a: BASE64.encode(&g1_to_bytes(&proof.a)),
+            b: BASE64.encode(&g2_to_bytes(&proof.b)),
+            c: BASE64.encode(&g1_to_bytes(&proof.c)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_relations::r1cs::ConstraintSystem;
+
+    use super::*;
+
+    struct TestChannel([u8; 256], [u8; 32]);
+
+    impl Channel for TestChannel {
+        fn secret_identifier(&self) -> &[u8; 256] {
+            &self.0
+        }
+
+        fn shared_secret(&self) -> &[u8; 32] {
+            &self.1
+        }
+    }
+
+    fn circuit(channel: &TestChannel, nonce: u32, ciphertext: &[u8]) -> SequenceHashCircuit {
+        SequenceHashCircuit {
+            nonce: Some(nonce),
+            secret_identifier: Some(*channel.secret_identifier()),
+            sequence_hash: Some(*channel.sequence_hash(nonce)),
+            ciphertext_digest: Some(Sha256::digest(ciphertext).into()),
+        }
+    }
+
+    #[test]
+    fn circuit_accepts_channel_sequence_hash() {
+        let channel = TestChannel([3u8; 256], [0u8; 32]);
+
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        circuit(&channel, 7, b"ciphertext")
+            .generate_constraints(cs.clone())
+            .unwrap();
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn circuit_rejects_other_sequence_hash() {
+        let channel = TestChannel([3u8; 256], [0u8; 32]);
+        let mut c = circuit(&channel, 7, b"ciphertext");
+        c.sequence_hash = Some(*channel.sequence_hash(8));
+
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        c.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn circuit_constrains_ciphertext_digest() {
+        let channel = TestChannel([3u8; 256], [0u8; 32]);
+
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        circuit(&channel, 7, b"ciphertext")
+            .generate_constraints(cs.clone())
+            .unwrap();
+        cs.finalize();
+        let matrices = cs.to_matrices().unwrap();
+
+        // instance variable 0 is the constant one, then the sequence hash
+        for input in 3..5 {
+            assert!(matrices
+                .a
+                .iter()
+                .chain(&matrices.b)
+                .flatten()
+                .any(|(_, variable)| *variable == input));
+        }
+    }
+
+    #[test]
+    #[ignore = "Slow without optimizations"]
+    fn proof_does_not_verify_for_other_ciphertext() {
+        let mut rng = rand::rngs::OsRng;
+        let (prover, verifying_key) = Prover::setup(&mut rng).unwrap();
+        let channel = TestChannel([3u8; 256], [0u8; 32]);
+
+        let proof = Groth16::<Bn254>::prove(
+            &prover.proving_key,
+            circuit(&channel, 7, b"ciphertext"),
+            &mut rng,
+        )
+        .unwrap();
+
+        let sequence_hash = channel.sequence_hash(7);
+        assert!(Groth16::<Bn254>::verify(
+            &verifying_key,
+            &public_inputs(&sequence_hash, b"ciphertext"),
+            &proof
+        )
+        .unwrap());
+        assert!(!Groth16::<Bn254>::verify(
+            &verifying_key,
+            &public_inputs(&sequence_hash, b"other ciphertext"),
+            &proof
+        )
+        .unwrap());
+    }
+
+    #[test]
+    #[ignore = "Slow without optimizations"]
+    fn prove_and_verify() {
+        let mut rng = rand::rngs::OsRng;
+        let (prover, verifying_key) = Prover::setup(&mut rng).unwrap();
+        let channel = TestChannel([3u8; 256], [0u8; 32]);
+
+        prover.prove(&channel, 7, b"ciphertext", &mut rng).unwrap();
+
+        let json = verifying_key_to_json(&verifying_key);
+        assert_eq!(json["ic"].as_array().unwrap().len(), 5);
+    }
+}
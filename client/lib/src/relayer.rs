@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use near_primitives::{
+    action::delegate::SignedDelegateAction, transaction::Action, types::AccountId,
+    views::FinalExecutionOutcomeView,
+};
+use serde::Deserialize;
+
+use crate::wallet::Wallet;
+
+/// Message repository methods that a relayer sponsors.
+pub const RELAYED_METHODS: &[&str] = &["publish", "publish_batch"];
+
+#[derive(Deserialize)]
+struct SponsoredArgs {
+    sponsor: Option<AccountId>,
+}
+
+/// Submits publishes signed by other accounts (see
+/// [`crate::message_repository::MessageRepository::sign_sponsored_publish`])
+/// as NEP-366 meta transactions. The relayer pays for their gas, and for
+/// their storage from its own storage balance on the message repository.
+pub struct Relayer {
+    wallet: Arc<Wallet>,
+    message_repository_id: AccountId,
+}
+
+impl Relayer {
+    pub fn new(wallet: Arc<Wallet>, message_repository_id: &'_ AccountId) -> Self {
+        Self {
+            wallet,
+            message_repository_id: message_repository_id.clone(),
+        }
+    }
+
+    pub fn account_id(&self) -> &AccountId {
+        &self.wallet.account_id
+    }
+
+    /// Checks that `signed_delegate_action` only publishes to the message
+    /// repository, attaches no deposit and names this relayer as the sponsor,
+    /// so that relaying it costs no more than gas and storage.
+    pub fn validate(&self, signed_delegate_action: &SignedDelegateAction) -> anyhow::Result<()> {
+        if !signed_delegate_action.verify() {
+            bail!("Invalid delegate action signature");
+        }
+
+        let delegate_action = &signed_delegate_action.delegate_action;
+        if delegate_action.receiver_id != self.message_repository_id {
+            bail!(
+                "Delegate action is for {}, not the message repository",
+                delegate_action.receiver_id,
+            );
+        }
+
+        for action in delegate_action.get_actions() {
+            let Action::FunctionCall(function_call) = action else {
+                bail!("Only function calls are relayed");
+            };
+            if !RELAYED_METHODS.contains(&function_call.method_name.as_str()) {
+                bail!("Method {} is not relayed", function_call.method_name);
+            }
+            if function_call.deposit != 0 {
+                bail!("Relayed function calls cannot attach a deposit");
+            }
+
+            let args: SponsoredArgs = serde_json::from_slice(&function_call.args)
+                .context("Invalid function call arguments")?;
+            if args.sponsor.as_ref() != Some(self.account_id()) {
+                bail!("Relayed function calls must be sponsored by the relayer");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates `signed_delegate_action` and submits it.
+    pub async fn relay(
+        &self,
+        signed_delegate_action: SignedDelegateAction,
+    ) -> anyhow::Result<FinalExecutionOutcomeView> {
+        self.validate(&signed_delegate_action)?;
+
+        self.wallet
+            .transact(
+                signed_delegate_action.delegate_action.sender_id.clone(),
+                vec![Action::Delegate(Box::new(signed_delegate_action))],
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_crypto::{InMemorySigner, KeyType};
+    use near_primitives::{
+        action::delegate::{DelegateAction, NonDelegateAction},
+        transaction::FunctionCallAction,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::wallet::sign_delegate_action;
+
+    fn relayer() -> Relayer {
+        let signer =
+            InMemorySigner::from_seed("relayer.near".parse().unwrap(), KeyType::ED25519, "relayer");
+        let wallet = Wallet::new(
+            "http://localhost:3030",
+            signer.account_id.clone(),
+            signer.into(),
+        );
+        Relayer::new(Arc::new(wallet), &"repository.near".parse().unwrap())
+    }
+
+    fn signed_publish(
+        receiver_id: &str,
+        args: serde_json::Value,
+        deposit: u128,
+    ) -> SignedDelegateAction {
+        let signer =
+            InMemorySigner::from_seed("alice.near".parse().unwrap(), KeyType::ED25519, "alice");
+        let action = Action::FunctionCall(Box::new(FunctionCallAction {
+            method_name: "publish".to_string(),
+            args: args.to_string().into_bytes(),
+            gas: 0,
+            deposit,
+        }));
+
+        sign_delegate_action(
+            &signer.clone().into(),
+            DelegateAction {
+                sender_id: signer.account_id.clone(),
+                receiver_id: receiver_id.parse().unwrap(),
+                actions: vec![NonDelegateAction::try_from(action).unwrap()],
+                nonce: 1,
+                max_block_height: 100,
+                public_key: signer.public_key(),
+            },
+        )
+    }
+
+    #[test]
+    fn accepts_sponsored_publish() {
+        let relayer = relayer();
+        let signed = signed_publish("repository.near", json!({ "sponsor": "relayer.near" }), 0);
+
+        relayer.validate(&signed).unwrap();
+    }
+
+    #[test]
+    fn rejects_unsponsored_calls() {
+        let relayer = relayer();
+
+        for signed in [
+            signed_publish("repository.near", json!({}), 0),
+            signed_publish("repository.near", json!({ "sponsor": "other.near" }), 0),
+            signed_publish("repository.near", json!({ "sponsor": "relayer.near" }), 1),
+            signed_publish("other.near", json!({ "sponsor": "relayer.near" }), 0),
+        ] {
+            assert!(relayer.validate(&signed).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_delegate_action() {
+        let relayer = relayer();
+        let mut signed = signed_publish("repository.near", json!({ "sponsor": "relayer.near" }), 0);
+        signed.delegate_action.nonce += 1;
+
+        assert!(relayer.validate(&signed).is_err());
+    }
+}
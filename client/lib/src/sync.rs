@@ -0,0 +1,222 @@
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{
+    channel::{CorrespondentId, SequenceHashProducer},
+    filter::NotificationFilter,
+    group::Group,
+    message_repository::{AggregatorCursor, EpochInfo, MessageRepository, ViewEncoding},
+    messenger::DecryptedMessage,
+};
+
+/// How many sequence numbers past a member's first missing message are
+/// tested, so that messages which landed in an earlier aggregator than the
+/// one before them are remembered until the gap is filled.
+const PENDING_LOOKAHEAD: u32 = 16;
+
+/// Most epochs fetched by a single sync, so that a client far behind catches
+/// up over several calls instead of one unbounded one.
+pub const MAX_EPOCHS_PER_SYNC: u64 = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncedMessage {
+    /// Index of the group (in the slice passed to [`NotificationSync::sync`])
+    /// that the message belongs to.
+    pub group_index: usize,
+    pub sender: CorrespondentId,
+    pub message: DecryptedMessage,
+}
+
+/// Receives messages for many groups at once by downloading the message
+/// repository's notification filters and testing pending sequence hashes
+/// locally. `get_message` is only called for probable hits, so the repository
+/// does not learn which sequence hashes the client is waiting for.
+///
+/// Repositories that seal their filters on fixed epochs are synced one whole
+/// epoch at a time, so the requests do not reveal when the client last synced
+/// beyond the epoch it falls into. A sync fetches at most
+/// [`MAX_EPOCHS_PER_SYNC`] epochs, see [`NotificationSync::is_caught_up`].
+///
+/// Neither the filters nor the messages are checked against the Merkle roots
+/// of their aggregators, so the RPC node can hide a message. Verifying takes
+/// roots pinned from `AggregatorSealed` events, which only the caller has, so
+/// callers that need it must opt in with
+/// [`MessageRepository::get_verified_message`].
+pub struct NotificationSync {
+    message_repository: Arc<MessageRepository>,
+    /// The aggregators that were still current at the last sync.
+    cursor: AggregatorCursor,
+    /// The epoch that the next sync starts from: the one still open at the
+    /// last sync, or the first one it did not reach.
+    next_epoch: Option<u64>,
+    caught_up: bool,
+    /// Sequence hashes found in filters behind a gap in a member's messages,
+    /// whose aggregators may not be fetched again.
+    pending_hits: HashSet<Vec<u8>>,
+}
+
+fn any_contains(filters: &[NotificationFilter], sequence_hash: &[u8]) -> bool {
+    filters.iter().any(|f| f.contains(sequence_hash))
+}
+
+impl NotificationSync {
+    pub fn new(message_repository: Arc<MessageRepository>) -> Self {
+        Self {
+            message_repository,
+            cursor: AggregatorCursor::new(),
+            next_epoch: None,
+            caught_up: false,
+            pending_hits: HashSet::new(),
+        }
+    }
+
+    /// Starts syncing from `epoch` rather than the repository's first one,
+    /// for a client that has nothing to receive from before it. Ignored by
+    /// repositories without epochs.
+    pub fn with_start_epoch(mut self, epoch: u64) -> Self {
+        self.next_epoch = Some(epoch);
+        self
+    }
+
+    /// Whether the last sync reached the current epoch. If not, the next sync
+    /// continues where it stopped.
+    pub fn is_caught_up(&self) -> bool {
+        self.caught_up
+    }
+
+    /// The aggregators that were still current at the last sync, which the
+    /// next sync starts from.
+    pub fn cursor(&self) -> &AggregatorCursor {
+        &self.cursor
+    }
+
+    /// Fetches the filters since the last sync, along with the cursor that
+    /// the next sync starts from.
+    async fn fetch_filters_since_last_sync(
+        &self,
+    ) -> anyhow::Result<(Vec<NotificationFilter>, AggregatorCursor)> {
+        let mut filters = vec![];
+        let mut aggregators = self
+            .message_repository
+            .aggregators_from(&self.cursor)
+            .with_encoding(ViewEncoding::Borsh);
+        while let Some(aggregator) = aggregators.next().await? {
+            filters.push(aggregator.filter);
+        }
+
+        Ok((filters, aggregators.cursor().clone()))
+    }
+
+    /// Fetches up to [`MAX_EPOCHS_PER_SYNC`] epochs from the one that the
+    /// next sync starts from, along with the epoch that the sync after it
+    /// starts from.
+    async fn fetch_epoch_filters(
+        &self,
+        epochs: EpochInfo,
+    ) -> anyhow::Result<(Vec<NotificationFilter>, u64)> {
+        let first_epoch = self.next_epoch.unwrap_or(epochs.first_epoch);
+        let last_epoch = epochs
+            .current_epoch
+            .min(first_epoch + MAX_EPOCHS_PER_SYNC - 1);
+
+        let mut filters = vec![];
+        for epoch in first_epoch..=last_epoch {
+            let mut aggregators = self
+                .message_repository
+                .epoch_aggregators(epoch)
+                .with_encoding(ViewEncoding::Borsh);
+            while let Some(aggregator) = aggregators.next().await? {
+                filters.push(aggregator.filter);
+            }
+        }
+
+        let next_epoch = if last_epoch < epochs.current_epoch {
+            last_epoch + 1
+        } else {
+            // the current epoch is fetched again, as it is still open
+            epochs.current_epoch.max(first_epoch)
+        };
+        Ok((filters, next_epoch))
+    }
+
+    /// Fetches every message that has arrived for `groups` since the last
+    /// sync, ordered by block timestamp. On a repository with epochs, a sync
+    /// covers at most [`MAX_EPOCHS_PER_SYNC`] of them.
+    pub async fn sync(&mut self, groups: &[&Group]) -> anyhow::Result<Vec<SyncedMessage>> {
+        let epochs = self.message_repository.get_epochs().await?;
+        let (filters, cursor, next_epoch) = match epochs {
+            Some(epochs) => {
+                let (filters, next_epoch) = self.fetch_epoch_filters(epochs).await?;
+                (filters, self.cursor.clone(), Some(next_epoch))
+            }
+            None => {
+                let (filters, cursor) = self.fetch_filters_since_last_sync().await?;
+                (filters, cursor, None)
+            }
+        };
+
+        let mut synced = vec![];
+
+        for (group_index, group) in groups.iter().enumerate() {
+            let sequence_hash = |message_index, correspondent_index| {
+                let nonce = group.get_nonce_for_message(message_index, correspondent_index);
+                group.sequence_hash(nonce).to_vec()
+            };
+            let mut starts = vec![];
+            let mut windows = vec![];
+
+            for correspondent_index in 0..group.member_count() {
+                let start = group.next_message_index_for(correspondent_index).await;
+                let probable_hits = (start..)
+                    .take_while(|&message_index| {
+                        let sequence_hash = sequence_hash(message_index, correspondent_index);
+                        self.pending_hits.contains(&sequence_hash)
+                            || any_contains(&filters, &sequence_hash)
+                    })
+                    .count() as u32;
+
+                starts.push(start);
+                if probable_hits > 0 {
+                    windows.push((correspondent_index, probable_hits));
+                }
+            }
+
+            if !windows.is_empty() {
+                // false positives simply come back empty, and deleted
+                // messages, which stay in their filters, are skipped past
+                for (correspondent_index, message) in group.receive_batch(&windows).await? {
+                    synced.push(SyncedMessage {
+                        group_index,
+                        sender: group.get_correspondent_id(correspondent_index).clone(),
+                        message,
+                    });
+                }
+            }
+
+            for (correspondent_index, start) in (0..group.member_count()).zip(starts) {
+                let next = group.next_message_index_for(correspondent_index).await;
+                for message_index in start..next {
+                    self.pending_hits
+                        .remove(&sequence_hash(message_index, correspondent_index));
+                }
+                // the first missing message may still be published into a
+                // later aggregator than the ones after it
+                for message_index in next + 1..=next + PENDING_LOOKAHEAD {
+                    let sequence_hash = sequence_hash(message_index, correspondent_index);
+                    if any_contains(&filters, &sequence_hash) {
+                        self.pending_hits.insert(sequence_hash);
+                    }
+                }
+            }
+        }
+
+        synced.sort_by_key(|s| s.message.block_timestamp_ms);
+
+        self.cursor = cursor;
+        self.caught_up = epochs
+            .zip(next_epoch)
+            .is_none_or(|(epochs, next_epoch)| next_epoch >= epochs.current_epoch);
+        self.next_epoch = next_epoch;
+
+        Ok(synced)
+    }
+}
@@ -1,17 +1,33 @@
 use anyhow::bail;
+use borsh::BorshDeserialize;
 use near_crypto::Signer;
 use near_jsonrpc_client::{methods, AsUrl, JsonRpcClient, MethodCallResult};
 use near_jsonrpc_primitives::types::query::QueryResponseKind;
 use near_primitives::{
+    action::delegate::{DelegateAction, NonDelegateAction, SignedDelegateAction},
     hash::CryptoHash,
-    transaction::{Action, Transaction, TransactionV0},
-    types::{AccountId, BlockReference, Finality},
+    serialize::dec_format,
+    transaction::{Action, FunctionCallAction, Transaction, TransactionV0},
+    types::{AccountId, Balance, BlockHeight, BlockReference, Finality, Nonce},
     views::{AccessKeyView, FinalExecutionOutcomeView, QueryRequest},
 };
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
 
 pub const ONE_TERAGAS: u64 = 10u64.pow(12);
 pub const ONE_NEAR: u128 = 10u128.pow(24);
+/// How many blocks a relayer has to submit a signed delegate action.
+pub const DELEGATE_ACTION_TTL_BLOCKS: BlockHeight = 120;
+
+/// An account's NEP-145 storage balance on a contract.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageBalance {
+    #[serde(with = "dec_format")]
+    pub total: Balance,
+    /// The part of `total` that is not paying for storage yet.
+    #[serde(with = "dec_format")]
+    pub available: Balance,
+}
 
 #[derive(Debug)]
 pub struct RpcClientWrapper {
@@ -27,7 +43,7 @@ impl RpcClientWrapper {
         &self,
         account_id: AccountId,
         public_key: near_crypto::PublicKey,
-    ) -> anyhow::Result<(u64, CryptoHash)> {
+    ) -> anyhow::Result<(Nonce, CryptoHash, BlockHeight)> {
         let response = self
             .client
             .call(methods::query::RpcQueryRequest {
@@ -41,7 +57,7 @@ impl RpcClientWrapper {
 
         match response.kind {
             QueryResponseKind::AccessKey(AccessKeyView { nonce, .. }) => {
-                Ok((nonce, response.block_hash))
+                Ok((nonce, response.block_hash, response.block_height))
             }
             _ => bail!("Invalid response from RPC"),
         }
@@ -55,6 +71,19 @@ impl RpcClientWrapper {
     }
 }
 
+/// Signs the NEP-461 hash of `delegate_action`, as relayers expect.
+pub fn sign_delegate_action(
+    signer: &Signer,
+    delegate_action: DelegateAction,
+) -> SignedDelegateAction {
+    let signature = signer.sign(delegate_action.get_nep461_hash().as_ref());
+
+    SignedDelegateAction {
+        delegate_action,
+        signature,
+    }
+}
+
 #[derive(Debug)]
 pub struct Wallet {
     rpc: RpcClientWrapper,
@@ -76,7 +105,7 @@ impl Wallet {
         receiver_id: AccountId,
         actions: Vec<Action>,
     ) -> anyhow::Result<FinalExecutionOutcomeView> {
-        let (current_nonce, block_hash) = self
+        let (current_nonce, block_hash, _) = self
             .rpc
             .sync_account_key(self.account_id.clone(), self.signer.public_key())
             .await?;
@@ -102,12 +131,63 @@ impl Wallet {
         Ok(result)
     }
 
+    /// Signs `actions` for a relayer to submit on this account's behalf in a
+    /// NEP-366 meta transaction. The relayer pays for the gas.
+    pub async fn sign_delegate_action(
+        &self,
+        receiver_id: AccountId,
+        actions: Vec<Action>,
+    ) -> anyhow::Result<SignedDelegateAction> {
+        let (current_nonce, _, block_height) = self
+            .rpc
+            .sync_account_key(self.account_id.clone(), self.signer.public_key())
+            .await?;
+
+        let delegate_action = DelegateAction {
+            sender_id: self.account_id.clone(),
+            receiver_id,
+            actions: actions
+                .into_iter()
+                .map(NonDelegateAction::try_from)
+                .collect::<Result<_, _>>()
+                .map_err(|_| anyhow::anyhow!("Delegate actions cannot be nested"))?,
+            nonce: current_nonce + 1,
+            max_block_height: block_height + DELEGATE_ACTION_TTL_BLOCKS,
+            public_key: self.signer.public_key(),
+        };
+
+        Ok(sign_delegate_action(&self.signer, delegate_action))
+    }
+
     pub async fn view<T: DeserializeOwned>(
         &self,
         account_id: AccountId,
         method_name: impl ToString,
         args: impl ToString,
     ) -> anyhow::Result<T> {
+        let result = self.view_raw(account_id, method_name, args).await?;
+
+        Ok(serde_json::from_slice(&result)?)
+    }
+
+    /// Calls a view method whose result is borsh-encoded.
+    pub async fn view_borsh<T: BorshDeserialize>(
+        &self,
+        account_id: AccountId,
+        method_name: impl ToString,
+        args: impl ToString,
+    ) -> anyhow::Result<T> {
+        let result = self.view_raw(account_id, method_name, args).await?;
+
+        Ok(T::try_from_slice(&result)?)
+    }
+
+    async fn view_raw(
+        &self,
+        account_id: AccountId,
+        method_name: impl ToString,
+        args: impl ToString,
+    ) -> anyhow::Result<Vec<u8>> {
         let response = self
             .rpc
             .send(methods::query::RpcQueryRequest {
@@ -125,8 +205,65 @@ impl Wallet {
             _ => bail!("Wrong response: {response:?}"),
         };
 
-        let response: T = serde_json::from_slice(&response.result)?;
+        Ok(response.result)
+    }
+
+    /// Tops up this account's storage balance on `contract_id`, registering
+    /// it if necessary.
+    pub async fn storage_deposit(
+        &self,
+        contract_id: AccountId,
+        amount: Balance,
+    ) -> anyhow::Result<()> {
+        self.transact(
+            contract_id,
+            vec![Action::FunctionCall(Box::new(FunctionCallAction {
+                method_name: "storage_deposit".to_string(),
+                args: json!({}).to_string().into_bytes(),
+                gas: 10 * ONE_TERAGAS,
+                deposit: amount,
+            }))],
+        )
+        .await?;
+
+        Ok(())
+    }
 
-        Ok(response)
+    /// Withdraws from this account's available storage balance on
+    /// `contract_id`, or all of it if `amount` is `None`.
+    pub async fn storage_withdraw(
+        &self,
+        contract_id: AccountId,
+        amount: Option<Balance>,
+    ) -> anyhow::Result<()> {
+        self.transact(
+            contract_id,
+            vec![Action::FunctionCall(Box::new(FunctionCallAction {
+                method_name: "storage_withdraw".to_string(),
+                args: json!({ "amount": amount.map(|a| a.to_string()) })
+                    .to_string()
+                    .into_bytes(),
+                gas: 10 * ONE_TERAGAS,
+                deposit: 1,
+            }))],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// The storage balance of `account_id` on `contract_id`, or `None` if the
+    /// account is not registered.
+    pub async fn storage_balance_of(
+        &self,
+        contract_id: AccountId,
+        account_id: &AccountId,
+    ) -> anyhow::Result<Option<StorageBalance>> {
+        self.view(
+            contract_id,
+            "storage_balance_of",
+            json!({ "account_id": account_id }),
+        )
+        .await
     }
 }
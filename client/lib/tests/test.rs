@@ -1,7 +1,18 @@
 use std::sync::Arc;
 
 use data_encoding::BASE64;
-use fc_client::{combined::CombinedMessageStream, messenger::Messenger, wallet::Wallet};
+use fc_client::{
+    combined::CombinedMessageStream,
+    events::MessageRepositoryEvent,
+    message_repository::{
+        AggregatorConfig, FilterKind, MessageRepository, OutgoingMessage, PublishError,
+        ViewEncoding,
+    },
+    messenger::Messenger,
+    relayer::Relayer,
+    sync::NotificationSync,
+    wallet::{Wallet, ONE_NEAR},
+};
 use near_workspaces::{network::Sandbox, Account, AccountId, Contract, Worker};
 use rand::rngs::OsRng;
 use serde_json::json;
@@ -9,12 +20,14 @@ use tokio::sync::OnceCell;
 
 enum ContractWasm {
     MessageRepository,
+    MessageDirectory,
     KeyRegistry,
 }
 
 impl ContractWasm {
     async fn load(&self) -> &'static [u8] {
         static MESSAGE_REPOSITORY_WASM: OnceCell<&'static [u8]> = OnceCell::const_new();
+        static MESSAGE_DIRECTORY_WASM: OnceCell<&'static [u8]> = OnceCell::const_new();
         static KEY_REGISTRY_WASM: OnceCell<&'static [u8]> = OnceCell::const_new();
 
         let (cell, path) = match self {
@@ -22,6 +35,9 @@ impl ContractWasm {
                 &MESSAGE_REPOSITORY_WASM,
                 "../../contract/message-repository/",
             ),
+            ContractWasm::MessageDirectory => {
+                (&MESSAGE_DIRECTORY_WASM, "../../contract/message-directory/")
+            }
             ContractWasm::KeyRegistry => (&KEY_REGISTRY_WASM, "../../contract/key-registry/"),
         };
 
@@ -51,6 +67,15 @@ async fn deploy_with_prefix_and_init(
     worker: &Worker<Sandbox>,
     prefix: &str,
     wasm: &[u8],
+) -> Contract {
+    deploy_with_prefix_and_init_args(worker, prefix, wasm, json!({})).await
+}
+
+async fn deploy_with_prefix_and_init_args(
+    worker: &Worker<Sandbox>,
+    prefix: &str,
+    wasm: &[u8],
+    args: serde_json::Value,
 ) -> Contract {
     let contract = prefixed_account(worker, prefix)
         .await
@@ -61,7 +86,7 @@ async fn deploy_with_prefix_and_init(
 
     contract
         .call("new")
-        .args_json(json!({}))
+        .args_json(args)
         .transact()
         .await
         .unwrap()
@@ -70,22 +95,26 @@ async fn deploy_with_prefix_and_init(
     contract
 }
 
-async fn create_messenger(
-    worker: &Worker<Sandbox>,
-    key_registry_contract_id: &AccountId,
-    message_repository_contract_id: &AccountId,
-    account: &Account,
-) -> Arc<Messenger> {
+fn create_wallet(worker: &Worker<Sandbox>, account: &Account) -> Arc<Wallet> {
     let signer = near_crypto::InMemorySigner::from_secret_key(
         account.id().clone(),
         account.secret_key().to_string().parse().unwrap(),
     );
 
-    let wallet = Arc::new(Wallet::new(
+    Arc::new(Wallet::new(
         worker.rpc_addr(),
         signer.account_id.clone(),
         signer.into(),
-    ));
+    ))
+}
+
+async fn create_messenger(
+    worker: &Worker<Sandbox>,
+    key_registry_contract_id: &AccountId,
+    message_repository_contract_id: &AccountId,
+    account: &Account,
+) -> Arc<Messenger> {
+    let wallet = create_wallet(worker, account);
 
     let messenger_key = x25519_dalek::StaticSecret::random_from_rng(OsRng);
 
@@ -177,6 +206,1262 @@ async fn happy_path() {
     );
 }
 
+struct Setup {
+    alice: Account,
+    bob: Account,
+    alice_messenger: Arc<Messenger>,
+    bob_messenger: Arc<Messenger>,
+}
+
+async fn setup() -> Setup {
+    let (worker, message_repository_wasm, key_registry_wasm) = tokio::join!(
+        async { near_workspaces::sandbox().await.unwrap() },
+        async { ContractWasm::MessageRepository.load().await },
+        async { ContractWasm::KeyRegistry.load().await },
+    );
+
+    let (message_repository_contract, key_registry_contract, alice, bob) = tokio::join!(
+        deploy_with_prefix_and_init(&worker, "msgrepo", message_repository_wasm),
+        deploy_with_prefix_and_init(&worker, "keyreg", key_registry_wasm),
+        prefixed_account(&worker, "alice"),
+        prefixed_account(&worker, "bob"),
+    );
+
+    println!("Creating messengers & syncing keys...");
+
+    let (alice_messenger, bob_messenger) = tokio::join!(
+        create_messenger(
+            &worker,
+            key_registry_contract.id(),
+            message_repository_contract.id(),
+            &alice
+        ),
+        create_messenger(
+            &worker,
+            key_registry_contract.id(),
+            message_repository_contract.id(),
+            &bob
+        ),
+    );
+
+    Setup {
+        alice,
+        bob,
+        alice_messenger,
+        bob_messenger,
+    }
+}
+
+#[tokio::test]
+async fn batch_send_and_receive() {
+    let Setup {
+        alice,
+        bob,
+        alice_messenger,
+        bob_messenger,
+        ..
+    } = setup().await;
+
+    let alice_group_with_bob = alice_messenger.direct_message(bob.id()).await.unwrap();
+    let bob_group_with_alice = bob_messenger.direct_message(alice.id()).await.unwrap();
+
+    alice_group_with_bob
+        .send_batch(["batch 1", "batch 2", "batch 3"])
+        .await
+        .unwrap();
+
+    let alice_index = bob_group_with_alice
+        .get_correspondent_index(&alice_messenger.public_key().to_bytes().into())
+        .unwrap();
+
+    let received = bob_group_with_alice
+        .receive_pending()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(from, m)| (from, String::from_utf8(m.message).unwrap()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        received,
+        vec![
+            (alice_index, "batch 1".to_string()),
+            (alice_index, "batch 2".to_string()),
+            (alice_index, "batch 3".to_string()),
+        ],
+    );
+
+    assert!(bob_group_with_alice
+        .receive_pending()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn delete_message() {
+    let Setup {
+        alice,
+        bob,
+        alice_messenger,
+        bob_messenger,
+        ..
+    } = setup().await;
+
+    let alice_group_with_bob = alice_messenger.direct_message(bob.id()).await.unwrap();
+    let bob_group_with_alice = bob_messenger.direct_message(alice.id()).await.unwrap();
+
+    // a sender learns its next sequence number by receiving its own messages
+    let kept = alice_group_with_bob.send_deletable("kept").await.unwrap();
+    alice_group_with_bob.receive_pending().await.unwrap();
+    let deleted = alice_group_with_bob
+        .send_deletable("deleted")
+        .await
+        .unwrap();
+
+    // deletion keys are derived per sender
+    assert_ne!(
+        bob_group_with_alice.deletion_key(kept[0]),
+        alice_group_with_bob.deletion_key(kept[0]),
+    );
+    bob_group_with_alice.delete(kept[0]).await.unwrap();
+    for sequence_number in deleted {
+        alice_group_with_bob.delete(sequence_number).await.unwrap();
+    }
+
+    let received = bob_group_with_alice.receive_pending().await.unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].1.message, b"kept");
+}
+
+#[tokio::test]
+async fn deleted_message_is_skipped() {
+    let Setup {
+        alice,
+        bob,
+        alice_messenger,
+        bob_messenger,
+        ..
+    } = setup().await;
+
+    let alice_group_with_bob = alice_messenger.direct_message(bob.id()).await.unwrap();
+
+    // a sender learns its next sequence number by receiving its own messages
+    alice_group_with_bob.send("first").await.unwrap();
+    alice_group_with_bob.receive_pending().await.unwrap();
+    let deleted = alice_group_with_bob
+        .send_deletable("deleted")
+        .await
+        .unwrap();
+    alice_group_with_bob.receive_pending().await.unwrap();
+    alice_group_with_bob.send("last").await.unwrap();
+    for sequence_number in deleted {
+        alice_group_with_bob.delete(sequence_number).await.unwrap();
+    }
+
+    let alice_index = alice_group_with_bob
+        .get_correspondent_index(&alice_messenger.public_key().to_bytes().into())
+        .unwrap();
+
+    let expected = vec!["first".to_string(), "last".to_string()];
+
+    let bob_group_with_alice = bob_messenger.direct_message(alice.id()).await.unwrap();
+    let received = bob_group_with_alice
+        .receive_pending()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(_, m)| String::from_utf8(m.message).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(received, expected);
+
+    let bob_group_with_alice = bob_messenger.direct_message(alice.id()).await.unwrap();
+    let mut received = vec![];
+    while let Some(m) = bob_group_with_alice
+        .receive_next_for(alice_index)
+        .await
+        .unwrap()
+    {
+        received.push(String::from_utf8(m.message).unwrap());
+    }
+    assert_eq!(received, expected);
+
+    let bob_group_with_alice = bob_messenger.direct_message(alice.id()).await.unwrap();
+    let received = NotificationSync::new(Arc::clone(&bob_messenger.message_repository))
+        .sync(&[&bob_group_with_alice])
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|synced| String::from_utf8(synced.message.message).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(received, expected);
+}
+
+#[tokio::test]
+async fn unreadable_message_is_skipped() {
+    let Setup {
+        alice,
+        bob,
+        alice_messenger,
+        bob_messenger,
+        ..
+    } = setup().await;
+
+    let alice_group_with_bob = alice_messenger.direct_message(bob.id()).await.unwrap();
+    let bob_group_with_alice = bob_messenger.direct_message(alice.id()).await.unwrap();
+
+    // bob takes the sequence hash of alice's first message
+    let alice_index = alice_group_with_bob
+        .get_correspondent_index(&alice_messenger.public_key().to_bytes().into())
+        .unwrap();
+    let (nonce, sequence_hash) = alice_group_with_bob
+        .next_sequence_hash_for(alice_index)
+        .await;
+    bob_messenger
+        .message_repository
+        .publish_message(&*sequence_hash, &[0; 64])
+        .await
+        .unwrap();
+
+    // alice skips past it
+    assert!(alice_group_with_bob
+        .receive_pending()
+        .await
+        .unwrap()
+        .is_empty());
+    alice_group_with_bob.send("after").await.unwrap();
+
+    let received = bob_group_with_alice.receive_pending().await.unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].1.message, b"after");
+
+    let rejected = bob_group_with_alice.take_rejected().await;
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].correspondent_index, alice_index);
+    assert_eq!(rejected[0].nonce, nonce);
+}
+
+#[tokio::test]
+async fn fragmented_message() {
+    let Setup {
+        alice,
+        bob,
+        alice_messenger,
+        bob_messenger,
+        ..
+    } = setup().await;
+
+    let alice_group_with_bob = alice_messenger.direct_message(bob.id()).await.unwrap();
+    let bob_group_with_alice = bob_messenger.direct_message(alice.id()).await.unwrap();
+
+    let file = (0..200_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+    let mut progress = vec![];
+    alice_group_with_bob
+        .send_with_progress(&file, |p| progress.push(p))
+        .await
+        .unwrap();
+    assert_eq!(progress.last().unwrap().completed, 4);
+    assert_eq!(progress.last().unwrap().total, 4);
+
+    let mut bob_group_receive = CombinedMessageStream::new(bob_group_with_alice.streams());
+    let (from, message) = bob_group_receive.next().await.unwrap().unwrap();
+
+    assert_eq!(&**from, alice_messenger.public_key().as_bytes());
+    assert_eq!(message.message, file);
+    assert!(bob_group_receive.progress().await.is_empty());
+}
+
+#[tokio::test]
+async fn payload_size_classes_enforced() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice) = tokio::join!(
+        deploy_with_prefix_and_init_args(
+            &worker,
+            "msgrepo",
+            message_repository_wasm,
+            json!({ "payload_size_classes": [256, 64] }),
+        ),
+        prefixed_account(&worker, "alice"),
+    );
+
+    let classes = message_repository_contract
+        .view("get_payload_size_classes")
+        .await
+        .unwrap()
+        .json::<Vec<u32>>()
+        .unwrap();
+    assert_eq!(classes, vec![64, 256]);
+
+    let publish = |sequence_hash: u8, len: usize| {
+        alice
+            .call(message_repository_contract.id(), "publish")
+            .args_json(json!({
+                "sequence_hash": BASE64.encode(&[sequence_hash; 32]),
+                "message": BASE64.encode(&vec![0u8; len]),
+            }))
+            .deposit(near_workspaces::types::NearToken::from_near(1))
+            .max_gas()
+            .transact()
+    };
+
+    assert!(publish(1, 10).await.unwrap().is_failure());
+    assert!(publish(2, 64).await.unwrap().is_success());
+    assert!(publish(3, 256).await.unwrap().is_success());
+}
+
+#[tokio::test]
+async fn commit_reveal_publishing() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice, mallory) = tokio::join!(
+        deploy_with_prefix_and_init_args(
+            &worker,
+            "msgrepo",
+            message_repository_wasm,
+            json!({ "require_commitments": true }),
+        ),
+        prefixed_account(&worker, "alice"),
+        prefixed_account(&worker, "mallory"),
+    );
+
+    let message = OutgoingMessage::new(&[1; 32], b"ciphertext");
+    let salt = [7u8; 32];
+    let commitment = BASE64.encode(&message.commitment(&salt));
+    let sequence_key = BASE64.encode(&message.sequence_key());
+
+    let reveal = |account: &Account| {
+        account
+            .call(message_repository_contract.id(), "reveal")
+            .args_json(json!({
+                "sequence_hash": BASE64.encode(&message.sequence_hash),
+                "message": BASE64.encode(&message.ciphertext),
+                "salt": BASE64.encode(&salt),
+            }))
+            .deposit(near_workspaces::types::NearToken::from_near(1))
+            .max_gas()
+            .transact()
+    };
+
+    let publish = alice
+        .call(message_repository_contract.id(), "publish")
+        .args_json(json!({
+            "sequence_hash": BASE64.encode(&message.sequence_hash),
+            "message": BASE64.encode(&message.ciphertext),
+        }))
+        .deposit(near_workspaces::types::NearToken::from_near(1))
+        .max_gas()
+        .transact()
+        .await
+        .unwrap();
+    assert!(publish.is_failure());
+
+    assert!(reveal(&alice).await.unwrap().is_failure());
+
+    let commit = |account: &Account, commitment: &str| {
+        account
+            .call(message_repository_contract.id(), "commit")
+            .args_json(json!({ "commitment": commitment, "sequence_key": sequence_key }))
+            .deposit(near_workspaces::types::NearToken::from_millinear(10))
+            .transact()
+    };
+
+    assert!(commit(&alice, &commitment).await.unwrap().is_success());
+
+    // commitments to other messages under the same sequence key don't block
+    // the reveal, and a copied commitment loses to the earliest one
+    let squatted = BASE64.encode(&message.commitment(&[8; 32]));
+    assert!(commit(&mallory, &squatted).await.unwrap().is_success());
+    assert!(commit(&mallory, &commitment).await.unwrap().is_success());
+    assert!(reveal(&mallory).await.unwrap().is_failure());
+    assert!(reveal(&alice).await.unwrap().is_success());
+
+    let remaining = message_repository_contract
+        .view("get_commitment")
+        .args_json(json!({ "commitment": commitment, "sequence_key": sequence_key }))
+        .await
+        .unwrap()
+        .json::<Option<serde_json::Value>>()
+        .unwrap();
+    assert_eq!(remaining, None);
+
+    let message_repository = MessageRepository::new(
+        create_wallet(&worker, &alice),
+        message_repository_contract.id(),
+    );
+    assert!(message_repository.requires_commitments().await.unwrap());
+
+    message_repository
+        .publish_message(&[2; 32], b"committed")
+        .await
+        .unwrap();
+    assert_eq!(
+        message_repository
+            .get_message(&[2; 32], ViewEncoding::Json)
+            .await
+            .unwrap()
+            .unwrap()
+            .message,
+        b"committed",
+    );
+}
+
+#[tokio::test]
+async fn aggregator_pages() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice) = tokio::join!(
+        deploy_with_prefix_and_init(&worker, "msgrepo", message_repository_wasm),
+        prefixed_account(&worker, "alice"),
+    );
+
+    let message_repository = MessageRepository::new(
+        create_wallet(&worker, &alice),
+        message_repository_contract.id(),
+    );
+
+    message_repository
+        .publish_message(&[1; 32], b"ciphertext")
+        .await
+        .unwrap();
+
+    let page = message_repository
+        .get_aggregators(u64::MAX, 0, Some(1), ViewEncoding::Json)
+        .await
+        .unwrap();
+    assert_eq!(page.next_index, None);
+    assert_eq!(page.aggregators.len(), 1);
+
+    let current = &page.aggregators[0];
+    assert_eq!(current.index, 0);
+    assert_eq!(current.end_block_timestamp_ms, None);
+    assert_eq!(current.item_count, 1);
+    assert!(current.filter.contains(&[1; 32]));
+
+    let mut aggregators = message_repository.aggregators_since(0);
+    assert_eq!(aggregators.next().await.unwrap().unwrap().index, 0);
+    assert!(aggregators.next().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn publish_quote_and_stats() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice) = tokio::join!(
+        deploy_with_prefix_and_init(&worker, "msgrepo", message_repository_wasm),
+        prefixed_account(&worker, "alice"),
+    );
+
+    message_repository_contract
+        .call("set_service_fee")
+        .args_json(json!({
+            "service_fee": {
+                "per_message": (ONE_NEAR / 1000).to_string(),
+                "per_byte": "1000",
+            },
+        }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let message_repository = MessageRepository::new(
+        create_wallet(&worker, &alice),
+        message_repository_contract.id(),
+    );
+
+    let message = OutgoingMessage::new(&[1; 32], b"ciphertext");
+    let quote = message_repository.quote_publish(&message).await.unwrap();
+    assert_eq!(
+        quote.total,
+        quote.storage_fee
+            + quote.rate_limit_storage_fee
+            + quote.aggregator_fee
+            + quote.publish_fee
+            + quote.service_fee,
+    );
+    assert!(quote.storage_fee > 0);
+    assert!(quote.aggregator_fee > 0);
+    assert_eq!(quote.service_fee, ONE_NEAR / 1000 + 1000 * 10);
+
+    // the quote is attached as the deposit, so the publish only lands if it
+    // covers the charge
+    message_repository.publish_outgoing(&message).await.unwrap();
+
+    let stats = message_repository.get_stats().await.unwrap();
+    assert_eq!(stats.message_count, Some(1));
+    assert_eq!(stats.aggregator_count, 1);
+    assert_eq!(stats.current_aggregator_item_count, 1);
+    assert!(stats.aggregator_storage_usage > 0);
+
+    let treasury: String = message_repository_contract
+        .view("get_treasury")
+        .await
+        .unwrap()
+        .json()
+        .unwrap();
+    assert_eq!(treasury, quote.service_fee.to_string());
+}
+
+#[tokio::test]
+async fn ordered_publish() {
+    let Setup {
+        alice,
+        bob,
+        alice_messenger,
+        bob_messenger,
+        ..
+    } = setup().await;
+
+    let message_repository = &alice_messenger.message_repository;
+
+    message_repository
+        .publish_outgoing(&OutgoingMessage::new(&[1; 32], b"first"))
+        .await
+        .unwrap();
+    message_repository
+        .publish_outgoing(&OutgoingMessage::new(&[2; 32], b"second").with_previous(&[1; 32]))
+        .await
+        .unwrap();
+    assert!(message_repository
+        .get_message(&[2; 32], ViewEncoding::Json)
+        .await
+        .unwrap()
+        .is_some());
+
+    // a message that follows one that was never published is rejected
+    message_repository
+        .publish_outgoing(&OutgoingMessage::new(&[4; 32], b"gap").with_previous(&[3; 32]))
+        .await
+        .unwrap();
+    assert!(message_repository
+        .get_message(&[4; 32], ViewEncoding::Json)
+        .await
+        .unwrap()
+        .is_none());
+
+    let alice_group_with_bob = alice_messenger
+        .direct_message(bob.id())
+        .await
+        .unwrap()
+        .with_ordered_publish();
+    let bob_group_with_alice = bob_messenger.direct_message(alice.id()).await.unwrap();
+
+    // each message of the batch follows the one before it
+    alice_group_with_bob
+        .send_batch(["ordered 1", "ordered 2", "ordered 3"])
+        .await
+        .unwrap();
+    alice_group_with_bob.send("ordered 4").await.unwrap();
+
+    let received = bob_group_with_alice
+        .receive_pending()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(_, m)| String::from_utf8(m.message).unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        received,
+        ["ordered 1", "ordered 2", "ordered 3", "ordered 4"]
+    );
+}
+
+#[tokio::test]
+async fn namespaced_repository() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice) = tokio::join!(
+        deploy_with_prefix_and_init(&worker, "msgrepo", message_repository_wasm),
+        prefixed_account(&worker, "alice"),
+    );
+
+    message_repository_contract
+        .call("create_namespace")
+        .args_json(json!({
+            "namespace": "staging",
+            "config": {
+                "aggregator_config": { "capacity": 2, "filter_kind": { "kind": "xor" } },
+                "payload_size_classes": [],
+                "publish_fee": (ONE_NEAR / 1000).to_string(),
+            },
+        }))
+        .deposit(near_workspaces::types::NearToken::from_near(1))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let wallet = create_wallet(&worker, &alice);
+    let default_namespace =
+        MessageRepository::new(Arc::clone(&wallet), message_repository_contract.id());
+    let staging =
+        MessageRepository::new(wallet, message_repository_contract.id()).with_namespace("staging");
+
+    let config = staging.get_namespace_config().await.unwrap();
+    assert_eq!(config.aggregator_config.capacity, 2);
+    assert_eq!(config.publish_fee, ONE_NEAR / 1000);
+    let quote = staging
+        .quote_publish(&OutgoingMessage::new(&[1; 32], b"staging"))
+        .await
+        .unwrap();
+    assert_eq!(quote.publish_fee, ONE_NEAR / 1000);
+
+    default_namespace
+        .publish_message(&[1; 32], b"default")
+        .await
+        .unwrap();
+    staging
+        .publish_messages(
+            &(1..=3)
+                .map(|i| OutgoingMessage::new(&[i; 32], b"staging"))
+                .collect::<Vec<_>>(),
+        )
+        .await
+        .unwrap();
+
+    for (message_repository, expected) in [
+        (&default_namespace, &b"default"[..]),
+        (&staging, &b"staging"[..]),
+    ] {
+        assert_eq!(
+            message_repository
+                .get_message(&[1; 32], ViewEncoding::Borsh)
+                .await
+                .unwrap()
+                .unwrap()
+                .message,
+            expected,
+        );
+    }
+    assert_eq!(
+        default_namespace
+            .get_message(&[2; 32], ViewEncoding::Json)
+            .await
+            .unwrap(),
+        None,
+    );
+
+    assert_eq!(
+        default_namespace.get_stats().await.unwrap().message_count,
+        Some(1)
+    );
+    let stats = staging.get_stats().await.unwrap();
+    assert_eq!(stats.message_count, Some(3));
+    assert_eq!(stats.aggregator_count, 2);
+
+    let mut aggregators = staging.aggregators_since(0);
+    let mut item_counts = vec![];
+    while let Some(aggregator) = aggregators.next().await.unwrap() {
+        item_counts.push(aggregator.item_count);
+    }
+    assert_eq!(item_counts, vec![2, 1]);
+
+    assert!(staging.delete_message(&[1; 32], b"key").await.is_err());
+}
+
+#[tokio::test]
+async fn private_repository() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice, bob) = tokio::join!(
+        deploy_with_prefix_and_init(&worker, "msgrepo", message_repository_wasm),
+        prefixed_account(&worker, "alice"),
+        prefixed_account(&worker, "bob"),
+    );
+
+    message_repository_contract
+        .call("set_access_mode")
+        .args_json(json!({ "access_mode": "private" }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    message_repository_contract
+        .call("grant_role")
+        .args_json(json!({ "account_id": alice.id(), "role": "publisher" }))
+        .deposit(near_workspaces::types::NearToken::from_near(1))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let alice_repository = MessageRepository::new(
+        create_wallet(&worker, &alice),
+        message_repository_contract.id(),
+    );
+    let bob_repository = MessageRepository::new(
+        create_wallet(&worker, &bob),
+        message_repository_contract.id(),
+    );
+
+    assert!(alice_repository.can_publish().await.unwrap());
+    assert!(!bob_repository.can_publish().await.unwrap());
+
+    alice_repository
+        .publish_message(&[1; 32], b"allowed")
+        .await
+        .unwrap();
+
+    let error = bob_repository
+        .publish_message(&[2; 32], b"rejected")
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<PublishError>(),
+        Some(&PublishError::NotAllowed {
+            account_id: bob.id().clone(),
+            repository_id: message_repository_contract.id().clone(),
+        }),
+    );
+
+    // reads stay public
+    assert!(bob_repository
+        .get_message(&[1; 32], ViewEncoding::Json)
+        .await
+        .unwrap()
+        .is_some());
+    assert!(bob_repository
+        .get_message(&[2; 32], ViewEncoding::Json)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn epoch_aggregators() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice) = tokio::join!(
+        deploy_with_prefix_and_init_args(
+            &worker,
+            "msgrepo",
+            message_repository_wasm,
+            json!({
+                "epoch_duration_ms": 1000,
+                "aggregator_config": {
+                    "capacity": 255,
+                    "fingerprint_bits": 16,
+                    "filter_kind": { "kind": "xor" },
+                },
+            }),
+        ),
+        prefixed_account(&worker, "alice"),
+    );
+
+    let message_repository = MessageRepository::new(
+        create_wallet(&worker, &alice),
+        message_repository_contract.id(),
+    );
+
+    assert!(message_repository
+        .get_aggregators(0, 0, None, ViewEncoding::Json)
+        .await
+        .is_err());
+    assert_eq!(
+        message_repository.get_config().await.unwrap(),
+        AggregatorConfig {
+            capacity: 255,
+            fingerprint_bits: 16,
+            filter_kind: FilterKind::Xor,
+        },
+    );
+
+    let publish_in_epoch = |sequence_hash: [u8; 32]| {
+        let message_repository = &message_repository;
+        async move {
+            message_repository
+                .publish_message(&sequence_hash, b"ciphertext")
+                .await
+                .unwrap();
+            let message = message_repository
+                .get_message(&sequence_hash, ViewEncoding::Borsh)
+                .await
+                .unwrap()
+                .unwrap();
+            message.block_timestamp_ms / 1000
+        }
+    };
+
+    let first_epoch = publish_in_epoch([1; 32]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(2000)).await;
+    let second_epoch = publish_in_epoch([2; 32]).await;
+    assert!(second_epoch > first_epoch);
+
+    let epochs = message_repository.get_epochs().await.unwrap().unwrap();
+    assert!(epochs.first_epoch <= first_epoch);
+    assert!(epochs.current_epoch >= second_epoch);
+
+    let sealed = message_repository
+        .get_epoch_aggregators(first_epoch, 0, None, ViewEncoding::Borsh)
+        .await
+        .unwrap();
+    assert_eq!(sealed.next_index, None);
+    assert_eq!(sealed.aggregators.len(), 1);
+    assert_eq!(
+        sealed.aggregators[0].end_block_timestamp_ms,
+        Some((first_epoch + 1) * 1000 - 1),
+    );
+    assert_eq!(
+        sealed.aggregators[0].filter_parameters.kind,
+        FilterKind::Xor
+    );
+    assert_eq!(sealed.aggregators[0].filter_parameters.capacity, 255);
+    assert!(sealed.aggregators[0].filter.contains(&[1; 32]));
+    assert!(!sealed.aggregators[0].filter.contains(&[2; 32]));
+
+    let mut current = message_repository.epoch_aggregators(second_epoch);
+    let aggregator = current.next().await.unwrap().unwrap();
+    assert_eq!(aggregator.end_block_timestamp_ms, None);
+    assert!(aggregator.filter.contains(&[2; 32]));
+    assert!(current.next().await.unwrap().is_none());
+
+    let sealed_index = sealed.aggregators[0].index;
+    assert_eq!(
+        message_repository
+            .get_merkle_root(aggregator.index)
+            .await
+            .unwrap(),
+        None,
+    );
+    let merkle_root = message_repository
+        .get_merkle_root(sealed_index)
+        .await
+        .unwrap()
+        .unwrap();
+    let verified = message_repository
+        .get_verified_message(&[1; 32], sealed_index, &merkle_root, ViewEncoding::Json)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(verified.message, b"ciphertext");
+    assert_eq!(
+        message_repository
+            .get_verified_message(&[2; 32], sealed_index, &merkle_root, ViewEncoding::Borsh)
+            .await
+            .unwrap(),
+        None,
+    );
+    assert!(message_repository
+        .get_verified_message(&[1; 32], sealed_index, &[0; 32], ViewEncoding::Json)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn prepaid_storage() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice) = tokio::join!(
+        deploy_with_prefix_and_init(&worker, "msgrepo", message_repository_wasm),
+        prefixed_account(&worker, "alice"),
+    );
+
+    let wallet = create_wallet(&worker, &alice);
+    let contract_id = message_repository_contract.id().clone();
+
+    assert_eq!(
+        wallet
+            .storage_balance_of(contract_id.clone(), alice.id())
+            .await
+            .unwrap(),
+        None,
+    );
+
+    wallet
+        .storage_deposit(contract_id.clone(), ONE_NEAR)
+        .await
+        .unwrap();
+    let deposited = wallet
+        .storage_balance_of(contract_id.clone(), alice.id())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(deposited.total, ONE_NEAR);
+    assert_eq!(deposited.available, ONE_NEAR);
+
+    let account_balance_before = alice.view_account().await.unwrap().balance;
+
+    let message_repository = MessageRepository::new(Arc::clone(&wallet), &contract_id);
+    message_repository
+        .publish_message(&[1; 32], b"prepaid")
+        .await
+        .unwrap();
+    assert!(message_repository
+        .get_message(&[1; 32], ViewEncoding::Json)
+        .await
+        .unwrap()
+        .is_some());
+
+    let charged = wallet
+        .storage_balance_of(contract_id.clone(), alice.id())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(charged.total, ONE_NEAR);
+    assert!(charged.available < ONE_NEAR);
+
+    // only gas was paid from the account itself
+    let account_balance_after = alice.view_account().await.unwrap().balance;
+    assert!(
+        account_balance_before.saturating_sub(account_balance_after)
+            < near_workspaces::types::NearToken::from_millinear(10)
+    );
+
+    wallet
+        .storage_withdraw(contract_id.clone(), Some(charged.available / 2))
+        .await
+        .unwrap();
+    let withdrawn = wallet
+        .storage_balance_of(contract_id.clone(), alice.id())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(withdrawn.total, ONE_NEAR - charged.available / 2);
+    assert_eq!(
+        withdrawn.available,
+        charged.available - charged.available / 2
+    );
+
+    // a balance that no longer covers a publish is not spent from
+    wallet
+        .storage_withdraw(contract_id.clone(), Some(withdrawn.available))
+        .await
+        .unwrap();
+    message_repository
+        .publish_message(&[2; 32], b"paid by deposit")
+        .await
+        .unwrap();
+    assert!(message_repository
+        .get_message(&[2; 32], ViewEncoding::Json)
+        .await
+        .unwrap()
+        .is_some());
+    let emptied = wallet
+        .storage_balance_of(contract_id, alice.id())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(emptied.available, 0);
+}
+
+#[tokio::test]
+async fn owner_upgrade() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice) = tokio::join!(
+        deploy_with_prefix_and_init(&worker, "msgrepo", message_repository_wasm),
+        prefixed_account(&worker, "alice"),
+    );
+
+    let message_repository = MessageRepository::new(
+        create_wallet(&worker, &alice),
+        message_repository_contract.id(),
+    );
+    message_repository
+        .publish_message(&[1; 32], b"ciphertext")
+        .await
+        .unwrap();
+
+    let owner: Option<AccountId> = message_repository_contract
+        .view("own_get_owner")
+        .await
+        .unwrap()
+        .json()
+        .unwrap();
+    assert_eq!(owner.as_ref(), Some(message_repository_contract.id()));
+
+    let not_owner = alice
+        .call(message_repository_contract.id(), "upgrade")
+        .args_borsh(message_repository_wasm.to_vec())
+        .max_gas()
+        .transact()
+        .await
+        .unwrap();
+    assert!(not_owner.is_failure());
+
+    message_repository_contract
+        .call("upgrade")
+        .args_borsh(message_repository_wasm.to_vec())
+        .max_gas()
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let schema_version: u32 = message_repository_contract
+        .view("get_schema_version")
+        .await
+        .unwrap()
+        .json()
+        .unwrap();
+    assert_eq!(schema_version, 12);
+    assert_eq!(
+        message_repository
+            .get_message(&[1; 32], ViewEncoding::Json)
+            .await
+            .unwrap()
+            .unwrap()
+            .message,
+        b"ciphertext",
+    );
+}
+
+#[tokio::test]
+async fn relayed_publish() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice, relayer) = tokio::join!(
+        deploy_with_prefix_and_init(&worker, "msgrepo", message_repository_wasm),
+        prefixed_account(&worker, "alice"),
+        prefixed_account(&worker, "relayer"),
+    );
+    let contract_id = message_repository_contract.id();
+
+    let relayer_wallet = create_wallet(&worker, &relayer);
+    relayer_wallet
+        .storage_deposit(contract_id.clone(), ONE_NEAR)
+        .await
+        .unwrap();
+    let relayer = Relayer::new(relayer_wallet.clone(), contract_id);
+
+    let alice_wallet = create_wallet(&worker, &alice);
+    let message_repository = MessageRepository::new(alice_wallet.clone(), contract_id);
+    let signed_delegate_action = message_repository
+        .sign_sponsored_publish(
+            &[OutgoingMessage::new(&[1; 32], b"relayed")],
+            relayer.account_id(),
+        )
+        .await
+        .unwrap();
+    let outcome = relayer.relay(signed_delegate_action).await.unwrap();
+
+    let events = MessageRepositoryEvent::from_outcome(&outcome, contract_id);
+    assert!(matches!(
+        &events[..],
+        [MessageRepositoryEvent::Publish {
+            sequence_hash,
+            message_length: 7,
+            aggregator_index: 0,
+            ..
+        }] if sequence_hash == &[1; 32],
+    ));
+
+    assert_eq!(
+        message_repository
+            .get_message(&[1; 32], ViewEncoding::Json)
+            .await
+            .unwrap()
+            .unwrap()
+            .message,
+        b"relayed",
+    );
+    assert_eq!(
+        alice_wallet
+            .storage_balance_of(contract_id.clone(), alice.id())
+            .await
+            .unwrap(),
+        None,
+    );
+    let relayer_balance = relayer_wallet
+        .storage_balance_of(contract_id.clone(), relayer.account_id())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(relayer_balance.available < ONE_NEAR);
+}
+
+#[tokio::test]
+async fn pow_publish() {
+    let (worker, message_repository_wasm) =
+        tokio::join!(async { near_workspaces::sandbox().await.unwrap() }, async {
+            ContractWasm::MessageRepository.load().await
+        },);
+
+    let (message_repository_contract, alice) = tokio::join!(
+        deploy_with_prefix_and_init(&worker, "msgrepo", message_repository_wasm),
+        prefixed_account(&worker, "alice"),
+    );
+    let contract_id = message_repository_contract.id();
+
+    message_repository_contract
+        .call("set_pow_difficulty")
+        .args_json(json!({ "difficulty": 8 }))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    message_repository_contract
+        .as_account()
+        .call(contract_id, "fund_sponsor_pool")
+        .deposit(near_workspaces::types::NearToken::from_near(1))
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let alice_wallet = create_wallet(&worker, &alice);
+    let message_repository =
+        MessageRepository::new(alice_wallet.clone(), contract_id).with_proof_of_work();
+    assert_eq!(
+        message_repository
+            .get_pow_challenge()
+            .await
+            .unwrap()
+            .unwrap()
+            .difficulty,
+        8,
+    );
+    message_repository
+        .publish_message(&[1; 32], b"proof of work")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        message_repository
+            .get_message(&[1; 32], ViewEncoding::Json)
+            .await
+            .unwrap()
+            .unwrap()
+            .message,
+        b"proof of work",
+    );
+    assert_eq!(
+        alice_wallet
+            .storage_balance_of(contract_id.clone(), alice.id())
+            .await
+            .unwrap(),
+        None,
+    );
+}
+
+#[tokio::test]
+async fn sharded_repository() {
+    let (worker, message_repository_wasm, message_directory_wasm) = tokio::join!(
+        async { near_workspaces::sandbox().await.unwrap() },
+        ContractWasm::MessageRepository.load(),
+        ContractWasm::MessageDirectory.load(),
+    );
+
+    let (shard_0, shard_1, alice) = tokio::join!(
+        deploy_with_prefix_and_init(&worker, "shard0", message_repository_wasm),
+        deploy_with_prefix_and_init(&worker, "shard1", message_repository_wasm),
+        prefixed_account(&worker, "alice"),
+    );
+    let ranges = [
+        json!({ "start": 0, "end": 0x7fff_ffffu32 }),
+        json!({ "start": 0x8000_0000u32, "end": u32::MAX }),
+    ];
+    for (shard, range) in [&shard_0, &shard_1].into_iter().zip(&ranges) {
+        shard
+            .call("set_shard_range")
+            .args_json(json!({ "shard_range": range }))
+            .transact()
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    let directory = deploy_with_prefix_and_init_args(
+        &worker,
+        "directory",
+        message_directory_wasm,
+        json!({
+            "shards": [
+                { "range": ranges[0], "account_id": shard_0.id() },
+                { "range": ranges[1], "account_id": shard_1.id() },
+            ],
+        }),
+    )
+    .await;
+
+    let alice_wallet = create_wallet(&worker, &alice);
+    let message_repository =
+        MessageRepository::from_directory(alice_wallet.clone(), directory.id())
+            .await
+            .unwrap();
+    message_repository
+        .publish_messages(&[
+            OutgoingMessage::new(&[0x10; 32], b"low"),
+            OutgoingMessage::new(&[0xf0; 32], b"high"),
+        ])
+        .await
+        .unwrap();
+
+    let low = MessageRepository::new(alice_wallet.clone(), shard_0.id());
+    let high = MessageRepository::new(alice_wallet.clone(), shard_1.id());
+    assert!(low
+        .get_message(&[0x10; 32], ViewEncoding::Json)
+        .await
+        .unwrap()
+        .is_some());
+    assert!(high
+        .get_message(&[0xf0; 32], ViewEncoding::Borsh)
+        .await
+        .unwrap()
+        .is_some());
+    assert!(low
+        .get_message(&[0xf0; 32], ViewEncoding::Borsh)
+        .await
+        .unwrap()
+        .is_none());
+
+    let messages = message_repository
+        .get_messages(
+            &[&[0xf0; 32], &[0x10; 32], &[0x20; 32]],
+            ViewEncoding::Borsh,
+        )
+        .await
+        .unwrap();
+    assert_eq!(messages[0].as_ref().unwrap().message, b"high");
+    assert_eq!(messages[1].as_ref().unwrap().message, b"low");
+    assert!(messages[2].is_none());
+
+    let mut aggregators = message_repository.aggregators_since(0);
+    let mut count = 0;
+    while aggregators.next().await.unwrap().is_some() {
+        count += 1;
+    }
+    assert_eq!(count, 2);
+
+    // a shard rejects sequence hashes outside of its range
+    assert!(high
+        .publish_message(&[0x20; 32], b"misrouted")
+        .await
+        .is_err());
+}
+
 struct KeyRegistry<'a> {
     contract: &'a Contract,
 }
@@ -1,64 +1,144 @@
 use near_sdk::{
-    collections::LookupMap, env, json_types::Base64VecU8, near, require, AccountId,
-    BorshStorageKey, PanicOnDefault, PromiseOrValue,
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::LookupMap,
+    env,
+    json_types::Base64VecU8,
+    near, require, AccountId, BorshStorageKey, IntoStorageKey, PanicOnDefault, PromiseOrValue,
 };
-use near_sdk_contract_tools::{event, standard::nep297::Event};
+use near_sdk_contract_tools::{
+    event,
+    owner::Owner,
+    standard::{
+        nep145::{Nep145Controller, StorageBalanceBounds},
+        nep297::Event,
+    },
+    Nep145, Owner, Upgrade,
+};
+
+mod migration;
+
+/// Bumped whenever the layout of the contract state changes, with a matching
+/// arm in [`PublicKeyManagerContract::migrate`].
+const SCHEMA_VERSION: u32 = 1;
+
+/// Covers the storage of an account's NEP-145 balance record.
+const STORAGE_REGISTRATION_BYTES: u128 = 250;
 
 #[derive(Debug, BorshStorageKey)]
 #[near]
 enum StorageKey {
     KeyMap,
+    SchemaVersion,
+}
+
+fn get_lazy<T: BorshDeserialize>(key: impl IntoStorageKey) -> Option<T> {
+    let bytes = env::storage_read(&key.into_storage_key())?;
+    borsh::from_slice(&bytes).ok()
+}
+
+fn write<T: BorshSerialize>(key: impl IntoStorageKey, value: T) {
+    env::storage_write(&key.into_storage_key(), &borsh::to_vec(&value).unwrap());
 }
 
 #[event(
     standard = "x-public-key-manager",
-    version = "1.0.0",
+    version = "1.1.0",
     serde = "near_sdk::serde"
 )]
 enum PublicKeyManagerEvent {
     PublicKeyChange {
         account_id: AccountId,
         public_key: Option<Base64VecU8>,
+        previous_public_key: Option<Base64VecU8>,
     },
 }
 
 #[near(contract_state)]
-#[derive(PanicOnDefault)]
+#[derive(PanicOnDefault, Nep145, Owner, Upgrade)]
+#[upgrade(hook = "owner", serializer = "borsh")]
 pub struct PublicKeyManagerContract {
     key_map: LookupMap<AccountId, Base64VecU8>,
 }
 
 #[near]
 impl PublicKeyManagerContract {
+    /// `owner_id` defaults to the account that initializes the contract.
     #[init]
-    pub fn new() -> Self {
-        Self {
+    pub fn new(owner_id: Option<AccountId>) -> Self {
+        let mut contract = Self {
             key_map: LookupMap::new(StorageKey::KeyMap),
-        }
+        };
+
+        contract.set_storage_balance_bounds(&StorageBalanceBounds {
+            min: env::storage_byte_cost().saturating_mul(STORAGE_REGISTRATION_BYTES),
+            max: None,
+        });
+        Owner::init(
+            &mut contract,
+            &owner_id.unwrap_or_else(env::predecessor_account_id),
+        );
+        write(StorageKey::SchemaVersion, SCHEMA_VERSION);
+
+        contract
+    }
+
+    /// Brings state written by any earlier schema version up to
+    /// [`SCHEMA_VERSION`]. [`PublicKeyManagerContract::upgrade`] calls this
+    /// once the new code is deployed; after deploying with a full access key,
+    /// call it in the same transaction.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        // kept outside of the contract struct so that it can be read before
+        // knowing how to deserialize the struct
+        let schema_version = get_lazy::<u32>(StorageKey::SchemaVersion).unwrap_or(0);
+        require!(
+            schema_version <= SCHEMA_VERSION,
+            "State was written by a newer schema version."
+        );
+
+        let contract = match schema_version {
+            0 => migration::from_v0(),
+            _ => env::state_read().unwrap_or_else(|| env::panic_str("Failed to read state.")),
+        };
+        write(StorageKey::SchemaVersion, SCHEMA_VERSION);
+
+        contract
+    }
+
+    pub fn get_schema_version(&self) -> u32 {
+        get_lazy(StorageKey::SchemaVersion).unwrap_or(0)
     }
 
     pub fn get_public_key(&self, account_id: AccountId) -> Option<Base64VecU8> {
         self.key_map.get(&account_id)
     }
 
+    /// Pays for storage from the attached deposit, refunding the rest, or
+    /// from the caller's storage balance if nothing is attached.
     #[payable]
     pub fn set_public_key(&mut self, public_key: Option<Base64VecU8>) -> PromiseOrValue<()> {
-        require!(!env::attached_deposit().is_zero(), "Requires deposit");
         let initial_storage_usage = env::storage_usage();
 
         let predecessor = env::predecessor_account_id();
-        if let Some(public_key) = public_key.as_ref() {
-            self.key_map.insert(&predecessor, public_key);
+        let previous_public_key = if let Some(public_key) = public_key.as_ref() {
+            self.key_map.insert(&predecessor, public_key)
         } else {
-            self.key_map.remove(&predecessor);
-        }
+            self.key_map.remove(&predecessor)
+        };
 
         PublicKeyManagerEvent::PublicKeyChange {
-            account_id: env::predecessor_account_id(),
+            account_id: predecessor,
             public_key,
+            previous_public_key,
         }
         .emit();
 
+        if env::attached_deposit().is_zero() {
+            self.settle_storage_balance(initial_storage_usage);
+            return PromiseOrValue::Value(());
+        }
+
         if let Some(p) =
             near_sdk_contract_tools::utils::apply_storage_fee_and_refund(initial_storage_usage, 0)
         {
@@ -67,4 +147,30 @@ impl PublicKeyManagerContract {
             PromiseOrValue::Value(())
         }
     }
+
+    /// Settles the predecessor's storage change since `initial_storage_usage`
+    /// against their NEP-145 balance. Freed storage unlocks at most what is
+    /// locked, since it may have been paid for with an attached deposit.
+    fn settle_storage_balance(&mut self, initial_storage_usage: u64) {
+        let predecessor = env::predecessor_account_id();
+        let final_storage_usage = env::storage_usage();
+
+        if final_storage_usage < initial_storage_usage {
+            let Ok(balance) = self.get_storage_balance(&predecessor) else {
+                return;
+            };
+            let credit = env::storage_byte_cost()
+                .saturating_mul((initial_storage_usage - final_storage_usage) as u128)
+                .min(balance.total.saturating_sub(balance.available));
+            self.unlock_storage(&predecessor, credit)
+                .unwrap_or_else(|e| env::panic_str(&format!("Storage accounting error: {e}")));
+        } else {
+            let fee = env::storage_byte_cost()
+                .saturating_mul((final_storage_usage - initial_storage_usage) as u128);
+            if !fee.is_zero() {
+                self.lock_storage(&predecessor, fee)
+                    .unwrap_or_else(|e| env::panic_str(&format!("Storage accounting error: {e}")));
+            }
+        }
+    }
 }
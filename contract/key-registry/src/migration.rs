@@ -0,0 +1,92 @@
+//! State layouts from earlier schema versions, and their conversions to the
+//! current [`PublicKeyManagerContract`].
+
+use near_sdk::{collections::LookupMap, env, json_types::Base64VecU8, near, AccountId};
+use near_sdk_contract_tools::{
+    owner::Owner,
+    standard::nep145::{Nep145Controller, StorageBalanceBounds},
+};
+
+use crate::{PublicKeyManagerContract, STORAGE_REGISTRATION_BYTES};
+
+/// The layout before the schema version was recorded, which had no owner.
+#[near]
+pub struct PublicKeyManagerContractV0 {
+    pub key_map: LookupMap<AccountId, Base64VecU8>,
+}
+
+/// The contract account itself becomes the owner, since it is the only
+/// account that could have deployed the new code. Storage registration costs
+/// the same as in a new registry.
+pub fn from_v0() -> PublicKeyManagerContract {
+    let v0: PublicKeyManagerContractV0 =
+        env::state_read().unwrap_or_else(|| env::panic_str("Failed to read version 0 state."));
+
+    let mut contract = PublicKeyManagerContract {
+        key_map: v0.key_map,
+    };
+    Owner::init(&mut contract, &env::current_account_id());
+    contract.set_storage_balance_bounds(&StorageBalanceBounds {
+        min: env::storage_byte_cost().saturating_mul(STORAGE_REGISTRATION_BYTES),
+        max: None,
+    });
+
+    contract
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::{test_utils::VMContextBuilder, testing_env};
+    use near_sdk_contract_tools::{owner::OwnerExternal, standard::nep145::Nep145};
+
+    use super::*;
+    use crate::{write, StorageKey, SCHEMA_VERSION};
+
+    fn registry_account() -> AccountId {
+        "registry.near".parse().unwrap()
+    }
+
+    fn set_context() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(registry_account())
+            .predecessor_account_id(registry_account())
+            .build());
+    }
+
+    #[test]
+    fn migrate_from_v0() {
+        set_context();
+
+        let alice: AccountId = "alice.near".parse().unwrap();
+        let mut v0 = PublicKeyManagerContractV0 {
+            key_map: LookupMap::new(StorageKey::KeyMap),
+        };
+        v0.key_map.insert(&alice, &Base64VecU8(vec![1; 32]));
+        env::state_write(&v0);
+
+        let contract = PublicKeyManagerContract::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert_eq!(contract.own_get_owner(), Some(registry_account()));
+        assert_eq!(
+            contract.storage_balance_bounds().min,
+            env::storage_byte_cost().saturating_mul(STORAGE_REGISTRATION_BYTES),
+        );
+        assert_eq!(
+            contract.get_public_key(alice),
+            Some(Base64VecU8(vec![1; 32])),
+        );
+    }
+
+    #[test]
+    #[should_panic = "State was written by a newer schema version."]
+    fn migrate_from_newer_version() {
+        set_context();
+
+        let contract = PublicKeyManagerContract::new(None);
+        env::state_write(&contract);
+        write(StorageKey::SchemaVersion, SCHEMA_VERSION + 1);
+
+        PublicKeyManagerContract::migrate();
+    }
+}
@@ -0,0 +1,218 @@
+use near_sdk::{
+    env, json_types::Base64VecU8, near, require, AccountId, BorshStorageKey, PanicOnDefault,
+};
+use near_sdk_contract_tools::{
+    event, owner::Owner, slot::Slot, standard::nep297::Event, Owner, Upgrade,
+};
+
+/// Bumped whenever the layout of the contract state changes, with a matching
+/// arm in [`MessageDirectory::migrate`].
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, BorshStorageKey)]
+#[near]
+enum StorageKey {
+    SchemaVersion,
+}
+
+fn schema_version_slot() -> Slot<u32> {
+    Slot::new(StorageKey::SchemaVersion)
+}
+
+#[event(
+    standard = "x-message-directory",
+    version = "1.0.0",
+    serde = "near_sdk::serde"
+)]
+enum ContractEvent {
+    SetShards { shards: Vec<Shard> },
+}
+
+/// The first four bytes of a sequence hash as a big-endian integer, with
+/// shorter hashes padded with zeros.
+pub fn sequence_hash_prefix(sequence_hash: &[u8]) -> u32 {
+    let mut prefix = [0; 4];
+    let len = sequence_hash.len().min(4);
+    prefix[..len].copy_from_slice(&sequence_hash[..len]);
+    u32::from_be_bytes(prefix)
+}
+
+/// An inclusive range of [`sequence_hash_prefix`]es.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct PrefixRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl PrefixRange {
+    pub fn contains(&self, sequence_hash: &[u8]) -> bool {
+        (self.start..=self.end).contains(&sequence_hash_prefix(sequence_hash))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct Shard {
+    pub range: PrefixRange,
+    /// The message repository that stores the range.
+    pub account_id: AccountId,
+}
+
+/// Shards must be in order and cover every prefix exactly once.
+fn validate_shards(shards: &[Shard]) {
+    require!(!shards.is_empty(), "At least one shard is required.");
+    require!(shards[0].range.start == 0, "Shards must start at prefix 0.");
+    require!(
+        shards[shards.len() - 1].range.end == u32::MAX,
+        "Shards must end at the last prefix."
+    );
+    for shard in shards {
+        require!(
+            shard.range.start <= shard.range.end,
+            "Shard range is empty."
+        );
+    }
+    for pair in shards.windows(2) {
+        require!(
+            pair[0].range.end.checked_add(1) == Some(pair[1].range.start),
+            "Shard ranges must be contiguous."
+        );
+    }
+}
+
+/// Describes a message repository that is sharded across several contracts,
+/// each of which stores the sequence hashes in one range of prefixes.
+#[near(contract_state)]
+#[derive(PanicOnDefault, Owner, Upgrade)]
+#[upgrade(hook = "owner", serializer = "borsh")]
+pub struct MessageDirectory {
+    shards: Vec<Shard>,
+}
+
+#[near]
+impl MessageDirectory {
+    /// `owner_id` defaults to the account that initializes the contract.
+    #[init]
+    pub fn new(owner_id: Option<AccountId>, shards: Vec<Shard>) -> Self {
+        validate_shards(&shards);
+        let mut contract = Self { shards };
+
+        Owner::init(
+            &mut contract,
+            &owner_id.unwrap_or_else(env::predecessor_account_id),
+        );
+        schema_version_slot().write(&SCHEMA_VERSION);
+
+        contract
+    }
+
+    /// Brings state written by any earlier schema version up to
+    /// [`SCHEMA_VERSION`]. [`MessageDirectory::upgrade`] calls this once the
+    /// new code is deployed.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let schema_version = schema_version_slot().read().unwrap_or(0);
+        require!(
+            schema_version <= SCHEMA_VERSION,
+            "State was written by a newer schema version."
+        );
+
+        let contract = env::state_read().unwrap_or_else(|| env::panic_str("Failed to read state."));
+        schema_version_slot().write(&SCHEMA_VERSION);
+
+        contract
+    }
+
+    pub fn get_schema_version(&self) -> u32 {
+        schema_version_slot().read().unwrap_or(0)
+    }
+
+    pub fn get_shards(&self) -> Vec<Shard> {
+        self.shards.clone()
+    }
+
+    /// The message repository that stores `sequence_hash`.
+    pub fn get_shard(&self, sequence_hash: Base64VecU8) -> AccountId {
+        self.shards
+            .iter()
+            .find(|shard| shard.range.contains(&sequence_hash.0))
+            .map(|shard| shard.account_id.clone())
+            .unwrap_or_else(|| env::panic_str("No shard covers the sequence hash."))
+    }
+
+    /// Replaces the shard map. Messages already published stay on the shards
+    /// that stored them, so ranges should only be reassigned to repositories
+    /// that have taken over their contents.
+    pub fn set_shards(&mut self, shards: Vec<Shard>) {
+        Self::require_owner();
+        validate_shards(&shards);
+        self.shards = shards.clone();
+
+        ContractEvent::SetShards { shards }.emit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::{test_utils::VMContextBuilder, testing_env};
+
+    use super::*;
+
+    fn shard(start: u32, end: u32, account_id: &str) -> Shard {
+        Shard {
+            range: PrefixRange { start, end },
+            account_id: account_id.parse().unwrap(),
+        }
+    }
+
+    fn directory() -> MessageDirectory {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("directory.near".parse().unwrap())
+            .predecessor_account_id("owner.near".parse().unwrap())
+            .build());
+
+        MessageDirectory::new(
+            None,
+            vec![
+                shard(0, 0x7fff_ffff, "shard-0.near"),
+                shard(0x8000_0000, u32::MAX, "shard-1.near"),
+            ],
+        )
+    }
+
+    #[test]
+    fn route_by_prefix() {
+        let contract = directory();
+
+        assert_eq!(
+            contract.get_shard(vec![0x7f; 32].into()).as_str(),
+            "shard-0.near"
+        );
+        assert_eq!(
+            contract.get_shard(vec![0x80; 32].into()).as_str(),
+            "shard-1.near"
+        );
+        assert_eq!(contract.get_shard(vec![].into()).as_str(), "shard-0.near");
+    }
+
+    #[test]
+    #[should_panic = "Shard ranges must be contiguous."]
+    fn reject_gap() {
+        let mut contract = directory();
+
+        contract.set_shards(vec![
+            shard(0, 10, "shard-0.near"),
+            shard(12, u32::MAX, "shard-1.near"),
+        ]);
+    }
+
+    #[test]
+    #[should_panic = "Shards must end at the last prefix."]
+    fn reject_partial_cover() {
+        let mut contract = directory();
+
+        contract.set_shards(vec![shard(0, 10, "shard-0.near")]);
+    }
+}
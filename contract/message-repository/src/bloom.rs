@@ -0,0 +1,98 @@
+use near_sdk::borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+
+use crate::filter::keyed_hash;
+
+/// The most hash functions a filter will use, which bounds its false positive
+/// rate at about 2^-32.
+const MAX_HASH_COUNT: u8 = 32;
+
+/// A Bloom filter over keyed SipHash-1-3. Bit indices are derived from a
+/// single 64-bit hash by double hashing.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct BloomFilter {
+    seed: u64,
+    hash_count: u8,
+    len: u32,
+    bits: Vec<u8>,
+}
+
+/// The number of hash functions that brings the false positive rate of an
+/// optimally sized filter down to `false_positive_rate`, which is about
+/// `2^-hash_count`.
+pub fn hash_count_for(false_positive_rate: f64) -> u8 {
+    let mut hash_count = 1;
+    let mut rate = 0.5;
+    while rate > false_positive_rate && hash_count < MAX_HASH_COUNT {
+        rate /= 2.0;
+        hash_count += 1;
+    }
+    hash_count
+}
+
+impl BloomFilter {
+    pub fn new(capacity: u64, false_positive_rate: f64, seed: u64) -> Self {
+        let hash_count = hash_count_for(false_positive_rate);
+        // k / ln 2 bits per item is optimal for k hash functions
+        let bit_count = (capacity.max(1) * hash_count as u64 * 1443).div_ceil(1000);
+
+        Self {
+            seed,
+            hash_count,
+            len: 0,
+            bits: vec![0; bit_count.div_ceil(8) as usize],
+        }
+    }
+
+    fn bit_indices(&self, bytes: &[u8]) -> impl Iterator<Item = usize> {
+        let hash = keyed_hash(self.seed, bytes);
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let bit_count = self.bits.len() as u64 * 8;
+
+        (0..self.hash_count as u64).map(move |i| ((h1 + i * h2) % bit_count) as usize)
+    }
+
+    pub fn insert(&mut self, bytes: &[u8]) {
+        for i in self.bit_indices(bytes).collect::<Vec<_>>() {
+            self.bits[i / 8] |= 1 << (i % 8);
+        }
+        self.len += 1;
+    }
+
+    pub fn contains(&self, bytes: &[u8]) -> bool {
+        self.bit_indices(bytes)
+            .all(|i| self.bits[i / 8] & (1 << (i % 8)) != 0)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_count_matches_false_positive_rate() {
+        assert_eq!(hash_count_for(0.5), 1);
+        assert_eq!(hash_count_for(0.01), 7);
+        assert_eq!(hash_count_for(0.0), MAX_HASH_COUNT);
+    }
+
+    #[test]
+    fn no_false_negatives() {
+        let mut filter = BloomFilter::new(1000, 0.01, 42);
+        for i in 0u32..1000 {
+            filter.insert(&i.to_le_bytes());
+        }
+
+        assert_eq!(filter.len(), 1000);
+        assert!((0u32..1000).all(|i| filter.contains(&i.to_le_bytes())));
+
+        let false_positives = (1000u32..11000)
+            .filter(|i| filter.contains(&i.to_le_bytes()))
+            .count();
+        assert!(false_positives < 200, "{false_positives} false positives");
+    }
+}
@@ -1,10 +1,194 @@
 use std::{collections::BTreeMap, hash::Hasher};
 
 use cuckoofilter::{CuckooFilter, ExportedCuckooFilter};
-use near_sdk::borsh::{
-    schema::{Declaration, Definition},
-    BorshDeserialize, BorshSchema, BorshSerialize,
+use near_sdk::{
+    borsh::{
+        self,
+        schema::{Declaration, Definition},
+        BorshDeserialize, BorshSchema, BorshSerialize,
+    },
+    near, require,
 };
+use siphasher::sip::{SipHasher, SipHasher13};
+
+use crate::{
+    bloom::BloomFilter,
+    xor::{KeySet, XorFilter},
+};
+
+/// Aggregators hold this many items unless configured otherwise.
+pub const DEFAULT_AGGREGATOR_CAPACITY: u64 = (1 << 10) - 1;
+
+/// Caps the storage that a single aggregator can take, and the work of
+/// sealing it. Sealing an aggregator and proving against its Merkle root read
+/// every one of its leaves, at about 0.1 Tgas each, which keeps either call
+/// around a third of the gas a transaction can attach.
+pub const MAX_AGGREGATOR_CAPACITY: u64 = DEFAULT_AGGREGATOR_CAPACITY;
+
+/// Prefixed to every encoded filter, ahead of the borsh-encoded [`Filter`],
+/// whose first byte in turn identifies the filter type.
+pub const FILTER_ENCODING_VERSION: u8 = 1;
+
+/// Keyed SipHash-1-3 over the raw bytes. Unlike hashing a slice through
+/// [`std::hash::Hash`], no `usize` length prefix is written, so the result is
+/// the same on every platform.
+pub fn keyed_hash(seed: u64, bytes: &[u8]) -> u64 {
+    let mut hasher = SipHasher13::new_with_keys(seed, 0);
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[near(serializers = [borsh, json])]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FilterKind {
+    Cuckoo,
+    Bloom {
+        false_positive_rate: f64,
+    },
+    /// Collects keys while current, and is sealed into an xor filter.
+    Xor,
+}
+
+impl FilterKind {
+    pub fn validate(&self) {
+        if let Self::Bloom {
+            false_positive_rate,
+        } = self
+        {
+            require!(
+                *false_positive_rate > 0.0 && *false_positive_rate < 1.0,
+                "False positive rate must be between 0 and 1."
+            );
+        }
+    }
+
+    /// Storage that an aggregator of this kind grows by per item, on top of
+    /// its size when empty.
+    pub fn storage_per_item(&self) -> u64 {
+        match self {
+            Self::Cuckoo | Self::Bloom { .. } => 0,
+            Self::Xor => std::mem::size_of::<u64>() as u64,
+        }
+    }
+}
+
+/// The parameters of every aggregator a repository creates, fixed when it
+/// is initialized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[near(serializers = [borsh, json])]
+#[serde(default)]
+pub struct AggregatorConfig {
+    /// Items per aggregator before it is sealed.
+    pub capacity: u64,
+    /// Bits per fingerprint of cuckoo and xor filters. Cuckoo filters only
+    /// support 8, and Bloom filters have no fingerprints.
+    pub fingerprint_bits: u8,
+    pub filter_kind: FilterKind,
+}
+
+impl Default for AggregatorConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_AGGREGATOR_CAPACITY,
+            fingerprint_bits: 8,
+            filter_kind: FilterKind::Cuckoo,
+        }
+    }
+}
+
+impl AggregatorConfig {
+    pub fn validate(&self) {
+        self.filter_kind.validate();
+        require!(
+            (1..=MAX_AGGREGATOR_CAPACITY).contains(&self.capacity),
+            "Aggregator capacity is out of range."
+        );
+        match self.filter_kind {
+            FilterKind::Cuckoo => require!(
+                self.fingerprint_bits == 8,
+                "Cuckoo filters only support 8-bit fingerprints."
+            ),
+            FilterKind::Xor => require!(
+                matches!(self.fingerprint_bits, 8 | 16),
+                "Xor filters support 8- or 16-bit fingerprints."
+            ),
+            FilterKind::Bloom { .. } => {}
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema)]
+#[borsh(crate = "near_sdk::borsh")]
+pub enum Filter {
+    Cuckoo(BorshCuckooFilter<SipHasher>),
+    Bloom(BloomFilter),
+    KeySet(KeySet),
+    Xor(XorFilter<u8>),
+    Xor16(XorFilter<u16>),
+}
+
+impl Filter {
+    pub fn new(kind: &FilterKind, capacity: u64, seed: u64) -> Self {
+        match kind {
+            FilterKind::Cuckoo => {
+                Self::Cuckoo(CuckooFilter::with_capacity(capacity as usize).into())
+            }
+            FilterKind::Bloom {
+                false_positive_rate,
+            } => Self::Bloom(BloomFilter::new(capacity, *false_positive_rate, seed)),
+            FilterKind::Xor => Self::KeySet(KeySet::new(seed)),
+        }
+    }
+
+    /// Adds an item, returning `false` without changing the filter if it
+    /// cannot take any more items.
+    pub fn insert(&mut self, bytes: &[u8]) -> bool {
+        match self {
+            Self::Cuckoo(filter) => {
+                // a failed insert evicts some other item, so roll it back
+                let snapshot = filter.0.export();
+                if filter.0.add(bytes).is_err() {
+                    filter.0 = snapshot.into();
+                    return false;
+                }
+            }
+            Self::Bloom(filter) => filter.insert(bytes),
+            Self::KeySet(filter) => filter.insert(bytes),
+            Self::Xor(_) | Self::Xor16(_) => return false,
+        }
+        true
+    }
+
+    pub fn len(&self) -> u64 {
+        match self {
+            Self::Cuckoo(filter) => filter.0.len() as u64,
+            Self::Bloom(filter) => filter.len(),
+            Self::KeySet(filter) => filter.len(),
+            Self::Xor(filter) => filter.len(),
+            Self::Xor16(filter) => filter.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Converts a filter that will take no more items into its final form.
+    pub fn seal(self, fingerprint_bits: u8) -> Self {
+        match self {
+            Self::KeySet(filter) if fingerprint_bits == 16 => Self::Xor16(filter.into_xor_filter()),
+            Self::KeySet(filter) => Self::Xor(filter.into_xor_filter()),
+            filter => filter,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![FILTER_ENCODING_VERSION];
+        borsh::to_writer(&mut bytes, self).unwrap();
+        bytes
+    }
+}
 
 pub struct BorshCuckooFilter<H>(pub CuckooFilter<H>);
 
@@ -73,6 +257,61 @@ mod tests {
 
     use super::*;
 
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    // The client's filter tests decode these same encodings.
+    #[test]
+    fn encoding_vectors() {
+        let mut bloom = Filter::new(
+            &FilterKind::Bloom {
+                false_positive_rate: 0.01,
+            },
+            4,
+            1,
+        );
+        let mut xor = Filter::new(&FilterKind::Xor, 4, 2);
+        let mut xor16 = Filter::new(&FilterKind::Xor, 4, 2);
+        for item in [&b"red"[..], b"green", b"blue"] {
+            assert!(bloom.insert(item));
+            assert!(xor.insert(item));
+            assert!(xor16.insert(item));
+        }
+
+        assert_eq!(
+            hex(&bloom.encode()),
+            "010101000000000000000703000000060000000875e84215a6",
+        );
+        assert_eq!(
+            hex(&xor.seal(8).encode()),
+            "01030200000000000000e7830665202abf3a030000000c000000240000000000001500000000000000\
+             000000000000000000000000000000000000000000dd0000b4",
+        );
+        assert_eq!(
+            hex(&xor16.seal(16).encode()),
+            "01040200000000000000e7830665202abf3a030000000c000000240000000000000000001569000000\
+             0000000000000000000000000000000000000000000000000000000000000000000000000000000000\
+             000000000000000000000000ddb400000000b414",
+        );
+    }
+
+    #[test]
+    fn failed_cuckoo_insert_leaves_filter_intact() {
+        // a single bucket of four fingerprints
+        let mut filter = Filter::new(&FilterKind::Cuckoo, 4, 0);
+        for i in 0u8..4 {
+            assert!(filter.insert(&[i]));
+        }
+
+        assert!(!filter.insert(&[4]));
+        assert_eq!(filter.len(), 4);
+        let Filter::Cuckoo(cuckoo) = &filter else {
+            unreachable!();
+        };
+        assert!((0u8..4).all(|i| cuckoo.0.contains(&[i][..])));
+    }
+
     #[test]
     fn test() {
         let mut c = CuckooFilter::<SipHasher>::with_capacity((1 << 16) - 1);
@@ -0,0 +1,218 @@
+//! Groth16 verification over alt_bn128 using NEAR's host functions.
+//!
+//! Points use the host function encoding: G1 is `x | y` and G2 is
+//! `x.re | x.im | y.re | y.im`, every coordinate 32 bytes little-endian.
+
+use near_sdk::{env, json_types::Base64VecU8, near};
+
+const G1_LEN: usize = 64;
+const G2_LEN: usize = 128;
+
+/// Sequence hash and ciphertext digest, each as two 128-bit halves.
+pub const PUBLISH_PUBLIC_INPUTS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct VerifyingKey {
+    pub alpha_g1: Base64VecU8,
+    pub beta_g2: Base64VecU8,
+    pub gamma_g2: Base64VecU8,
+    pub delta_g2: Base64VecU8,
+    pub ic: Vec<Base64VecU8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [json])]
+pub struct Proof {
+    pub a: Base64VecU8,
+    pub b: Base64VecU8,
+    pub c: Base64VecU8,
+}
+
+impl Proof {
+    pub fn is_well_formed(&self) -> bool {
+        self.a.0.len() == G1_LEN && self.b.0.len() == G2_LEN && self.c.0.len() == G1_LEN
+    }
+}
+
+impl VerifyingKey {
+    pub fn is_well_formed(&self, public_inputs: usize) -> bool {
+        self.alpha_g1.0.len() == G1_LEN
+            && self.beta_g2.0.len() == G2_LEN
+            && self.gamma_g2.0.len() == G2_LEN
+            && self.delta_g2.0.len() == G2_LEN
+            && self.ic.len() == public_inputs + 1
+            && self.ic.iter().all(|p| p.0.len() == G1_LEN)
+    }
+
+    /// Checks `e(A, B) = e(alpha, beta) * e(vk_x, gamma) * e(C, delta)`,
+    /// where `vk_x = IC[0] + sum(input[i] * IC[i + 1])`. Inputs are
+    /// little-endian scalars.
+    pub fn verify(&self, proof: &Proof, public_inputs: &[[u8; 32]]) -> bool {
+        if !proof.is_well_formed() || public_inputs.len() + 1 != self.ic.len() {
+            return false;
+        }
+
+        let mut multiexp = Vec::with_capacity(public_inputs.len() * (G1_LEN + 32));
+        for (ic, input) in self.ic[1..].iter().zip(public_inputs) {
+            multiexp.extend_from_slice(&ic.0);
+            multiexp.extend_from_slice(input);
+        }
+        let inputs_sum = env::alt_bn128_g1_multiexp(&multiexp);
+
+        // g1_sum takes `sign | point` items, where sign 1 negates the point
+        let vk_x =
+            env::alt_bn128_g1_sum(&[&[0], &self.ic[0].0[..], &[0], &inputs_sum[..]].concat());
+        let neg_a = env::alt_bn128_g1_sum(&[&[1], &proof.a.0[..]].concat());
+
+        env::alt_bn128_pairing_check(
+            &[
+                &neg_a[..],
+                &proof.b.0,
+                &self.alpha_g1.0,
+                &self.beta_g2.0,
+                &vk_x,
+                &self.gamma_g2.0,
+                &proof.c.0,
+                &self.delta_g2.0,
+            ]
+            .concat(),
+        )
+    }
+}
+
+fn halves(bytes: &[u8; 32]) -> [[u8; 32]; 2] {
+    let mut low = [0u8; 32];
+    let mut high = [0u8; 32];
+    low[..16].copy_from_slice(&bytes[..16]);
+    high[..16].copy_from_slice(&bytes[16..]);
+    [low, high]
+}
+
+/// The public inputs of the publish circuit: the sequence hash and the
+/// SHA-256 digest of the ciphertext, each split into two 128-bit halves.
+pub fn publish_public_inputs(
+    sequence_hash: &[u8; 32],
+    message: &[u8],
+) -> [[u8; 32]; PUBLISH_PUBLIC_INPUTS] {
+    let [h0, h1] = halves(sequence_hash);
+    let [c0, c1] = halves(&env::sha256_array(message));
+    [h0, h1, c0, c1]
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bn254::{Bn254, Fq, Fr, G1Affine, G2Affine};
+    use ark_ec::AffineRepr;
+    use ark_ff::{BigInteger, PrimeField};
+    use ark_groth16::Groth16;
+    use ark_relations::{
+        lc,
+        r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError},
+    };
+    use ark_snark::SNARK;
+    use ark_std::rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    /// Knows `a * b = inputs[0]`; squares the remaining inputs so that they
+    /// are bound by the proof.
+    #[derive(Clone)]
+    struct TestCircuit {
+        a: Fr,
+        b: Fr,
+        inputs: [Fr; PUBLISH_PUBLIC_INPUTS],
+    }
+
+    impl ConstraintSynthesizer<Fr> for TestCircuit {
+        fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+            let inputs = self
+                .inputs
+                .iter()
+                .map(|&x| cs.new_input_variable(|| Ok(x)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let a = cs.new_witness_variable(|| Ok(self.a))?;
+            let b = cs.new_witness_variable(|| Ok(self.b))?;
+            cs.enforce_constraint(lc!() + a, lc!() + b, lc!() + inputs[0])?;
+
+            for (&x, &value) in inputs[1..].iter().zip(&self.inputs[1..]) {
+                let square = cs.new_witness_variable(|| Ok(value * value))?;
+                cs.enforce_constraint(lc!() + x, lc!() + x, lc!() + square)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    fn fq(f: &Fq) -> Vec<u8> {
+        f.into_bigint().to_bytes_le()
+    }
+
+    fn g1(p: &G1Affine) -> Base64VecU8 {
+        let (x, y) = p.xy().unwrap();
+        [fq(x), fq(y)].concat().into()
+    }
+
+    fn g2(p: &G2Affine) -> Base64VecU8 {
+        let (x, y) = p.xy().unwrap();
+        [fq(&x.c0), fq(&x.c1), fq(&y.c0), fq(&y.c1)].concat().into()
+    }
+
+    fn scalar(f: &Fr) -> [u8; 32] {
+        f.into_bigint().to_bytes_le().try_into().unwrap()
+    }
+
+    #[test]
+    fn verify_arkworks_proof() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let circuit = TestCircuit {
+            a: Fr::from(3u64),
+            b: Fr::from(5u64),
+            inputs: [15u64, 7, 11, 13].map(Fr::from),
+        };
+
+        let (pk, ark_vk) =
+            Groth16::<Bn254>::circuit_specific_setup(circuit.clone(), &mut rng).unwrap();
+        let ark_proof = Groth16::<Bn254>::prove(&pk, circuit.clone(), &mut rng).unwrap();
+
+        let vk = VerifyingKey {
+            alpha_g1: g1(&ark_vk.alpha_g1),
+            beta_g2: g2(&ark_vk.beta_g2),
+            gamma_g2: g2(&ark_vk.gamma_g2),
+            delta_g2: g2(&ark_vk.delta_g2),
+            ic: ark_vk.gamma_abc_g1.iter().map(g1).collect(),
+        };
+        assert!(vk.is_well_formed(PUBLISH_PUBLIC_INPUTS));
+
+        let proof = Proof {
+            a: g1(&ark_proof.a),
+            b: g2(&ark_proof.b),
+            c: g1(&ark_proof.c),
+        };
+
+        let inputs = circuit.inputs.map(|x| scalar(&x));
+        assert!(vk.verify(&proof, &inputs));
+
+        let mut wrong_inputs = inputs;
+        wrong_inputs[2] = scalar(&Fr::from(12u64));
+        assert!(!vk.verify(&proof, &wrong_inputs));
+
+        let swapped = Proof {
+            a: proof.c.clone(),
+            c: proof.a.clone(),
+            ..proof
+        };
+        assert!(!vk.verify(&swapped, &inputs));
+    }
+
+    #[test]
+    fn public_inputs_split_into_halves() {
+        let sequence_hash: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let inputs = publish_public_inputs(&sequence_hash, b"message");
+
+        assert_eq!(inputs[0][..16], sequence_hash[..16]);
+        assert_eq!(inputs[1][..16], sequence_hash[16..]);
+        assert_eq!(inputs[2][..16], env::sha256(b"message")[..16]);
+        assert!(inputs.iter().all(|i| i[16..] == [0; 16]));
+    }
+}
@@ -1,18 +1,62 @@
-use cuckoofilter::CuckooFilter;
 use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
-    collections::{LookupMap, Vector},
+    collections::{LookupMap, LookupSet, Vector},
     env,
-    json_types::Base64VecU8,
-    near, require, BorshStorageKey, IntoStorageKey, NearToken, PanicOnDefault, PromiseOrValue,
+    json_types::{Base64VecU8, U64},
+    near, require, AccountId, AccountIdRef, BorshStorageKey, IntoStorageKey, NearToken,
+    PanicOnDefault, Promise, PromiseOrValue,
+};
+use near_sdk_contract_tools::{
+    event,
+    owner::Owner,
+    standard::{
+        nep145::{Nep145Controller, StorageBalanceBounds},
+        nep297::Event,
+    },
+    Nep145, Owner, Upgrade,
 };
-use near_sdk_contract_tools::{event, standard::nep297::Event};
-use siphasher::sip::SipHasher;
 
+mod bloom;
 mod filter;
-use filter::BorshCuckooFilter;
+use filter::{AggregatorConfig, Filter, FilterKind};
+mod groth16;
+mod merkle;
+mod migration;
+mod pow;
+mod xor;
+use groth16::{publish_public_inputs, Proof, VerifyingKey, PUBLISH_PUBLIC_INPUTS};
+use merkle::{MerkleLeaf, MerkleProof};
+use pow::PowChallenge;
 
-const AGGREGATOR_CAPACITY: u64 = (1 << 10) - 1;
+/// Bumped whenever the layout of the contract state changes, with a matching
+/// arm in [`MessageRepository::migrate`].
+const SCHEMA_VERSION: u32 = 12;
+/// Covers the storage of an account's NEP-145 balance record.
+const STORAGE_REGISTRATION_BYTES: u128 = 250;
+const DEFAULT_AGGREGATOR_PAGE_LIMIT: u32 = 16;
+const MAX_AGGREGATOR_PAGE_LIMIT: u32 = 64;
+/// How long a commitment reserves its slot before anyone may clear it.
+const COMMITMENT_TTL_MS: u64 = 60 * 60 * 1000;
+/// A [`MerkleLeaf`] record: 40 bytes of overhead, a 17-byte key and the
+/// 64-byte leaf.
+const MERKLE_LEAF_STORAGE_BYTES: u64 = 40 + 17 + 64;
+/// A Merkle root record: 40 bytes of overhead, a 9-byte key and the root.
+const MERKLE_ROOT_STORAGE_BYTES: u64 = 40 + 9 + 32;
+/// A [`Message`] record besides its ciphertext: 40 bytes of overhead, the
+/// 37-byte key of a 32-byte sequence hash, and 12 bytes of length prefix and
+/// timestamp.
+const MESSAGE_STORAGE_BYTES: u64 = 40 + 37 + 12;
+/// A [`DeletionKey`] record besides the payer's account ID: 40 bytes of
+/// overhead, a 37-byte key and 41 bytes of key hash, payer variant and
+/// account ID length prefix.
+const DELETION_KEY_STORAGE_BYTES: u64 = 40 + 37 + 41;
+/// A [`RateLimitWindow`] record besides the account ID: 40 bytes of
+/// overhead, 5 bytes of key prefix and 12 bytes of window.
+const RATE_LIMIT_WINDOW_STORAGE_BYTES: u64 = 40 + 5 + 12;
+/// The key of a namespaced message is longer than that of a [`Message`] in
+/// the default namespace by the length prefix and bytes of the namespace.
+const NAMESPACE_KEY_STORAGE_BYTES: u64 = 4;
+const MAX_NAMESPACE_LENGTH: usize = 64;
 
 #[derive(BorshStorageKey)]
 #[near]
@@ -20,18 +64,207 @@ enum StorageKey {
     Messages,
     CurrentAggregator,
     AggregatorHistory,
+    Commitments,
+    SchemaVersion,
+    RateLimitWindows,
+    MerkleRoots,
+    MerkleLeaf {
+        aggregator_index: u64,
+        position: u64,
+    },
+    DeletionKeys,
+    Tombstones,
+    Namespaces,
+    NamespaceMessages {
+        namespace: String,
+    },
+    NamespaceAggregatorHistory {
+        namespace: String,
+    },
+    NamespaceCurrentAggregator {
+        namespace: String,
+    },
+    Roles,
+    SequenceCommitments,
 }
 
 #[event(
     standard = "x-message-repository",
-    version = "1.0.0",
+    version = "1.7.0",
     serde = "near_sdk::serde"
 )]
 enum ContractEvent {
-    Publish { sequence_hash: Base64VecU8 },
+    Publish {
+        sequence_hash: Base64VecU8,
+        block_height: u64,
+        message_length: u32,
+        /// The aggregator that the sequence hash was inserted into.
+        aggregator_index: u64,
+        /// `None` for the default namespace.
+        namespace: Option<String>,
+    },
+    AggregatorSealed {
+        index: u64,
+        end_block_timestamp_ms: u64,
+        /// `None` for aggregators that were not complete when the
+        /// repository started keeping Merkle roots.
+        merkle_root: Option<Base64VecU8>,
+        namespace: Option<String>,
+    },
+    Commit {
+        commitment: Base64VecU8,
+    },
+    Delete {
+        sequence_hash: Base64VecU8,
+    },
+    Pause {
+        method: Option<PausableMethod>,
+    },
+    Unpause {
+        method: Option<PausableMethod>,
+    },
+    SetRateLimit {
+        rate_limit: Option<RateLimit>,
+    },
+    SetPowDifficulty {
+        difficulty: Option<u8>,
+    },
+    FundSponsorPool {
+        account_id: AccountId,
+        amount: NearToken,
+    },
+    WithdrawSponsorPool {
+        amount: NearToken,
+    },
+    SetShardRange {
+        shard_range: Option<PrefixRange>,
+    },
+    CreateNamespace {
+        namespace: String,
+        config: NamespaceConfig,
+    },
+    UpdateNamespace {
+        namespace: String,
+        config: NamespaceConfig,
+    },
+    SetAccessMode {
+        access_mode: AccessMode,
+    },
+    GrantRole {
+        account_id: AccountId,
+        role: Role,
+    },
+    RevokeRole {
+        account_id: AccountId,
+        role: Role,
+    },
+    SetServiceFee {
+        service_fee: Option<ServiceFee>,
+    },
+    WithdrawTreasury {
+        amount: NearToken,
+    },
+}
+
+/// Methods that the owner can pause individually.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[near(serializers = [borsh, json])]
+#[serde(rename_all = "snake_case")]
+pub enum PausableMethod {
+    Publish,
+    PublishBatch,
+    Commit,
+    Reveal,
+    PublishWithPow,
+}
+
+/// Who may publish. Reads are public either way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[near(serializers = [borsh, json])]
+#[serde(rename_all = "snake_case")]
+pub enum AccessMode {
+    Public,
+    /// Only accounts with [`Role::Publisher`] can publish or commit.
+    Private,
+}
+
+/// Roles that the owner grants to accounts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[near(serializers = [borsh, json])]
+#[serde(rename_all = "snake_case")]
+// Borsh refuses vectors of zero-sized types, which a single variant enum is.
+#[repr(u8)]
+pub enum Role {
+    /// Can publish to a repository in [`AccessMode::Private`].
+    Publisher,
+}
+
+/// Allows each account to publish at most `max_messages` messages in every
+/// fixed window of `window_ms` milliseconds.
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct RateLimit {
+    pub max_messages: u32,
+    pub window_ms: u64,
+}
+
+impl RateLimit {
+    fn validate(&self) {
+        require!(self.max_messages > 0, "Rate limit must allow messages.");
+        require!(self.window_ms > 0, "Rate limit window must be nonzero.");
+    }
+}
+
+/// Charged for every message on top of its storage, and added to the
+/// treasury.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct ServiceFee {
+    pub per_message: NearToken,
+    /// Per byte of ciphertext.
+    pub per_byte: NearToken,
+}
+
+impl ServiceFee {
+    fn for_message(&self, message_len: usize) -> NearToken {
+        self.per_byte
+            .saturating_mul(message_len as u128)
+            .saturating_add(self.per_message)
+    }
+}
+
+/// The first four bytes of a sequence hash as a big-endian integer, with
+/// shorter hashes padded with zeros. Sharded deployments assign each
+/// repository a range of these.
+pub fn sequence_hash_prefix(sequence_hash: &[u8]) -> u32 {
+    let mut prefix = [0; 4];
+    let len = sequence_hash.len().min(4);
+    prefix[..len].copy_from_slice(&sequence_hash[..len]);
+    u32::from_be_bytes(prefix)
+}
+
+/// An inclusive range of [`sequence_hash_prefix`]es.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct PrefixRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl PrefixRange {
+    pub fn contains(&self, sequence_hash: &[u8]) -> bool {
+        (self.start..=self.end).contains(&sequence_hash_prefix(sequence_hash))
+    }
+}
+
+/// How many messages an account has published in its latest window.
+#[near]
+pub struct RateLimitWindow {
+    pub window: u64,
+    pub message_count: u32,
 }
 
-type Aggregator = BorshCuckooFilter<SipHasher>;
+type Aggregator = Filter;
 
 #[near]
 pub struct AggregatorRecord {
@@ -39,6 +272,109 @@ pub struct AggregatorRecord {
     pub aggregator: Aggregator,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct FilterParameters {
+    #[serde(flatten)]
+    pub kind: FilterKind,
+    pub capacity: u64,
+    pub fingerprint_bits: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct AggregatorView {
+    pub index: u64,
+    /// `None` for the current aggregator, which is still accepting messages.
+    pub end_block_timestamp_ms: Option<u64>,
+    pub item_count: u64,
+    pub filter_parameters: FilterParameters,
+    pub filter: Base64VecU8,
+}
+
+impl AggregatorView {
+    fn new(
+        index: u64,
+        end_block_timestamp_ms: Option<u64>,
+        aggregator: &Aggregator,
+        config: &AggregatorConfig,
+    ) -> Self {
+        Self {
+            index,
+            end_block_timestamp_ms,
+            item_count: aggregator.len(),
+            filter_parameters: FilterParameters {
+                kind: config.filter_kind,
+                capacity: config.capacity,
+                fingerprint_bits: config.fingerprint_bits,
+            },
+            filter: aggregator.encode().into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct AggregatorPage {
+    pub aggregators: Vec<AggregatorView>,
+    /// Where the next page starts, or `None` once the current aggregator has
+    /// been returned.
+    pub next_index: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [json])]
+pub struct EpochInfo {
+    pub epoch_duration_ms: u64,
+    /// The epoch of the oldest aggregator.
+    pub first_epoch: u64,
+    /// The epoch of the current block.
+    pub current_epoch: u64,
+}
+
+/// What [`MessageRepository::publish`] charges for one message.
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [json])]
+pub struct PublishQuote {
+    /// The message's own records, refunded if it is deleted.
+    pub storage_fee: NearToken,
+    /// The account's rate limit window, charged once per call to the first
+    /// message the account publishes.
+    pub rate_limit_storage_fee: NearToken,
+    /// The message's share of its aggregator.
+    pub aggregator_fee: NearToken,
+    /// The namespace's [`NamespaceConfig::publish_fee`], which is zero in
+    /// the default namespace.
+    pub publish_fee: NearToken,
+    /// The repository's [`ServiceFee`] for the message, which is zero when
+    /// none is set.
+    pub service_fee: NearToken,
+    pub total: NearToken,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [json])]
+pub struct RepositoryStats {
+    /// Messages stored, less those deleted. `None` for repositories upgraded
+    /// from before schema version 8, which did not count them.
+    pub message_count: Option<u64>,
+    /// Sealed aggregators plus the current one.
+    pub aggregator_count: u64,
+    pub current_aggregator_item_count: u64,
+    pub aggregator_capacity: u64,
+    /// Storage of an aggregator and the Merkle leaves and root it fills, which
+    /// its messages share.
+    pub aggregator_storage_usage: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [json])]
+pub struct PowChallengeView {
+    pub challenge: Base64VecU8,
+    /// Leading zero bits required of the proof of work.
+    pub difficulty: u8,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 #[near(serializers = [borsh, json])]
 pub struct Message {
@@ -46,16 +382,329 @@ pub struct Message {
     pub block_timestamp_ms: u64,
 }
 
+/// A pending `sha256(sequence_hash || message || salt)` commitment.
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct Commitment {
+    pub account_id: AccountId,
+    pub block_height: u64,
+    pub block_timestamp_ms: u64,
+    /// Refunded to `account_id` once the commitment is revealed or removed.
+    /// Zero when the commitment was paid for from a storage balance, which
+    /// is credited instead.
+    pub storage_deposit: NearToken,
+}
+
+impl Commitment {
+    pub fn is_expired(&self) -> bool {
+        env::block_timestamp_ms() >= self.block_timestamp_ms.saturating_add(COMMITMENT_TTL_MS)
+    }
+}
+
+/// A [`Commitment`] stored under the sequence key of the message it commits
+/// to.
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct SequenceCommitment {
+    pub digest: Base64VecU8,
+    pub commitment: Commitment,
+}
+
+/// Whoever paid for the storage of a message, and is refunded when it is
+/// deleted.
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MessagePayer {
+    /// Attached a deposit, and is refunded by transfer.
+    Deposit {
+        account_id: AccountId,
+    },
+    /// Paid from its storage balance, which is credited.
+    StorageBalance {
+        account_id: AccountId,
+    },
+    SponsorPool,
+}
+
+/// Lets anyone who knows the preimage of `key_hash` delete a message.
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct DeletionKey {
+    pub key_hash: Base64VecU8,
+    pub payer: MessagePayer,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [json])]
+pub struct PublishItem {
+    pub sequence_hash: Base64VecU8,
+    pub message: Base64VecU8,
+    /// Required when the repository has a publish verifying key.
+    pub proof: Option<Proof>,
+    /// `sha256` of a key that can later [`MessageRepository::delete`] the
+    /// message. Messages published without one are kept forever.
+    pub deletion_key_hash: Option<Base64VecU8>,
+    /// When set, the message is only published if this sequence hash is
+    /// already stored or comes earlier in the same batch, so that a sender's
+    /// chain of messages has no gaps. See [`MessageRepository::publish_after`].
+    pub previous_sequence_hash: Option<Base64VecU8>,
+}
+
+/// The settings of a [`Namespace`]. The aggregator parameters are fixed when
+/// it is created.
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct NamespaceConfig {
+    pub aggregator_config: AggregatorConfig,
+    /// Allowed ciphertext lengths. Empty means any length is accepted.
+    pub payload_size_classes: Vec<u32>,
+    /// Charged for every message on top of its storage and the
+    /// repository's [`ServiceFee`], and added to the treasury.
+    pub publish_fee: NearToken,
+}
+
+/// A logical repository within the deployment, with its own messages,
+/// aggregators and fees. Its aggregators are sealed only when full, without
+/// Merkle roots, and its messages cannot be deleted. The methods of those
+/// features fail when given a namespace. Pausing, rate limits, the shard
+/// range and the publish verifying key apply to every namespace.
+#[near]
+pub struct Namespace {
+    messages: LookupMap<Vec<u8>, Message>,
+    aggregator_history: Vector<AggregatorRecord>,
+    aggregator_storage_usage: u64,
+    config: NamespaceConfig,
+    message_count: u64,
+}
+
+impl Namespace {
+    fn current_aggregator(namespace: &str) -> Aggregator {
+        get_lazy(StorageKey::NamespaceCurrentAggregator {
+            namespace: namespace.to_string(),
+        })
+        .unwrap()
+    }
+
+    fn seal_aggregator(&mut self, namespace: &str, aggregator: Aggregator) {
+        let index = self.aggregator_history.len();
+        let end_block_timestamp_ms = env::block_timestamp_ms();
+        self.aggregator_history.push(&AggregatorRecord {
+            aggregator: aggregator.seal(self.config.aggregator_config.fingerprint_bits),
+            end_block_timestamp_ms,
+        });
+
+        ContractEvent::AggregatorSealed {
+            index,
+            end_block_timestamp_ms,
+            merkle_root: None,
+            namespace: Some(namespace.to_string()),
+        }
+        .emit();
+    }
+
+    /// Returns the index of the aggregator that each sequence hash was
+    /// inserted into.
+    fn add_to_current_aggregator<'a>(
+        &mut self,
+        namespace: &str,
+        sequence_hashes: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Vec<u64> {
+        let config = self.config.aggregator_config;
+        let mut current_aggregator = Self::current_aggregator(namespace);
+
+        let mut aggregator_indices = vec![];
+        for sequence_hash in sequence_hashes {
+            if current_aggregator.len() >= config.capacity
+                || !current_aggregator.insert(sequence_hash)
+            {
+                let sealed = std::mem::replace(&mut current_aggregator, new_aggregator(&config));
+                self.seal_aggregator(namespace, sealed);
+
+                require!(
+                    current_aggregator.insert(sequence_hash),
+                    "Failed to add to a new aggregator."
+                );
+            }
+            aggregator_indices.push(self.aggregator_history.len());
+        }
+
+        write(
+            StorageKey::NamespaceCurrentAggregator {
+                namespace: namespace.to_string(),
+            },
+            current_aggregator,
+        );
+
+        aggregator_indices
+    }
+}
+
 #[near(contract_state)]
-#[derive(PanicOnDefault)]
+#[derive(PanicOnDefault, Nep145, Owner, Upgrade)]
+#[upgrade(hook = "owner", serializer = "borsh")]
 pub struct MessageRepository {
     messages: LookupMap<Vec<u8>, Message>,
     aggregator_history: Vector<AggregatorRecord>,
     aggregator_storage_usage: u64,
+    /// Allowed ciphertext lengths. Empty means any length is accepted.
+    payload_size_classes: Vec<u32>,
+    /// When set, every publish must prove knowledge of the channel secret
+    /// behind its sequence hash.
+    publish_verifying_key: Option<VerifyingKey>,
+    commitments: LookupMap<Vec<u8>, Commitment>,
+    /// When set, messages can only be published with [`MessageRepository::reveal`].
+    require_commitments: bool,
+    /// When set, the current aggregator is also sealed at the end of every
+    /// epoch of this many milliseconds, and aggregators are only served by
+    /// epoch number.
+    epoch_duration_ms: Option<u64>,
+    /// The epoch that the current aggregator belongs to.
+    current_epoch: u64,
+    aggregator_config: AggregatorConfig,
+    /// Pauses every method in [`PausableMethod`].
+    paused: bool,
+    paused_methods: Vec<PausableMethod>,
+    rate_limit: Option<RateLimit>,
+    rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+    /// When set, messages can be published without a deposit by
+    /// [`MessageRepository::publish_with_pow`].
+    pow_difficulty: Option<u8>,
+    pow_challenge: PowChallenge,
+    /// Pays for the storage of messages published with proof of work.
+    sponsor_pool: NearToken,
+    /// The sequence hashes this repository accepts, when it is one shard of
+    /// a sharded deployment.
+    shard_range: Option<PrefixRange>,
+    /// The [`merkle`] root of each sealed aggregator, from
+    /// `first_merkle_aggregator` on.
+    merkle_roots: LookupMap<u64, [u8; 32]>,
+    first_merkle_aggregator: u64,
+    deletion_keys: LookupMap<Vec<u8>, DeletionKey>,
+    /// The sequence hashes of deleted messages, which cannot be published
+    /// again.
+    tombstones: LookupSet<Vec<u8>>,
+    /// See [`RepositoryStats::message_count`].
+    message_count: Option<u64>,
+    namespaces: LookupMap<String, Namespace>,
+    access_mode: AccessMode,
+    roles: LookupMap<AccountId, Vec<Role>>,
+    service_fee: Option<ServiceFee>,
+    /// Service and publish fees collected, which the owner can withdraw.
+    treasury: NearToken,
+    /// The live commitments to each sequence key, earliest first.
+    /// `commitments` only holds those made before schema version 12, keyed
+    /// by digest.
+    sequence_commitments: LookupMap<Vec<u8>, Vec<SequenceCommitment>>,
+}
+
+/// Pays for the storage of a call without an attached deposit.
+enum StoragePayer {
+    /// From the account's storage balance.
+    Account(AccountId),
+    SponsorPool,
+}
+
+fn new_aggregator(config: &AggregatorConfig) -> Aggregator {
+    let seed = u64::from_le_bytes(env::random_seed_array()[..8].try_into().unwrap());
+    Filter::new(&config.filter_kind, config.capacity, seed)
+}
+
+/// The storage usage of a new aggregator, plus what its items will add.
+fn new_aggregator_storage_usage(key: StorageKey, config: &AggregatorConfig) -> u64 {
+    let start_usage = env::storage_usage();
+    write(key, new_aggregator(config));
+    let end_usage = env::storage_usage();
+    // should never underflow if everything is working properly
+    end_usage - start_usage + config.filter_kind.storage_per_item() * config.capacity
+}
+
+/// Each item's share of an aggregator, rounded up.
+fn item_aggregator_fee(aggregator_storage_usage: u64, capacity: u64) -> NearToken {
+    let aggregator_storage_cost =
+        env::storage_byte_cost().saturating_mul(aggregator_storage_usage as u128);
+    let single_item_storage_cost = aggregator_storage_cost.saturating_div(capacity as u128);
+    let remainder = aggregator_storage_cost.as_yoctonear() % capacity as u128;
+    if remainder > 0 {
+        single_item_storage_cost.saturating_add(NearToken::from_yoctonear(1))
+    } else {
+        single_item_storage_cost
+    }
+}
+
+fn normalize_payload_size_classes(mut payload_size_classes: Vec<u32>) -> Vec<u32> {
+    payload_size_classes.sort_unstable();
+    payload_size_classes.dedup();
+    require!(
+        !payload_size_classes.contains(&0),
+        "Payload size classes must be nonzero."
+    );
+    payload_size_classes
+}
+
+fn is_earlier_in_batch(earlier_items: &[PublishItem], sequence_hash: &Base64VecU8) -> bool {
+    earlier_items
+        .iter()
+        .any(|item| &item.sequence_hash == sequence_hash)
+}
+
+fn is_allowed_payload_size(payload_size_classes: &[u32], len: usize) -> bool {
+    payload_size_classes.is_empty() || payload_size_classes.binary_search(&(len as u32)).is_ok()
+}
+
+/// Index of the oldest sealed aggregator in `aggregator_history` that ended
+/// at or after `block_timestamp_ms`, or of the current aggregator if there is
+/// none. End timestamps never decrease, so this is a binary search.
+fn first_aggregator_index_since(
+    aggregator_history: &Vector<AggregatorRecord>,
+    block_timestamp_ms: u64,
+) -> u64 {
+    let (mut low, mut high) = (0, aggregator_history.len());
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let record = aggregator_history.get(mid).unwrap();
+        if record.end_block_timestamp_ms < block_timestamp_ms {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
 }
 
-fn new_aggregator() -> Aggregator {
-    CuckooFilter::with_capacity(AGGREGATOR_CAPACITY as usize).into()
+/// See [`MessageRepository::get_aggregators`].
+fn aggregator_page(
+    aggregator_history: &Vector<AggregatorRecord>,
+    current_aggregator: impl Fn() -> Aggregator,
+    config: &AggregatorConfig,
+    since_block_timestamp_ms: Option<u64>,
+    from_index: Option<u64>,
+    limit: Option<u32>,
+) -> AggregatorPage {
+    let limit = page_limit(limit);
+
+    let first_index =
+        first_aggregator_index_since(aggregator_history, since_block_timestamp_ms.unwrap_or(0));
+    let current_index = aggregator_history.len();
+    let start = from_index.unwrap_or(0).clamp(first_index, current_index);
+    let end = start.saturating_add(limit as u64).min(current_index + 1);
+
+    let aggregators = (start..end)
+        .map(|index| match aggregator_history.get(index) {
+            Some(record) => AggregatorView::new(
+                index,
+                Some(record.end_block_timestamp_ms),
+                &record.aggregator,
+                config,
+            ),
+            None => AggregatorView::new(index, None, &current_aggregator(), config),
+        })
+        .collect();
+
+    AggregatorPage {
+        aggregators,
+        next_index: (end <= current_index).then_some(end),
+    }
 }
 
 fn get_lazy<T: BorshDeserialize>(key: impl IntoStorageKey) -> Option<T> {
@@ -63,115 +712,2789 @@ fn get_lazy<T: BorshDeserialize>(key: impl IntoStorageKey) -> Option<T> {
     borsh::from_slice(&bytes).ok()
 }
 
+fn page_limit(limit: Option<u32>) -> u32 {
+    let limit = limit
+        .unwrap_or(DEFAULT_AGGREGATOR_PAGE_LIMIT)
+        .min(MAX_AGGREGATOR_PAGE_LIMIT);
+    require!(limit > 0, "Limit must be nonzero.");
+    limit
+}
+
 fn write<T: BorshSerialize>(key: impl IntoStorageKey, value: T) {
     env::storage_write(&key.into_storage_key(), &borsh::to_vec(&value).unwrap());
 }
 
 #[near]
 impl MessageRepository {
+    /// `owner_id` defaults to the account that initializes the contract.
+    /// `require_commitments` defaults to `false`, in which case messages can
+    /// also be published directly and a sequence hash seen in a pending
+    /// publish can be front-run; set it to only accept revealed commitments.
     #[init]
-    pub fn new() -> Self {
-        let aggregator_storage_usage = {
-            let start_usage = env::storage_usage();
-            write(StorageKey::CurrentAggregator, new_aggregator());
-            let end_usage = env::storage_usage();
-            end_usage - start_usage // should never underflow if everything is working properly
-        };
+    pub fn new(
+        owner_id: Option<AccountId>,
+        payload_size_classes: Option<Vec<u32>>,
+        publish_verifying_key: Option<VerifyingKey>,
+        require_commitments: Option<bool>,
+        epoch_duration_ms: Option<u64>,
+        aggregator_config: Option<AggregatorConfig>,
+    ) -> Self {
+        let aggregator_config = aggregator_config.unwrap_or_default();
+        aggregator_config.validate();
+        require!(
+            epoch_duration_ms != Some(0),
+            "Epoch duration must be nonzero."
+        );
 
-        Self {
+        let payload_size_classes =
+            normalize_payload_size_classes(payload_size_classes.unwrap_or_default());
+        if let Some(vk) = publish_verifying_key.as_ref() {
+            require!(
+                vk.is_well_formed(PUBLISH_PUBLIC_INPUTS),
+                "Malformed publish verifying key."
+            );
+        }
+
+        let aggregator_storage_usage =
+            new_aggregator_storage_usage(StorageKey::CurrentAggregator, &aggregator_config)
+                + MERKLE_LEAF_STORAGE_BYTES * aggregator_config.capacity
+                + MERKLE_ROOT_STORAGE_BYTES;
+
+        let mut contract = Self {
             messages: LookupMap::new(StorageKey::Messages),
             aggregator_storage_usage,
             aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            payload_size_classes,
+            publish_verifying_key,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: require_commitments.unwrap_or(false),
+            epoch_duration_ms,
+            current_epoch: epoch_duration_ms.map_or(0, |d| env::block_timestamp_ms() / d),
+            aggregator_config,
+            paused: false,
+            paused_methods: vec![],
+            rate_limit: None,
+            rate_limit_windows: LookupMap::new(StorageKey::RateLimitWindows),
+            pow_difficulty: None,
+            pow_challenge: PowChallenge::new(),
+            sponsor_pool: NearToken::from_yoctonear(0),
+            shard_range: None,
+            merkle_roots: LookupMap::new(StorageKey::MerkleRoots),
+            first_merkle_aggregator: 0,
+            deletion_keys: LookupMap::new(StorageKey::DeletionKeys),
+            tombstones: LookupSet::new(StorageKey::Tombstones),
+            message_count: Some(0),
+            namespaces: LookupMap::new(StorageKey::Namespaces),
+            access_mode: AccessMode::Public,
+            roles: LookupMap::new(StorageKey::Roles),
+            service_fee: None,
+            treasury: NearToken::from_yoctonear(0),
+            sequence_commitments: LookupMap::new(StorageKey::SequenceCommitments),
+        };
+
+        contract.set_storage_balance_bounds(&StorageBalanceBounds {
+            min: env::storage_byte_cost().saturating_mul(STORAGE_REGISTRATION_BYTES),
+            max: None,
+        });
+        Owner::init(
+            &mut contract,
+            &owner_id.unwrap_or_else(env::predecessor_account_id),
+        );
+        write(StorageKey::SchemaVersion, SCHEMA_VERSION);
+
+        contract
+    }
+
+    /// Brings state written by any earlier schema version up to
+    /// [`SCHEMA_VERSION`]. [`MessageRepository::upgrade`] calls this once the
+    /// new code is deployed; after deploying with a full access key, call it
+    /// in the same transaction.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        // kept outside of the contract struct so that it can be read before
+        // knowing how to deserialize the struct
+        let schema_version = get_lazy::<u32>(StorageKey::SchemaVersion).unwrap_or(0);
+        require!(
+            schema_version <= SCHEMA_VERSION,
+            "State was written by a newer schema version."
+        );
+
+        let contract = match schema_version {
+            0 => migration::from_v0(),
+            1 => migration::from_v1(),
+            2 => migration::from_v2(),
+            3 => migration::from_v3(),
+            4 => migration::from_v4(),
+            5 => migration::from_v5(),
+            6 => migration::from_v6(),
+            7 => migration::from_v7(),
+            8 => migration::from_v8(),
+            9 => migration::from_v9(),
+            10 => migration::from_v10(),
+            11 => migration::from_v11(),
+            _ => env::state_read().unwrap_or_else(|| env::panic_str("Failed to read state.")),
+        };
+        write(StorageKey::SchemaVersion, SCHEMA_VERSION);
+
+        contract
+    }
+
+    /// Settles the storage used since `initial_storage_usage` against the
+    /// account's NEP-145 balance, and debits `additional_fee` from it, as an
+    /// attached deposit would pay it. Freed storage unlocks at most what the
+    /// account has locked, since it may have been paid for with an attached
+    /// deposit.
+    fn settle_storage_balance(
+        &mut self,
+        account_id: &AccountIdRef,
+        initial_storage_usage: u64,
+        additional_fee: NearToken,
+    ) {
+        let final_storage_usage = env::storage_usage();
+
+        if final_storage_usage < initial_storage_usage {
+            let Ok(balance) = self.get_storage_balance(account_id) else {
+                return;
+            };
+            let credit = env::storage_byte_cost()
+                .saturating_mul((initial_storage_usage - final_storage_usage) as u128)
+                .min(balance.total.saturating_sub(balance.available));
+            self.unlock_storage(account_id, credit)
+                .unwrap_or_else(|e| env::panic_str(&format!("Storage accounting error: {e}")));
+        } else {
+            let fee = env::storage_byte_cost()
+                .saturating_mul((final_storage_usage - initial_storage_usage) as u128);
+            if !fee.is_zero() {
+                self.lock_storage(account_id, fee)
+                    .unwrap_or_else(|e| env::panic_str(&format!("Storage accounting error: {e}")));
+            }
+        }
+
+        if !additional_fee.is_zero() {
+            self.withdraw_from_storage_account(account_id, additional_fee)
+                .unwrap_or_else(|e| env::panic_str(&format!("Storage accounting error: {e}")));
         }
     }
 
-    fn add_to_current_aggregator(&mut self, bytes: &[u8]) {
-        let mut current_aggregator: Aggregator = get_lazy(StorageKey::CurrentAggregator).unwrap();
+    /// Charges for the storage used since `initial_storage_usage`, plus
+    /// `additional_fee`: from the attached deposit if there is one, refunding
+    /// the rest to the predecessor, or else from `payer`.
+    fn charge_storage(
+        &mut self,
+        payer: StoragePayer,
+        initial_storage_usage: u64,
+        additional_fee: NearToken,
+    ) -> PromiseOrValue<()> {
+        if env::attached_deposit().is_zero() {
+            match payer {
+                StoragePayer::Account(account_id) => {
+                    self.settle_storage_balance(&account_id, initial_storage_usage, additional_fee)
+                }
+                StoragePayer::SponsorPool => {
+                    let fee = env::storage_byte_cost()
+                        .saturating_mul(
+                            env::storage_usage().saturating_sub(initial_storage_usage) as u128
+                        )
+                        .saturating_add(additional_fee);
+                    self.sponsor_pool = self
+                        .sponsor_pool
+                        .checked_sub(fee)
+                        .unwrap_or_else(|| env::panic_str("Sponsor pool is exhausted."));
+                }
+            }
+            return PromiseOrValue::Value(());
+        }
 
-        // create new aggregator if current one is full
-        if current_aggregator.0.len() as u64 >= AGGREGATOR_CAPACITY {
-            let record = AggregatorRecord {
-                aggregator: current_aggregator,
-                end_block_timestamp_ms: env::block_timestamp_ms(),
+        near_sdk_contract_tools::utils::apply_storage_fee_and_refund(
+            initial_storage_usage,
+            additional_fee.as_yoctonear(),
+        )
+        .map_or(PromiseOrValue::Value(()), |p| p.into())
+    }
+
+    /// The account whose storage balance pays for a call without an attached
+    /// deposit. A sponsor must be the signer of the transaction, as the
+    /// relayer of a NEP-366 meta transaction is, so nobody else can spend its
+    /// balance. Otherwise, the predecessor pays.
+    fn storage_payer(sponsor: Option<AccountId>) -> StoragePayer {
+        let Some(sponsor) = sponsor else {
+            return StoragePayer::Account(env::predecessor_account_id());
+        };
+
+        require!(
+            sponsor == env::signer_account_id(),
+            "Sponsor must sign the transaction."
+        );
+        require!(
+            env::attached_deposit().is_zero(),
+            "Sponsored calls cannot attach a deposit."
+        );
+        StoragePayer::Account(sponsor)
+    }
+
+    /// Who [`MessageRepository::charge_storage`] charges as `payer`.
+    fn message_payer(payer: &StoragePayer) -> MessagePayer {
+        if !env::attached_deposit().is_zero() {
+            return MessagePayer::Deposit {
+                account_id: env::predecessor_account_id(),
             };
-            self.aggregator_history.push(&record);
-            current_aggregator = new_aggregator();
         }
 
-        current_aggregator.0.add(bytes).unwrap();
-        write(StorageKey::CurrentAggregator, current_aggregator);
+        match payer {
+            StoragePayer::Account(account_id) => MessagePayer::StorageBalance {
+                account_id: account_id.clone(),
+            },
+            StoragePayer::SponsorPool => MessagePayer::SponsorPool,
+        }
     }
 
-    pub fn get_message(&self, sequence_hash: Base64VecU8) -> Option<Message> {
-        self.messages.get(&sequence_hash.0)
+    fn require_unpaused(&self, method: PausableMethod) {
+        require!(!self.paused, "Contract is paused.");
+        require!(!self.paused_methods.contains(&method), "Method is paused.");
     }
 
-    pub fn get_aggregators_since(&self, block_timestamp_ms: u64) -> Vec<Base64VecU8> {
-        let mut history = self
-            .aggregator_history
+    /// The [`ServiceFee`] of `items`.
+    fn items_service_fee(&self, items: &[PublishItem]) -> NearToken {
+        let Some(service_fee) = self.service_fee else {
+            return NearToken::from_yoctonear(0);
+        };
+
+        items
             .iter()
-            .rev()
-            .map_while(|a| {
-                if block_timestamp_ms > a.end_block_timestamp_ms {
-                    Some(borsh::to_vec(&a.aggregator).unwrap().into())
-                } else {
-                    None
-                }
+            .fold(NearToken::from_yoctonear(0), |total, item| {
+                total.saturating_add(service_fee.for_message(item.message.0.len()))
             })
-            .collect::<Vec<Base64VecU8>>();
-
-        history.push(
-            borsh::to_vec(&get_lazy::<Aggregator>(StorageKey::CurrentAggregator).unwrap())
-                .unwrap()
-                .into(),
-        );
+    }
 
-        history
+    fn has_role(&self, account_id: &AccountId, role: Role) -> bool {
+        self.roles
+            .get(account_id)
+            .is_some_and(|roles| roles.contains(&role))
     }
 
-    #[payable]
-    pub fn publish(
-        &mut self,
-        sequence_hash: Base64VecU8,
-        message: Base64VecU8,
-    ) -> PromiseOrValue<()> {
+    /// Requires the predecessor to be allowed to publish. A sponsor only
+    /// pays, so it needs no role.
+    fn require_publish_access(&self) {
         require!(
-            !self.messages.contains_key(&sequence_hash.0),
-            "Sequence hash already exists."
+            self.can_publish(env::predecessor_account_id()),
+            "Account is not allowed to publish."
         );
+    }
 
-        // outside of storage usage calculation so that users aren't charged when a new aggregator is created
-        self.add_to_current_aggregator(&sequence_hash.0);
-
-        let item_aggregator_fee = {
-            let aggregator_storage_cost =
-                env::storage_byte_cost().saturating_mul(self.aggregator_storage_usage as u128);
-            let single_item_storage_cost =
-                aggregator_storage_cost.saturating_div(AGGREGATOR_CAPACITY as u128);
-            let remainder = aggregator_storage_cost.as_yoctonear() % AGGREGATOR_CAPACITY as u128;
-            if remainder > 0 {
-                single_item_storage_cost.saturating_add(NearToken::from_yoctonear(1))
-            } else {
-                single_item_storage_cost
-            }
+    /// Counts `message_count` messages against the predecessor's current
+    /// window.
+    fn consume_rate_limit(&mut self, message_count: usize) {
+        let Some(rate_limit) = self.rate_limit.as_ref() else {
+            return;
         };
 
-        let initial_storage_usage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let window = env::block_timestamp_ms() / rate_limit.window_ms;
+        let previous_count = self
+            .rate_limit_windows
+            .get(&account_id)
+            .filter(|w| w.window == window)
+            .map_or(0, |w| w.message_count);
+        let message_count = previous_count.saturating_add(message_count as u32);
+        require!(
+            message_count <= rate_limit.max_messages,
+            "Rate limit exceeded."
+        );
 
-        self.messages.insert(
-            &sequence_hash.0,
-            &Message {
-                message,
-                block_timestamp_ms: env::block_timestamp_ms(),
+        self.rate_limit_windows.insert(
+            &account_id,
+            &RateLimitWindow {
+                window,
+                message_count,
             },
         );
+    }
 
-        ContractEvent::Publish { sequence_hash }.emit();
+    /// Rejects a namespace passed to a feature that only the default
+    /// namespace has, rather than quietly using the default namespace's
+    /// state.
+    fn require_default_namespace(namespace: Option<String>) {
+        if let Some(namespace) = namespace {
+            env::panic_str(&format!(
+                "Namespace {namespace} does not support this method."
+            ));
+        }
+    }
 
-        near_sdk_contract_tools::utils::apply_storage_fee_and_refund(
-            initial_storage_usage,
-            item_aggregator_fee.as_yoctonear(),
-        )
-        .map_or(PromiseOrValue::Value(()), |p| p.into())
+    fn require_epoch_duration_ms(&self) -> u64 {
+        self.epoch_duration_ms
+            .unwrap_or_else(|| env::panic_str("Aggregators are not sealed on epochs."))
+    }
+
+    fn require_publish_without_commitment(&self) {
+        require!(
+            !self.require_commitments,
+            "Messages must be committed before they are published."
+        );
+    }
+
+    fn sequence_commitments(&self, sequence_key: &[u8]) -> Vec<SequenceCommitment> {
+        self.sequence_commitments
+            .get(&sequence_key.to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Removes the commitment at `index` among those to `sequence_key` and
+    /// refunds its storage.
+    fn remove_sequence_commitment(&mut self, sequence_key: &[u8], index: usize) -> Commitment {
+        let initial_storage_usage = env::storage_usage();
+        let mut commitments = self.sequence_commitments(sequence_key);
+        let SequenceCommitment { commitment, .. } = commitments.remove(index);
+        if commitments.is_empty() {
+            self.sequence_commitments.remove(&sequence_key.to_vec());
+        } else {
+            self.sequence_commitments
+                .insert(&sequence_key.to_vec(), &commitments);
+        }
+
+        self.refund_commitment(&commitment, initial_storage_usage);
+        commitment
+    }
+
+    /// Removes a commitment made before schema version 12 and refunds its
+    /// storage.
+    fn remove_digest_commitment(&mut self, digest: &[u8]) -> Commitment {
+        let initial_storage_usage = env::storage_usage();
+        let commitment = self
+            .commitments
+            .remove(&digest.to_vec())
+            .unwrap_or_else(|| env::panic_str("Commitment not found."));
+
+        self.refund_commitment(&commitment, initial_storage_usage);
+        commitment
+    }
+
+    fn refund_commitment(&mut self, commitment: &Commitment, initial_storage_usage: u64) {
+        if commitment.storage_deposit.is_zero() {
+            self.settle_storage_balance(
+                &commitment.account_id,
+                initial_storage_usage,
+                NearToken::from_yoctonear(0),
+            );
+        } else {
+            Promise::new(commitment.account_id.clone()).transfer(commitment.storage_deposit);
+        }
+    }
+
+    fn namespace(&self, namespace: &str) -> Namespace {
+        self.namespaces
+            .get(&namespace.to_string())
+            .unwrap_or_else(|| env::panic_str("Namespace not found."))
+    }
+
+    /// The leaves of an aggregator's Merkle tree, in key order.
+    fn merkle_leaves(&self, aggregator_index: u64, item_count: u64) -> Vec<MerkleLeaf> {
+        let mut leaves = (0..item_count)
+            .map(|position| {
+                get_lazy(StorageKey::MerkleLeaf {
+                    aggregator_index,
+                    position,
+                })
+                .unwrap_or_else(|| env::panic_str("Missing Merkle leaf."))
+            })
+            .collect::<Vec<_>>();
+        leaves.sort_unstable();
+        leaves
+    }
+
+    fn seal_aggregator(&mut self, aggregator: Aggregator, end_block_timestamp_ms: u64) {
+        let index = self.aggregator_history.len();
+        let merkle_root = (index >= self.first_merkle_aggregator).then(|| {
+            let root = merkle::root(&self.merkle_leaves(index, aggregator.len()));
+            self.merkle_roots.insert(&index, &root);
+            root
+        });
+        self.aggregator_history.push(&AggregatorRecord {
+            aggregator: aggregator.seal(self.aggregator_config.fingerprint_bits),
+            end_block_timestamp_ms,
+        });
+
+        ContractEvent::AggregatorSealed {
+            index,
+            end_block_timestamp_ms,
+            merkle_root: merkle_root.map(|root| root.to_vec().into()),
+            namespace: None,
+        }
+        .emit();
+    }
+
+    /// Returns the index of the aggregator that each item was inserted into.
+    fn add_to_current_aggregator<'a>(
+        &mut self,
+        items: impl IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    ) -> Vec<u64> {
+        let mut current_aggregator: Aggregator = get_lazy(StorageKey::CurrentAggregator).unwrap();
+
+        if let Some(epoch_duration_ms) = self.epoch_duration_ms {
+            let epoch = env::block_timestamp_ms() / epoch_duration_ms;
+            if epoch != self.current_epoch {
+                // empty epochs leave no record behind
+                if !current_aggregator.is_empty() {
+                    let sealed = std::mem::replace(
+                        &mut current_aggregator,
+                        new_aggregator(&self.aggregator_config),
+                    );
+                    self.seal_aggregator(sealed, (self.current_epoch + 1) * epoch_duration_ms - 1);
+                }
+                self.current_epoch = epoch;
+            }
+        }
+
+        let mut aggregator_indices = vec![];
+        for (sequence_hash, message) in items {
+            // create new aggregator if current one is full
+            if current_aggregator.len() >= self.aggregator_config.capacity
+                || !current_aggregator.insert(sequence_hash)
+            {
+                let sealed = std::mem::replace(
+                    &mut current_aggregator,
+                    new_aggregator(&self.aggregator_config),
+                );
+                self.seal_aggregator(sealed, env::block_timestamp_ms());
+
+                require!(
+                    current_aggregator.insert(sequence_hash),
+                    "Failed to add to a new aggregator."
+                );
+            }
+            let aggregator_index = self.aggregator_history.len();
+            write(
+                StorageKey::MerkleLeaf {
+                    aggregator_index,
+                    position: current_aggregator.len() - 1,
+                },
+                MerkleLeaf::new(sequence_hash, message),
+            );
+            aggregator_indices.push(aggregator_index);
+        }
+
+        write(StorageKey::CurrentAggregator, current_aggregator);
+
+        aggregator_indices
+    }
+
+    fn verify_publish_proof(&self, item: &PublishItem) {
+        let Some(vk) = self.publish_verifying_key.as_ref() else {
+            return;
+        };
+
+        let Some(proof) = item.proof.as_ref() else {
+            env::panic_str("Publish proof required.");
+        };
+
+        let Ok(sequence_hash) = item.sequence_hash.0.as_slice().try_into() else {
+            env::panic_str("Sequence hash must be 32 bytes.");
+        };
+
+        require!(
+            vk.verify(
+                proof,
+                &publish_public_inputs(sequence_hash, &item.message.0)
+            ),
+            "Invalid publish proof."
+        );
+    }
+
+    /// Stores the messages in `namespace`, or else the default namespace, and
+    /// charges `payer` for their storage plus their share of the aggregators.
+    fn publish_items(
+        &mut self,
+        namespace: Option<String>,
+        items: Vec<PublishItem>,
+        payer: StoragePayer,
+    ) -> PromiseOrValue<()> {
+        self.require_publish_access();
+        if let Some(namespace) = namespace {
+            return self.publish_namespaced_items(namespace, items, payer);
+        }
+
+        for (i, item) in items.iter().enumerate() {
+            require!(
+                !self.messages.contains_key(&item.sequence_hash.0),
+                "Sequence hash already exists."
+            );
+            require!(
+                !self.tombstones.contains(&item.sequence_hash.0),
+                "Sequence hash was deleted."
+            );
+            if let Some(previous) = &item.previous_sequence_hash {
+                // a deleted predecessor was published, so it leaves no gap
+                require!(
+                    self.messages.contains_key(&previous.0)
+                        || self.tombstones.contains(&previous.0)
+                        || is_earlier_in_batch(&items[..i], previous),
+                    "Previous message not found."
+                );
+            }
+            require!(
+                item.deletion_key_hash
+                    .as_ref()
+                    .is_none_or(|hash| hash.0.len() == 32),
+                "Deletion key hash must be 32 bytes."
+            );
+            require!(
+                self.shard_range
+                    .is_none_or(|range| range.contains(&item.sequence_hash.0)),
+                "Sequence hash belongs to another shard."
+            );
+            require!(
+                is_allowed_payload_size(&self.payload_size_classes, item.message.0.len()),
+                "Message length is not an allowed payload size class."
+            );
+            self.verify_publish_proof(item);
+        }
+
+        // outside of storage usage calculation so that users aren't charged when a new aggregator is created
+        let aggregator_indices = self.add_to_current_aggregator(
+            items
+                .iter()
+                .map(|item| (&item.sequence_hash.0[..], &item.message.0[..])),
+        );
+
+        let aggregator_fee = item_aggregator_fee(
+            self.aggregator_storage_usage,
+            self.aggregator_config.capacity,
+        )
+        .saturating_mul(items.len() as u128);
+        let service_fee = self.items_service_fee(&items);
+        self.treasury = self.treasury.saturating_add(service_fee);
+
+        let initial_storage_usage = env::storage_usage();
+
+        // the first message in a window pays for the account's counter
+        self.consume_rate_limit(items.len());
+
+        for (
+            PublishItem {
+                sequence_hash,
+                message,
+                deletion_key_hash,
+                ..
+            },
+            aggregator_index,
+        ) in items.into_iter().zip(aggregator_indices)
+        {
+            let message_length = message.0.len() as u32;
+            let previous = self.messages.insert(
+                &sequence_hash.0,
+                &Message {
+                    message,
+                    block_timestamp_ms: env::block_timestamp_ms(),
+                },
+            );
+            require!(previous.is_none(), "Duplicate sequence hash in batch.");
+            self.message_count = self.message_count.map(|count| count + 1);
+            if let Some(key_hash) = deletion_key_hash {
+                self.deletion_keys.insert(
+                    &sequence_hash.0,
+                    &DeletionKey {
+                        key_hash,
+                        payer: Self::message_payer(&payer),
+                    },
+                );
+            }
+
+            ContractEvent::Publish {
+                sequence_hash,
+                block_height: env::block_height(),
+                message_length,
+                aggregator_index,
+                namespace: None,
+            }
+            .emit();
+        }
+
+        self.charge_storage(
+            payer,
+            initial_storage_usage,
+            aggregator_fee.saturating_add(service_fee),
+        )
+    }
+
+    /// [`MessageRepository::publish_items`] for a [`Namespace`], which also
+    /// charges its publish fee.
+    fn publish_namespaced_items(
+        &mut self,
+        namespace: String,
+        items: Vec<PublishItem>,
+        payer: StoragePayer,
+    ) -> PromiseOrValue<()> {
+        let mut record = self.namespace(&namespace);
+
+        for (i, item) in items.iter().enumerate() {
+            require!(
+                !record.messages.contains_key(&item.sequence_hash.0),
+                "Sequence hash already exists."
+            );
+            if let Some(previous) = &item.previous_sequence_hash {
+                require!(
+                    record.messages.contains_key(&previous.0)
+                        || is_earlier_in_batch(&items[..i], previous),
+                    "Previous message not found."
+                );
+            }
+            require!(
+                item.deletion_key_hash.is_none(),
+                "Namespaced messages cannot be deleted."
+            );
+            require!(
+                self.shard_range
+                    .is_none_or(|range| range.contains(&item.sequence_hash.0)),
+                "Sequence hash belongs to another shard."
+            );
+            require!(
+                is_allowed_payload_size(&record.config.payload_size_classes, item.message.0.len()),
+                "Message length is not an allowed payload size class."
+            );
+            self.verify_publish_proof(item);
+        }
+
+        // outside of storage usage calculation so that users aren't charged when a new aggregator is created
+        let aggregator_indices = record.add_to_current_aggregator(
+            &namespace,
+            items.iter().map(|item| &item.sequence_hash.0[..]),
+        );
+
+        let aggregator_fee = item_aggregator_fee(
+            record.aggregator_storage_usage,
+            record.config.aggregator_config.capacity,
+        )
+        .saturating_mul(items.len() as u128);
+        let treasury_fee = record
+            .config
+            .publish_fee
+            .saturating_mul(items.len() as u128)
+            .saturating_add(self.items_service_fee(&items));
+        self.treasury = self.treasury.saturating_add(treasury_fee);
+
+        let initial_storage_usage = env::storage_usage();
+
+        self.consume_rate_limit(items.len());
+
+        for (
+            PublishItem {
+                sequence_hash,
+                message,
+                ..
+            },
+            aggregator_index,
+        ) in items.into_iter().zip(aggregator_indices)
+        {
+            let message_length = message.0.len() as u32;
+            let previous = record.messages.insert(
+                &sequence_hash.0,
+                &Message {
+                    message,
+                    block_timestamp_ms: env::block_timestamp_ms(),
+                },
+            );
+            require!(previous.is_none(), "Duplicate sequence hash in batch.");
+            record.message_count += 1;
+
+            ContractEvent::Publish {
+                sequence_hash,
+                block_height: env::block_height(),
+                message_length,
+                aggregator_index,
+                namespace: Some(namespace.clone()),
+            }
+            .emit();
+        }
+        // rewriting the record does not change its size
+        self.namespaces.insert(&namespace, &record);
+
+        self.charge_storage(
+            payer,
+            initial_storage_usage,
+            aggregator_fee.saturating_add(treasury_fee),
+        )
+    }
+
+    pub fn get_epochs(&self, namespace: Option<String>) -> Option<EpochInfo> {
+        Self::require_default_namespace(namespace);
+        let epoch_duration_ms = self.epoch_duration_ms?;
+
+        Some(EpochInfo {
+            epoch_duration_ms,
+            first_epoch: self
+                .aggregator_history
+                .get(0)
+                .map_or(self.current_epoch, |r| {
+                    r.end_block_timestamp_ms / epoch_duration_ms
+                }),
+            current_epoch: env::block_timestamp_ms() / epoch_duration_ms,
+        })
+    }
+
+    /// Pages through the aggregators of a single epoch. Several aggregators
+    /// share an epoch when it fills more than one. The current aggregator
+    /// is included while no message from a later epoch has sealed it.
+    pub fn get_epoch_aggregators(
+        &self,
+        epoch: u64,
+        from_index: Option<u64>,
+        limit: Option<u32>,
+        namespace: Option<String>,
+    ) -> AggregatorPage {
+        Self::require_default_namespace(namespace);
+        let epoch_duration_ms = self.require_epoch_duration_ms();
+        let limit = page_limit(limit) as usize;
+        let epoch_start_ms = epoch.saturating_mul(epoch_duration_ms);
+        let epoch_end_ms = epoch_start_ms.saturating_add(epoch_duration_ms);
+        let current_index = self.aggregator_history.len();
+
+        let mut index = from_index.unwrap_or(0).max(first_aggregator_index_since(
+            &self.aggregator_history,
+            epoch_start_ms,
+        ));
+        let mut aggregators = vec![];
+
+        let next_index = loop {
+            let view = match self.aggregator_history.get(index) {
+                Some(record) if record.end_block_timestamp_ms < epoch_end_ms => {
+                    AggregatorView::new(
+                        index,
+                        Some(record.end_block_timestamp_ms),
+                        &record.aggregator,
+                        &self.aggregator_config,
+                    )
+                }
+                None if index == current_index && self.current_epoch == epoch => {
+                    AggregatorView::new(
+                        index,
+                        None,
+                        &get_lazy::<Aggregator>(StorageKey::CurrentAggregator).unwrap(),
+                        &self.aggregator_config,
+                    )
+                }
+                _ => break None,
+            };
+
+            if aggregators.len() == limit {
+                break Some(index);
+            }
+            aggregators.push(view);
+            index += 1;
+        };
+
+        AggregatorPage {
+            aggregators,
+            next_index,
+        }
+    }
+
+    /// [`MessageRepository::get_epoch_aggregators`] with a borsh-encoded
+    /// result.
+    #[result_serializer(borsh)]
+    pub fn get_epoch_aggregators_borsh(
+        &self,
+        epoch: u64,
+        from_index: Option<u64>,
+        limit: Option<u32>,
+        namespace: Option<String>,
+    ) -> AggregatorPage {
+        self.get_epoch_aggregators(epoch, from_index, limit, namespace)
+    }
+
+    pub fn get_schema_version(&self) -> u32 {
+        get_lazy(StorageKey::SchemaVersion).unwrap_or(0)
+    }
+
+    /// The aggregator parameters set at initialization.
+    pub fn get_config(&self) -> AggregatorConfig {
+        self.aggregator_config
+    }
+
+    pub fn get_payload_size_classes(&self) -> Vec<u32> {
+        self.payload_size_classes.clone()
+    }
+
+    pub fn get_publish_verifying_key(&self) -> Option<VerifyingKey> {
+        self.publish_verifying_key.clone()
+    }
+
+    pub fn get_require_commitments(&self) -> bool {
+        self.require_commitments
+    }
+
+    pub fn get_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn get_paused_methods(&self) -> Vec<PausableMethod> {
+        self.paused_methods.clone()
+    }
+
+    pub fn get_rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit.clone()
+    }
+
+    pub fn get_shard_range(&self) -> Option<PrefixRange> {
+        self.shard_range
+    }
+
+    pub fn get_service_fee(&self) -> Option<ServiceFee> {
+        self.service_fee
+    }
+
+    pub fn get_treasury(&self) -> NearToken {
+        self.treasury
+    }
+
+    pub fn get_access_mode(&self) -> AccessMode {
+        self.access_mode
+    }
+
+    pub fn get_roles(&self, account_id: AccountId) -> Vec<Role> {
+        self.roles.get(&account_id).unwrap_or_default()
+    }
+
+    /// Whether `account_id` may publish under the current access mode.
+    pub fn can_publish(&self, account_id: AccountId) -> bool {
+        match self.access_mode {
+            AccessMode::Public => true,
+            AccessMode::Private => self.has_role(&account_id, Role::Publisher),
+        }
+    }
+
+    /// The challenge to solve for [`MessageRepository::publish_with_pow`], or
+    /// `None` if proof-of-work publishing is disabled.
+    pub fn get_pow_challenge(&self) -> Option<PowChallengeView> {
+        Some(PowChallengeView {
+            challenge: self.pow_challenge.current.to_vec().into(),
+            difficulty: self.pow_difficulty?,
+        })
+    }
+
+    pub fn get_sponsor_pool(&self) -> NearToken {
+        self.sponsor_pool
+    }
+
+    /// What [`MessageRepository::publish`] charges `account_id`, publishing
+    /// and paying for a message of `ciphertext_len` bytes under a 32-byte
+    /// sequence hash in `namespace`, with a deletion key hash if `deletable`.
+    pub fn quote_publish(
+        &self,
+        ciphertext_len: u32,
+        account_id: AccountId,
+        deletable: Option<bool>,
+        namespace: Option<String>,
+    ) -> PublishQuote {
+        let account_id_len = account_id.len() as u64;
+        let mut storage_bytes = MESSAGE_STORAGE_BYTES + ciphertext_len as u64;
+
+        let (payload_size_classes, aggregator_fee, publish_fee) = match namespace {
+            Some(namespace) => {
+                require!(
+                    !deletable.unwrap_or(false),
+                    "Namespaced messages cannot be deleted."
+                );
+                storage_bytes += NAMESPACE_KEY_STORAGE_BYTES + namespace.len() as u64;
+                let Namespace {
+                    aggregator_storage_usage,
+                    config,
+                    ..
+                } = self.namespace(&namespace);
+                (
+                    config.payload_size_classes,
+                    item_aggregator_fee(
+                        aggregator_storage_usage,
+                        config.aggregator_config.capacity,
+                    ),
+                    config.publish_fee,
+                )
+            }
+            None => {
+                if deletable.unwrap_or(false) {
+                    storage_bytes += DELETION_KEY_STORAGE_BYTES + account_id_len;
+                }
+                (
+                    self.payload_size_classes.clone(),
+                    item_aggregator_fee(
+                        self.aggregator_storage_usage,
+                        self.aggregator_config.capacity,
+                    ),
+                    NearToken::from_yoctonear(0),
+                )
+            }
+        };
+        require!(
+            is_allowed_payload_size(&payload_size_classes, ciphertext_len as usize),
+            "Message length is not an allowed payload size class."
+        );
+
+        let rate_limit_storage_bytes =
+            if self.rate_limit.is_some() && !self.rate_limit_windows.contains_key(&account_id) {
+                RATE_LIMIT_WINDOW_STORAGE_BYTES + account_id_len
+            } else {
+                0
+            };
+
+        let storage_fee = env::storage_byte_cost().saturating_mul(storage_bytes as u128);
+        let rate_limit_storage_fee =
+            env::storage_byte_cost().saturating_mul(rate_limit_storage_bytes as u128);
+        let service_fee = self
+            .service_fee
+            .map_or(NearToken::from_yoctonear(0), |fee| {
+                fee.for_message(ciphertext_len as usize)
+            });
+
+        PublishQuote {
+            storage_fee,
+            rate_limit_storage_fee,
+            aggregator_fee,
+            publish_fee,
+            service_fee,
+            total: storage_fee
+                .saturating_add(rate_limit_storage_fee)
+                .saturating_add(aggregator_fee)
+                .saturating_add(publish_fee)
+                .saturating_add(service_fee),
+        }
+    }
+
+    pub fn get_stats(&self, namespace: Option<String>) -> RepositoryStats {
+        if let Some(namespace) = namespace {
+            let record = self.namespace(&namespace);
+            return RepositoryStats {
+                message_count: Some(record.message_count),
+                aggregator_count: record.aggregator_history.len() + 1,
+                current_aggregator_item_count: Namespace::current_aggregator(&namespace).len(),
+                aggregator_capacity: record.config.aggregator_config.capacity,
+                aggregator_storage_usage: record.aggregator_storage_usage,
+            };
+        }
+
+        RepositoryStats {
+            message_count: self.message_count,
+            aggregator_count: self.aggregator_history.len() + 1,
+            current_aggregator_item_count: get_lazy::<Aggregator>(StorageKey::CurrentAggregator)
+                .unwrap()
+                .len(),
+            aggregator_capacity: self.aggregator_config.capacity,
+            aggregator_storage_usage: self.aggregator_storage_usage,
+        }
+    }
+
+    /// The earliest commitment with the digest `commitment`. `sequence_key`
+    /// is as in [`MessageRepository::remove_commitment_and_refund`].
+    pub fn get_commitment(
+        &self,
+        commitment: Base64VecU8,
+        sequence_key: Option<Base64VecU8>,
+    ) -> Option<Commitment> {
+        match sequence_key {
+            Some(sequence_key) => self
+                .sequence_commitments(&sequence_key.0)
+                .into_iter()
+                .find(|c| c.digest == commitment)
+                .map(|c| c.commitment),
+            None => self.commitments.get(&commitment.0),
+        }
+    }
+
+    pub fn get_namespace_config(&self, namespace: String) -> Option<NamespaceConfig> {
+        self.namespaces.get(&namespace).map(|record| record.config)
+    }
+
+    /// Looks `sequence_hash` up in `namespace`, or else the default
+    /// namespace.
+    pub fn get_message(
+        &self,
+        sequence_hash: Base64VecU8,
+        namespace: Option<String>,
+    ) -> Option<Message> {
+        match namespace {
+            Some(namespace) => self.namespace(&namespace).messages.get(&sequence_hash.0),
+            None => self.messages.get(&sequence_hash.0),
+        }
+    }
+
+    pub fn get_deletion_key(&self, sequence_hash: Base64VecU8) -> Option<DeletionKey> {
+        self.deletion_keys.get(&sequence_hash.0)
+    }
+
+    pub fn is_deleted(&self, sequence_hash: Base64VecU8) -> bool {
+        self.tombstones.contains(&sequence_hash.0)
+    }
+
+    /// [`MessageRepository::is_deleted`] for many sequence hashes, in the
+    /// same order.
+    pub fn are_deleted(&self, sequence_hashes: Vec<Base64VecU8>) -> Vec<bool> {
+        sequence_hashes
+            .iter()
+            .map(|sequence_hash| self.tombstones.contains(&sequence_hash.0))
+            .collect()
+    }
+
+    pub fn get_messages(
+        &self,
+        sequence_hashes: Vec<Base64VecU8>,
+        namespace: Option<String>,
+    ) -> Vec<Option<Message>> {
+        let record = namespace.map(|namespace| self.namespace(&namespace));
+        let messages = record
+            .as_ref()
+            .map_or(&self.messages, |record| &record.messages);
+        sequence_hashes
+            .iter()
+            .map(|sequence_hash| messages.get(&sequence_hash.0))
+            .collect()
+    }
+
+    /// [`MessageRepository::get_message`] with a borsh-encoded result, which
+    /// carries the ciphertext as raw bytes rather than base64.
+    #[result_serializer(borsh)]
+    pub fn get_message_borsh(
+        &self,
+        sequence_hash: Base64VecU8,
+        namespace: Option<String>,
+    ) -> Option<Message> {
+        self.get_message(sequence_hash, namespace)
+    }
+
+    /// [`MessageRepository::get_messages`] with a borsh-encoded result.
+    #[result_serializer(borsh)]
+    pub fn get_messages_borsh(
+        &self,
+        sequence_hashes: Vec<Base64VecU8>,
+        namespace: Option<String>,
+    ) -> Vec<Option<Message>> {
+        self.get_messages(sequence_hashes, namespace)
+    }
+
+    /// The Merkle root over the messages of a sealed aggregator, or `None`
+    /// for the current aggregator and for any that were not complete when the
+    /// repository started keeping roots.
+    pub fn get_merkle_root(
+        &self,
+        aggregator_index: u64,
+        namespace: Option<String>,
+    ) -> Option<Base64VecU8> {
+        Self::require_default_namespace(namespace);
+        self.merkle_roots
+            .get(&aggregator_index)
+            .map(|root| root.to_vec().into())
+    }
+
+    /// Proves whether `sequence_hash` was published into a sealed aggregator,
+    /// against [`MessageRepository::get_merkle_root`].
+    pub fn get_merkle_proof(
+        &self,
+        aggregator_index: u64,
+        sequence_hash: Base64VecU8,
+        namespace: Option<String>,
+    ) -> Option<MerkleProof> {
+        Self::require_default_namespace(namespace);
+        if !self.merkle_roots.contains_key(&aggregator_index) {
+            return None;
+        }
+        let record = self.aggregator_history.get(aggregator_index)?;
+        let leaves = self.merkle_leaves(aggregator_index, record.aggregator.len());
+
+        Some(merkle::prove(&leaves, &sequence_hash.0))
+    }
+
+    /// Pages through the aggregators that can contain messages published at
+    /// or after `since_block_timestamp_ms`, oldest first. Sealed aggregators
+    /// hold the messages published up to and including their end timestamp,
+    /// and the page that reaches the current aggregator is the last one.
+    /// Namespaces are never sealed on epochs.
+    pub fn get_aggregators(
+        &self,
+        since_block_timestamp_ms: Option<u64>,
+        from_index: Option<u64>,
+        limit: Option<u32>,
+        namespace: Option<String>,
+    ) -> AggregatorPage {
+        if let Some(namespace) = namespace {
+            let record = self.namespace(&namespace);
+            return aggregator_page(
+                &record.aggregator_history,
+                || Namespace::current_aggregator(&namespace),
+                &record.config.aggregator_config,
+                since_block_timestamp_ms,
+                from_index,
+                limit,
+            );
+        }
+
+        require!(
+            self.epoch_duration_ms.is_none(),
+            "Aggregators are addressed by epoch number."
+        );
+        aggregator_page(
+            &self.aggregator_history,
+            || get_lazy(StorageKey::CurrentAggregator).unwrap(),
+            &self.aggregator_config,
+            since_block_timestamp_ms,
+            from_index,
+            limit,
+        )
+    }
+
+    /// [`MessageRepository::get_aggregators`] with a borsh-encoded result,
+    /// which carries the filters as raw bytes rather than base64.
+    #[result_serializer(borsh)]
+    pub fn get_aggregators_borsh(
+        &self,
+        since_block_timestamp_ms: Option<u64>,
+        from_index: Option<u64>,
+        limit: Option<u32>,
+        namespace: Option<String>,
+    ) -> AggregatorPage {
+        self.get_aggregators(since_block_timestamp_ms, from_index, limit, namespace)
+    }
+
+    /// The encoded filters of every aggregator that can contain messages
+    /// published at or after `block_timestamp_ms`, oldest first. Kept for
+    /// clients from before [`MessageRepository::get_aggregators`], whose
+    /// pages should be used instead, since this response is unbounded.
+    pub fn get_aggregators_since(
+        &self,
+        block_timestamp_ms: u64,
+        namespace: Option<String>,
+    ) -> Vec<Base64VecU8> {
+        let mut filters = vec![];
+        let mut from_index = Some(0);
+        while let Some(index) = from_index {
+            let page = self.get_aggregators(
+                Some(block_timestamp_ms),
+                Some(index),
+                Some(MAX_AGGREGATOR_PAGE_LIMIT),
+                namespace.clone(),
+            );
+            filters.extend(page.aggregators.into_iter().map(|a| a.filter));
+            from_index = page.next_index;
+        }
+        filters
+    }
+
+    /// Publishes into `namespace`, or else the default namespace. Storage is
+    /// paid for with the attached deposit or, without one, from the storage
+    /// balance of `sponsor` if given, or else of the predecessor.
+    #[payable]
+    pub fn publish(
+        &mut self,
+        sequence_hash: Base64VecU8,
+        message: Base64VecU8,
+        proof: Option<Proof>,
+        sponsor: Option<AccountId>,
+        deletion_key_hash: Option<Base64VecU8>,
+        namespace: Option<String>,
+    ) -> PromiseOrValue<()> {
+        self.require_unpaused(PausableMethod::Publish);
+        self.require_publish_without_commitment();
+        let payer = Self::storage_payer(sponsor);
+        self.publish_items(
+            namespace,
+            vec![PublishItem {
+                sequence_hash,
+                message,
+                proof,
+                deletion_key_hash,
+                previous_sequence_hash: None,
+            }],
+            payer,
+        )
+    }
+
+    /// [`MessageRepository::publish`], but only if the message's
+    /// `previous_sequence_hash` was published first, in the same namespace.
+    /// Senders that chain each message to the one before never leave a gap
+    /// that would stall their receivers. Paused along with `publish`.
+    #[payable]
+    pub fn publish_after(
+        &mut self,
+        message: PublishItem,
+        sponsor: Option<AccountId>,
+        namespace: Option<String>,
+    ) -> PromiseOrValue<()> {
+        self.require_unpaused(PausableMethod::Publish);
+        self.require_publish_without_commitment();
+        require!(
+            message.previous_sequence_hash.is_some(),
+            "Previous sequence hash is required."
+        );
+        let payer = Self::storage_payer(sponsor);
+        self.publish_items(namespace, vec![message], payer)
+    }
+
+    /// Publishes into a namespace and pays for storage as in
+    /// [`MessageRepository::publish`].
+    #[payable]
+    pub fn publish_batch(
+        &mut self,
+        messages: Vec<PublishItem>,
+        sponsor: Option<AccountId>,
+        namespace: Option<String>,
+    ) -> PromiseOrValue<()> {
+        self.require_unpaused(PausableMethod::PublishBatch);
+        self.require_publish_without_commitment();
+        require!(!messages.is_empty(), "Batch is empty.");
+        let payer = Self::storage_payer(sponsor);
+        self.publish_items(namespace, messages, payer)
+    }
+
+    /// Publishes without a deposit, given a proof of work over the message
+    /// (see [`pow`]). Storage and the service fee are paid for from the
+    /// sponsor pool.
+    pub fn publish_with_pow(
+        &mut self,
+        sequence_hash: Base64VecU8,
+        message: Base64VecU8,
+        proof: Option<Proof>,
+        pow_nonce: U64,
+        deletion_key_hash: Option<Base64VecU8>,
+        namespace: Option<String>,
+    ) -> PromiseOrValue<()> {
+        Self::require_default_namespace(namespace);
+        self.require_unpaused(PausableMethod::PublishWithPow);
+        self.require_publish_without_commitment();
+        let difficulty = self
+            .pow_difficulty
+            .unwrap_or_else(|| env::panic_str("Proof-of-work publishing is disabled."));
+        require!(
+            self.pow_challenge
+                .is_solved_by(difficulty, &sequence_hash.0, &message.0, pow_nonce.0),
+            "Invalid proof of work."
+        );
+        self.pow_challenge.rotate_if_stale();
+
+        self.publish_items(
+            None,
+            vec![PublishItem {
+                sequence_hash,
+                message,
+                proof,
+                deletion_key_hash,
+                previous_sequence_hash: None,
+            }],
+            StoragePayer::SponsorPool,
+        )
+    }
+
+    /// Reserves a slot for `sha256(sequence_hash || message || salt)` without
+    /// disclosing the sequence hash. The commitment is stored under
+    /// `sequence_key`, which is `sha256(sequence_hash)`, after any earlier
+    /// ones; expired commitments to it are removed. The deposit covers the
+    /// commitment's storage and is refunded when it is revealed or removed.
+    #[payable]
+    pub fn commit(
+        &mut self,
+        commitment: Base64VecU8,
+        sequence_key: Base64VecU8,
+        namespace: Option<String>,
+    ) -> PromiseOrValue<()> {
+        Self::require_default_namespace(namespace);
+        self.require_unpaused(PausableMethod::Commit);
+        self.require_publish_access();
+        require!(commitment.0.len() == 32, "Commitment must be 32 bytes.");
+        require!(sequence_key.0.len() == 32, "Sequence key must be 32 bytes.");
+        while let Some(index) = self
+            .sequence_commitments(&sequence_key.0)
+            .iter()
+            .position(|c| c.commitment.is_expired())
+        {
+            self.remove_sequence_commitment(&sequence_key.0, index);
+        }
+        let mut commitments = self.sequence_commitments(&sequence_key.0);
+        require!(
+            !commitments.iter().any(|c| c.digest == commitment
+                && c.commitment.account_id == env::predecessor_account_id()),
+            "Commitment already exists."
+        );
+
+        let initial_storage_usage = env::storage_usage();
+
+        commitments.push(SequenceCommitment {
+            digest: commitment.clone(),
+            commitment: Commitment {
+                account_id: env::predecessor_account_id(),
+                block_height: env::block_height(),
+                block_timestamp_ms: env::block_timestamp_ms(),
+                storage_deposit: NearToken::from_yoctonear(0),
+            },
+        });
+        self.sequence_commitments
+            .insert(&sequence_key.0, &commitments);
+        if !env::attached_deposit().is_zero() {
+            // rewriting a fixed-size field does not change the storage usage
+            commitments.last_mut().unwrap().commitment.storage_deposit = env::storage_byte_cost()
+                .saturating_mul((env::storage_usage() - initial_storage_usage) as u128);
+            self.sequence_commitments
+                .insert(&sequence_key.0, &commitments);
+        }
+
+        ContractEvent::Commit { commitment }.emit();
+
+        self.charge_storage(
+            StoragePayer::Account(env::predecessor_account_id()),
+            initial_storage_usage,
+            NearToken::from_yoctonear(0),
+        )
+    }
+
+    /// Publishes a message committed to in an earlier block.
+    ///
+    /// Reveals the earliest live commitment to the message, which only the
+    /// committing account can do, and only after the block of its
+    /// commitment. Committing again to a message learned from an in-flight
+    /// reveal does not take it over, and commitments to other messages under
+    /// the same sequence key do not block it.
+    #[payable]
+    pub fn reveal(
+        &mut self,
+        sequence_hash: Base64VecU8,
+        message: Base64VecU8,
+        salt: Base64VecU8,
+        proof: Option<Proof>,
+        deletion_key_hash: Option<Base64VecU8>,
+        namespace: Option<String>,
+    ) -> PromiseOrValue<()> {
+        Self::require_default_namespace(namespace);
+        self.require_unpaused(PausableMethod::Reveal);
+        let digest = env::sha256(&[&sequence_hash.0[..], &message.0[..], &salt.0[..]].concat());
+        let sequence_key = env::sha256(&sequence_hash.0);
+        let index = self
+            .sequence_commitments(&sequence_key)
+            .iter()
+            .position(|c| c.digest.0 == digest && !c.commitment.is_expired())
+            .unwrap_or_else(|| env::panic_str("Commitment not found."));
+        let commitment = self.remove_sequence_commitment(&sequence_key, index);
+
+        require!(
+            commitment.account_id == env::predecessor_account_id(),
+            "Commitment belongs to another account."
+        );
+        require!(
+            commitment.block_height < env::block_height(),
+            "Commitment must be revealed in a later block."
+        );
+
+        self.publish_items(
+            None,
+            vec![PublishItem {
+                sequence_hash,
+                message,
+                proof,
+                deletion_key_hash,
+                previous_sequence_hash: None,
+            }],
+            StoragePayer::Account(env::predecessor_account_id()),
+        )
+    }
+
+    /// Removes a commitment and refunds its storage deposit to the account
+    /// that made it. The committer can do so at any time, anyone else only
+    /// once the commitment has expired. `sequence_key` is the one it was made
+    /// under, or `None` if it was made before schema version 12.
+    pub fn remove_commitment_and_refund(
+        &mut self,
+        commitment: Base64VecU8,
+        sequence_key: Option<Base64VecU8>,
+    ) {
+        let predecessor = env::predecessor_account_id();
+        let removed = match sequence_key {
+            Some(sequence_key) => {
+                let commitments = self.sequence_commitments(&sequence_key.0);
+                let index = commitments
+                    .iter()
+                    .position(|c| {
+                        c.digest == commitment
+                            && (c.commitment.account_id == predecessor || c.commitment.is_expired())
+                    })
+                    .unwrap_or_else(|| {
+                        require!(
+                            !commitments.iter().any(|c| c.digest == commitment),
+                            "Commitment has not expired."
+                        );
+                        env::panic_str("Commitment not found.")
+                    });
+                self.remove_sequence_commitment(&sequence_key.0, index)
+            }
+            None => self.remove_digest_commitment(&commitment.0),
+        };
+        require!(
+            removed.account_id == predecessor || removed.is_expired(),
+            "Commitment has not expired."
+        );
+    }
+
+    /// Deletes a message published with a deletion key hash, given the key,
+    /// and refunds the storage it frees to whoever paid for it. The sequence
+    /// hash is tombstoned so that it cannot be published again. The message
+    /// stays in its aggregator and Merkle tree.
+    pub fn delete(
+        &mut self,
+        sequence_hash: Base64VecU8,
+        deletion_key: Base64VecU8,
+        namespace: Option<String>,
+    ) -> PromiseOrValue<()> {
+        Self::require_default_namespace(namespace);
+        let DeletionKey { key_hash, payer } = self
+            .deletion_keys
+            .get(&sequence_hash.0)
+            .unwrap_or_else(|| env::panic_str("Message cannot be deleted."));
+        require!(
+            env::sha256(&deletion_key.0) == key_hash.0,
+            "Invalid deletion key."
+        );
+
+        let initial_storage_usage = env::storage_usage();
+        self.deletion_keys.remove(&sequence_hash.0);
+        self.messages.remove(&sequence_hash.0);
+        self.tombstones.insert(&sequence_hash.0);
+        self.message_count = self.message_count.map(|count| count - 1);
+        let refund = env::storage_byte_cost()
+            .saturating_mul(initial_storage_usage.saturating_sub(env::storage_usage()) as u128);
+
+        ContractEvent::Delete { sequence_hash }.emit();
+
+        match payer {
+            MessagePayer::Deposit { account_id } => {
+                return Promise::new(account_id).transfer(refund).into();
+            }
+            MessagePayer::StorageBalance { account_id } => self.settle_storage_balance(
+                &account_id,
+                initial_storage_usage,
+                NearToken::from_yoctonear(0),
+            ),
+            MessagePayer::SponsorPool => {
+                self.sponsor_pool = self.sponsor_pool.saturating_add(refund);
+            }
+        }
+
+        PromiseOrValue::Value(())
+    }
+
+    /// Pauses `method`, or every pausable method if it is `None`. Expired
+    /// commitments can still be removed, messages deleted, and storage
+    /// balances withdrawn.
+    pub fn pause(&mut self, method: Option<PausableMethod>) {
+        Self::require_owner();
+        match method {
+            Some(method) => {
+                require!(
+                    !self.paused_methods.contains(&method),
+                    "Method is already paused."
+                );
+                self.paused_methods.push(method);
+            }
+            None => {
+                require!(!self.paused, "Contract is already paused.");
+                self.paused = true;
+            }
+        }
+
+        ContractEvent::Pause { method }.emit();
+    }
+
+    /// Lifts a pause set by [`MessageRepository::pause`] with the same
+    /// argument. Unpausing the contract leaves individually paused methods
+    /// paused.
+    pub fn unpause(&mut self, method: Option<PausableMethod>) {
+        Self::require_owner();
+        match method {
+            Some(method) => {
+                require!(
+                    self.paused_methods.contains(&method),
+                    "Method is not paused."
+                );
+                self.paused_methods.retain(|m| *m != method);
+            }
+            None => {
+                require!(self.paused, "Contract is not paused.");
+                self.paused = false;
+            }
+        }
+
+        ContractEvent::Unpause { method }.emit();
+    }
+
+    /// Replaces the rate limit, or removes it if `rate_limit` is `None`.
+    pub fn set_rate_limit(&mut self, rate_limit: Option<RateLimit>) {
+        Self::require_owner();
+        if let Some(rate_limit) = rate_limit.as_ref() {
+            rate_limit.validate();
+        }
+        self.rate_limit.clone_from(&rate_limit);
+
+        ContractEvent::SetRateLimit { rate_limit }.emit();
+    }
+
+    /// Restricts the repository to the sequence hashes in `shard_range`, as
+    /// assigned by a message directory, or lifts the restriction if it is
+    /// `None`. Messages already stored are unaffected.
+    pub fn set_shard_range(&mut self, shard_range: Option<PrefixRange>) {
+        Self::require_owner();
+        if let Some(range) = shard_range {
+            require!(range.start <= range.end, "Shard range is empty.");
+        }
+        self.shard_range = shard_range;
+
+        ContractEvent::SetShardRange { shard_range }.emit();
+    }
+
+    /// Switches between public and private publishing. Roles granted while
+    /// the repository is public take effect once it is private.
+    pub fn set_access_mode(&mut self, access_mode: AccessMode) {
+        Self::require_owner();
+        self.access_mode = access_mode;
+
+        ContractEvent::SetAccessMode { access_mode }.emit();
+    }
+
+    /// Grants `role` to `account_id`. The storage of the grant is paid for
+    /// with the attached deposit or from the owner's storage balance.
+    #[payable]
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) -> PromiseOrValue<()> {
+        Self::require_owner();
+        require!(
+            !self.has_role(&account_id, role),
+            "Account already has the role."
+        );
+
+        let initial_storage_usage = env::storage_usage();
+
+        let mut roles = self.get_roles(account_id.clone());
+        roles.push(role);
+        self.roles.insert(&account_id, &roles);
+
+        ContractEvent::GrantRole { account_id, role }.emit();
+
+        self.charge_storage(
+            StoragePayer::Account(env::predecessor_account_id()),
+            initial_storage_usage,
+            NearToken::from_yoctonear(0),
+        )
+    }
+
+    /// Revokes `role` from `account_id`, crediting the storage it frees to
+    /// the owner's storage balance.
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        Self::require_owner();
+        require!(
+            self.has_role(&account_id, role),
+            "Account does not have the role."
+        );
+
+        let initial_storage_usage = env::storage_usage();
+
+        let mut roles = self.get_roles(account_id.clone());
+        roles.retain(|r| *r != role);
+        if roles.is_empty() {
+            self.roles.remove(&account_id);
+        } else {
+            self.roles.insert(&account_id, &roles);
+        }
+
+        ContractEvent::RevokeRole { account_id, role }.emit();
+
+        self.settle_storage_balance(
+            &env::predecessor_account_id(),
+            initial_storage_usage,
+            NearToken::from_yoctonear(0),
+        );
+    }
+
+    /// Creates an empty namespace. The storage of its first aggregator is
+    /// paid for with the attached deposit or from the owner's storage
+    /// balance, and the storage of its messages by their publishers.
+    #[payable]
+    pub fn create_namespace(
+        &mut self,
+        namespace: String,
+        config: NamespaceConfig,
+    ) -> PromiseOrValue<()> {
+        Self::require_owner();
+        require!(
+            (1..=MAX_NAMESPACE_LENGTH).contains(&namespace.len()),
+            "Namespace must be 1 to 64 bytes."
+        );
+        require!(
+            !self.namespaces.contains_key(&namespace),
+            "Namespace already exists."
+        );
+        config.aggregator_config.validate();
+        let config = NamespaceConfig {
+            payload_size_classes: normalize_payload_size_classes(config.payload_size_classes),
+            ..config
+        };
+
+        let initial_storage_usage = env::storage_usage();
+
+        let aggregator_storage_usage = new_aggregator_storage_usage(
+            StorageKey::NamespaceCurrentAggregator {
+                namespace: namespace.clone(),
+            },
+            &config.aggregator_config,
+        );
+        self.namespaces.insert(
+            &namespace,
+            &Namespace {
+                messages: LookupMap::new(StorageKey::NamespaceMessages {
+                    namespace: namespace.clone(),
+                }),
+                aggregator_history: Vector::new(StorageKey::NamespaceAggregatorHistory {
+                    namespace: namespace.clone(),
+                }),
+                aggregator_storage_usage,
+                config: config.clone(),
+                message_count: 0,
+            },
+        );
+
+        ContractEvent::CreateNamespace { namespace, config }.emit();
+
+        self.charge_storage(
+            StoragePayer::Account(env::predecessor_account_id()),
+            initial_storage_usage,
+            NearToken::from_yoctonear(0),
+        )
+    }
+
+    /// Replaces the payload size classes and publish fee of a namespace,
+    /// where given.
+    pub fn update_namespace(
+        &mut self,
+        namespace: String,
+        payload_size_classes: Option<Vec<u32>>,
+        publish_fee: Option<NearToken>,
+    ) {
+        Self::require_owner();
+        let mut record = self.namespace(&namespace);
+        if let Some(payload_size_classes) = payload_size_classes {
+            record.config.payload_size_classes =
+                normalize_payload_size_classes(payload_size_classes);
+        }
+        if let Some(publish_fee) = publish_fee {
+            record.config.publish_fee = publish_fee;
+        }
+        self.namespaces.insert(&namespace, &record);
+
+        ContractEvent::UpdateNamespace {
+            namespace,
+            config: record.config,
+        }
+        .emit();
+    }
+
+    /// Enables proof-of-work publishing at `difficulty` leading zero bits, or
+    /// disables it if `difficulty` is `None`.
+    pub fn set_pow_difficulty(&mut self, difficulty: Option<u8>) {
+        Self::require_owner();
+        require!(difficulty != Some(0), "Difficulty must be nonzero.");
+        self.pow_difficulty = difficulty;
+
+        ContractEvent::SetPowDifficulty { difficulty }.emit();
+    }
+
+    /// Adds the attached deposit to the sponsor pool.
+    #[payable]
+    pub fn fund_sponsor_pool(&mut self) {
+        let amount = env::attached_deposit();
+        require!(!amount.is_zero(), "Deposit required.");
+        self.sponsor_pool = self.sponsor_pool.saturating_add(amount);
+
+        ContractEvent::FundSponsorPool {
+            account_id: env::predecessor_account_id(),
+            amount,
+        }
+        .emit();
+    }
+
+    /// Transfers `amount` out of the sponsor pool to the owner.
+    pub fn withdraw_sponsor_pool(&mut self, amount: NearToken) -> Promise {
+        Self::require_owner();
+        self.sponsor_pool = self
+            .sponsor_pool
+            .checked_sub(amount)
+            .unwrap_or_else(|| env::panic_str("Sponsor pool is too small."));
+
+        ContractEvent::WithdrawSponsorPool { amount }.emit();
+
+        Promise::new(env::predecessor_account_id()).transfer(amount)
+    }
+
+    /// Replaces the service fee, or removes it if `service_fee` is `None`.
+    /// Messages already published keep what they paid.
+    pub fn set_service_fee(&mut self, service_fee: Option<ServiceFee>) {
+        Self::require_owner();
+        self.service_fee = service_fee;
+
+        ContractEvent::SetServiceFee { service_fee }.emit();
+    }
+
+    /// Transfers `amount` out of the treasury to the owner.
+    pub fn withdraw_treasury(&mut self, amount: NearToken) -> Promise {
+        Self::require_owner();
+        self.treasury = self
+            .treasury
+            .checked_sub(amount)
+            .unwrap_or_else(|| env::panic_str("Treasury is too small."));
+
+        ContractEvent::WithdrawTreasury { amount }.emit();
+
+        Promise::new(env::predecessor_account_id()).transfer(amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::{
+        serde_json::{self, json},
+        test_utils::{get_logs, VMContextBuilder},
+        testing_env, Gas,
+    };
+    use near_sdk_contract_tools::standard::nep145::Nep145;
+
+    use super::*;
+
+    fn owner() -> AccountId {
+        "owner.near".parse().unwrap()
+    }
+
+    fn alice() -> AccountId {
+        "alice.near".parse().unwrap()
+    }
+
+    fn set_context(predecessor: AccountId, block_timestamp_ms: u64) {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("repository.near".parse().unwrap())
+            .predecessor_account_id(predecessor)
+            .attached_deposit(NearToken::from_near(1))
+            .block_timestamp(block_timestamp_ms * 1_000_000)
+            .build());
+    }
+
+    /// A call relayed by `relayer` in a meta transaction signed by `sender`.
+    fn set_relayed_context(sender: AccountId, relayer: AccountId) {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("repository.near".parse().unwrap())
+            .predecessor_account_id(sender)
+            .signer_account_id(relayer)
+            .build());
+    }
+
+    fn repository(rate_limit: Option<RateLimit>) -> MessageRepository {
+        set_context(owner(), 0);
+        let mut contract = MessageRepository::new(None, None, None, None, None, None);
+        contract.set_rate_limit(rate_limit);
+        contract
+    }
+
+    /// Like [`set_context`], at `block_height` instead of a timestamp.
+    fn set_block_context(predecessor: AccountId, block_height: u64) {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("repository.near".parse().unwrap())
+            .predecessor_account_id(predecessor)
+            .attached_deposit(NearToken::from_near(1))
+            .block_height(block_height)
+            .build());
+    }
+
+    /// The commitment to publishing `vec![message; 16]` with an empty salt.
+    fn digest(sequence_hash: u8, message: u8) -> Base64VecU8 {
+        env::sha256(&[[sequence_hash; 32].as_slice(), &[message; 16]].concat()).into()
+    }
+
+    fn sequence_key(sequence_hash: u8) -> Base64VecU8 {
+        env::sha256(&[sequence_hash; 32]).into()
+    }
+
+    fn commit(contract: &mut MessageRepository, sequence_hash: u8, message: u8) {
+        contract.commit(
+            digest(sequence_hash, message),
+            sequence_key(sequence_hash),
+            None,
+        );
+    }
+
+    fn reveal(contract: &mut MessageRepository, sequence_hash: u8, message: u8) {
+        contract.reveal(
+            vec![sequence_hash; 32].into(),
+            vec![message; 16].into(),
+            vec![].into(),
+            None,
+            None,
+            None,
+        );
+    }
+
+    fn publish(contract: &mut MessageRepository, sequence_hash: u8) {
+        contract.publish(
+            vec![sequence_hash; 32].into(),
+            vec![0; 16].into(),
+            None,
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn rate_limit_resets_each_window() {
+        let mut contract = repository(Some(RateLimit {
+            max_messages: 2,
+            window_ms: 1000,
+        }));
+
+        set_context(alice(), 1000);
+        publish(&mut contract, 1);
+        publish(&mut contract, 2);
+
+        set_context("bob.near".parse().unwrap(), 1999);
+        publish(&mut contract, 3);
+
+        set_context(alice(), 2000);
+        publish(&mut contract, 4);
+        publish(&mut contract, 5);
+    }
+
+    #[test]
+    #[should_panic = "Rate limit exceeded."]
+    fn rate_limit_counts_batches() {
+        let mut contract = repository(Some(RateLimit {
+            max_messages: 2,
+            window_ms: 1000,
+        }));
+
+        set_context(alice(), 1000);
+        publish(&mut contract, 1);
+        contract.publish_batch(
+            (2..4)
+                .map(|i| PublishItem {
+                    sequence_hash: vec![i; 32].into(),
+                    message: vec![0; 16].into(),
+                    proof: None,
+                    deletion_key_hash: None,
+                    previous_sequence_hash: None,
+                })
+                .collect(),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn pause_and_unpause() {
+        let mut contract = repository(None);
+
+        contract.pause(Some(PausableMethod::Commit));
+        contract.pause(None);
+        assert!(contract.get_paused());
+        assert_eq!(contract.get_paused_methods(), vec![PausableMethod::Commit]);
+
+        contract.unpause(None);
+        set_context(alice(), 0);
+        publish(&mut contract, 1);
+    }
+
+    #[test]
+    #[should_panic = "Method is paused."]
+    fn paused_method_is_rejected() {
+        let mut contract = repository(None);
+
+        contract.pause(Some(PausableMethod::Publish));
+        set_context(alice(), 0);
+        publish(&mut contract, 1);
+    }
+
+    #[test]
+    #[should_panic = "Contract is paused."]
+    fn paused_contract_rejects_reveals() {
+        let mut contract = repository(None);
+
+        contract.pause(None);
+        set_context(alice(), 0);
+        contract.reveal(
+            vec![1; 32].into(),
+            vec![0; 16].into(),
+            vec![0; 16].into(),
+            None,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic = "Owner only"]
+    fn only_owner_can_pause() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        contract.pause(None);
+    }
+
+    #[test]
+    fn private_repository_accepts_publishers() {
+        let mut contract = repository(None);
+
+        contract.set_access_mode(AccessMode::Private);
+        contract.grant_role(alice(), Role::Publisher);
+        assert_eq!(contract.get_roles(alice()), vec![Role::Publisher]);
+        assert!(contract.can_publish(alice()));
+        assert!(!contract.can_publish(owner()));
+
+        set_context(alice(), 0);
+        publish(&mut contract, 1);
+
+        set_context(owner(), 0);
+        contract.revoke_role(alice(), Role::Publisher);
+        assert_eq!(contract.get_roles(alice()), vec![]);
+        assert!(!contract.can_publish(alice()));
+
+        contract.set_access_mode(AccessMode::Public);
+        assert!(contract.can_publish(alice()));
+    }
+
+    #[test]
+    #[should_panic = "Account is not allowed to publish."]
+    fn private_repository_rejects_other_accounts() {
+        let mut contract = repository(None);
+
+        contract.set_access_mode(AccessMode::Private);
+        contract.grant_role(alice(), Role::Publisher);
+
+        set_context("bob.near".parse().unwrap(), 0);
+        commit(&mut contract, 1, 0);
+    }
+
+    #[test]
+    fn reveal_publishes_committed_message() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        commit(&mut contract, 1, 0);
+
+        set_block_context(alice(), 1);
+        reveal(&mut contract, 1, 0);
+
+        assert!(contract.get_message(vec![1; 32].into(), None).is_some());
+        assert_eq!(
+            contract.get_commitment(digest(1, 0), Some(sequence_key(1))),
+            None
+        );
+    }
+
+    #[test]
+    fn other_commitments_do_not_block_reveal() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        commit(&mut contract, 1, 0);
+
+        // commitments to other messages under the same sequence key
+        set_context("bob.near".parse().unwrap(), 0);
+        commit(&mut contract, 1, 1);
+        commit(&mut contract, 1, 2);
+
+        set_block_context(alice(), 1);
+        reveal(&mut contract, 1, 0);
+
+        assert!(contract.get_message(vec![1; 32].into(), None).is_some());
+    }
+
+    #[test]
+    #[should_panic = "Commitment belongs to another account."]
+    fn earliest_commitment_to_message_wins() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        commit(&mut contract, 1, 0);
+
+        set_context("bob.near".parse().unwrap(), 0);
+        commit(&mut contract, 1, 0);
+
+        set_block_context("bob.near".parse().unwrap(), 1);
+        reveal(&mut contract, 1, 0);
+    }
+
+    #[test]
+    fn expired_commitments_are_removed_on_commit() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        commit(&mut contract, 1, 0);
+
+        set_context("bob.near".parse().unwrap(), COMMITMENT_TTL_MS);
+        commit(&mut contract, 1, 1);
+
+        assert_eq!(
+            contract.get_commitment(digest(1, 0), Some(sequence_key(1))),
+            None
+        );
+        let commitment = contract
+            .get_commitment(digest(1, 1), Some(sequence_key(1)))
+            .unwrap();
+        assert_eq!(commitment.account_id, "bob.near");
+    }
+
+    #[test]
+    #[should_panic = "Commitment has not expired."]
+    fn only_committer_can_remove_live_commitment() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        commit(&mut contract, 1, 0);
+
+        set_context("bob.near".parse().unwrap(), 0);
+        contract.remove_commitment_and_refund(digest(1, 0), Some(sequence_key(1)));
+    }
+
+    #[test]
+    fn sponsored_publish_charges_relayer() {
+        let mut contract = repository(None);
+        let relayer: AccountId = "relayer.near".parse().unwrap();
+
+        set_context(relayer.clone(), 0);
+        contract.storage_deposit(None, None);
+        let initial = contract.storage_balance_of(relayer.clone()).unwrap();
+
+        let quote = contract.quote_publish(16, relayer.clone(), None, None);
+        set_relayed_context(alice(), relayer.clone());
+        contract.publish(
+            vec![1; 32].into(),
+            vec![0; 16].into(),
+            None,
+            Some(relayer.clone()),
+            None,
+            None,
+        );
+
+        let charged = contract.storage_balance_of(relayer).unwrap();
+        assert_eq!(
+            charged.total,
+            initial.total.saturating_sub(quote.aggregator_fee)
+        );
+        assert!(charged.available < initial.available);
+        assert_eq!(contract.storage_balance_of(alice()), None);
+    }
+
+    #[test]
+    #[should_panic = "Sponsor must sign the transaction."]
+    fn sponsor_must_sign() {
+        let mut contract = repository(None);
+
+        set_relayed_context(alice(), alice());
+        contract.publish(
+            vec![1; 32].into(),
+            vec![0; 16].into(),
+            None,
+            Some("relayer.near".parse().unwrap()),
+            None,
+            None,
+        );
+    }
+
+    fn publish_deletable(contract: &mut MessageRepository, sponsor: Option<AccountId>) {
+        contract.publish(
+            vec![1; 32].into(),
+            vec![0; 16].into(),
+            None,
+            sponsor,
+            Some(env::sha256(&[7; 32]).into()),
+            None,
+        );
+    }
+
+    #[test]
+    fn delete_refunds_storage_balance() {
+        let mut contract = repository(None);
+        let relayer: AccountId = "relayer.near".parse().unwrap();
+
+        set_context(relayer.clone(), 0);
+        contract.storage_deposit(None, None);
+        set_relayed_context(alice(), relayer.clone());
+        publish_deletable(&mut contract, Some(relayer.clone()));
+        let charged = contract.storage_balance_of(relayer.clone()).unwrap();
+        assert_eq!(
+            contract.get_deletion_key(vec![1; 32].into()).unwrap().payer,
+            MessagePayer::StorageBalance {
+                account_id: relayer.clone(),
+            },
+        );
+
+        set_context("bob.near".parse().unwrap(), 0);
+        contract.delete(vec![1; 32].into(), vec![7; 32].into(), None);
+
+        assert_eq!(contract.get_message(vec![1; 32].into(), None), None);
+        assert_eq!(contract.get_deletion_key(vec![1; 32].into()), None);
+        assert!(contract.is_deleted(vec![1; 32].into()));
+        assert_eq!(
+            contract.are_deleted(vec![vec![1; 32].into(), vec![2; 32].into()]),
+            vec![true, false],
+        );
+        assert!(contract.storage_balance_of(relayer).unwrap().available > charged.available);
+    }
+
+    #[test]
+    #[should_panic = "Sequence hash was deleted."]
+    fn deleted_sequence_hash_cannot_be_reused() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        publish_deletable(&mut contract, None);
+        contract.delete(vec![1; 32].into(), vec![7; 32].into(), None);
+        publish(&mut contract, 1);
+    }
+
+    #[test]
+    #[should_panic = "Invalid deletion key."]
+    fn delete_requires_deletion_key() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        publish_deletable(&mut contract, None);
+        contract.delete(vec![1; 32].into(), vec![8; 32].into(), None);
+    }
+
+    #[test]
+    #[should_panic = "Message cannot be deleted."]
+    fn messages_without_deletion_key_are_kept() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        publish(&mut contract, 1);
+        contract.delete(vec![1; 32].into(), vec![7; 32].into(), None);
+    }
+
+    fn publish_after(contract: &mut MessageRepository, previous: u8, sequence_hash: u8) {
+        contract.publish_after(chained_item(previous, sequence_hash), None, None);
+    }
+
+    fn chained_item(previous: u8, sequence_hash: u8) -> PublishItem {
+        PublishItem {
+            sequence_hash: vec![sequence_hash; 32].into(),
+            message: vec![0; 16].into(),
+            proof: None,
+            deletion_key_hash: None,
+            previous_sequence_hash: Some(vec![previous; 32].into()),
+        }
+    }
+
+    #[test]
+    fn publish_after_extends_chain() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        publish_deletable(&mut contract, None);
+        publish_after(&mut contract, 1, 2);
+        // the second item follows the first, which is not stored yet
+        contract.publish_batch(vec![chained_item(2, 3), chained_item(3, 4)], None, None);
+
+        // a deleted predecessor still counts
+        contract.delete(vec![1; 32].into(), vec![7; 32].into(), None);
+        publish_after(&mut contract, 1, 5);
+
+        for i in 2..=5 {
+            assert!(contract.get_message(vec![i; 32].into(), None).is_some());
+        }
+    }
+
+    #[test]
+    #[should_panic = "Previous message not found."]
+    fn publish_after_requires_previous() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        publish(&mut contract, 1);
+        publish_after(&mut contract, 2, 3);
+    }
+
+    #[test]
+    #[should_panic = "Previous message not found."]
+    fn publish_batch_requires_previous_earlier_in_batch() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        contract.publish_batch(vec![chained_item(4, 3), chained_item(2, 4)], None, None);
+    }
+
+    #[test]
+    fn quote_matches_charged_storage() {
+        let mut contract = repository(Some(RateLimit {
+            max_messages: 10,
+            window_ms: 1000,
+        }));
+        set_context(alice(), 0);
+        contract.storage_deposit(None, None);
+        let initial = contract.storage_balance_of(alice()).unwrap();
+
+        let quote = contract.quote_publish(16, alice(), Some(true), None);
+        set_relayed_context(alice(), alice());
+        publish_deletable(&mut contract, None);
+
+        let charged = contract.storage_balance_of(alice()).unwrap();
+        assert_eq!(
+            initial.available.saturating_sub(charged.available),
+            quote.total
+        );
+        assert!(contract
+            .quote_publish(16, alice(), Some(true), None)
+            .rate_limit_storage_fee
+            .is_zero());
+
+        let stats = contract.get_stats(None);
+        assert_eq!(stats.message_count, Some(1));
+        assert_eq!(stats.aggregator_count, 1);
+        assert_eq!(stats.current_aggregator_item_count, 1);
+
+        contract.delete(vec![1; 32].into(), vec![7; 32].into(), None);
+        assert_eq!(contract.get_stats(None).message_count, Some(0));
+    }
+
+    fn tenant() -> String {
+        "tenant".to_string()
+    }
+
+    fn namespaced_repository() -> MessageRepository {
+        let mut contract = repository(None);
+        contract.create_namespace(
+            tenant(),
+            NamespaceConfig {
+                aggregator_config: AggregatorConfig {
+                    capacity: 2,
+                    fingerprint_bits: 8,
+                    filter_kind: FilterKind::Xor,
+                },
+                payload_size_classes: vec![16, 16, 32],
+                publish_fee: NearToken::from_millinear(1),
+            },
+        );
+        contract
+    }
+
+    fn publish_namespaced(contract: &mut MessageRepository, sequence_hash: u8) {
+        contract.publish(
+            vec![sequence_hash; 32].into(),
+            vec![sequence_hash; 16].into(),
+            None,
+            None,
+            None,
+            Some(tenant()),
+        );
+    }
+
+    #[test]
+    fn aggregators_since_shim() {
+        let mut contract = namespaced_repository();
+
+        set_context(alice(), 1000);
+        for i in 1..=3 {
+            publish_namespaced(&mut contract, i);
+        }
+        let page = contract.get_aggregators(None, None, None, Some(tenant()));
+        assert_eq!(
+            contract.get_aggregators_since(0, Some(tenant())),
+            page.aggregators
+                .into_iter()
+                .map(|a| a.filter)
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            contract.get_aggregators_since(1001, Some(tenant())).len(),
+            1
+        );
+        assert_eq!(contract.get_aggregators_since(0, None).len(), 1);
+    }
+
+    #[test]
+    #[should_panic = "Namespace tenant does not support this method."]
+    fn merkle_roots_reject_namespaces() {
+        let contract = namespaced_repository();
+
+        contract.get_merkle_root(0, Some(tenant()));
+    }
+
+    #[test]
+    fn namespaces_are_isolated() {
+        let mut contract = namespaced_repository();
+        assert_eq!(
+            contract
+                .get_namespace_config(tenant())
+                .unwrap()
+                .payload_size_classes,
+            vec![16, 32],
+        );
+
+        set_context(alice(), 1000);
+        publish(&mut contract, 1);
+        for i in 1..=3 {
+            publish_namespaced(&mut contract, i);
+        }
+
+        assert_eq!(
+            contract
+                .get_message(vec![1; 32].into(), None)
+                .unwrap()
+                .message,
+            vec![0; 16].into(),
+        );
+        assert_eq!(
+            contract
+                .get_message(vec![1; 32].into(), Some(tenant()))
+                .unwrap()
+                .message,
+            vec![1; 16].into(),
+        );
+        assert_eq!(contract.get_message(vec![2; 32].into(), None), None);
+        assert_eq!(contract.get_stats(None).message_count, Some(1));
+        assert_eq!(contract.get_stats(Some(tenant())).message_count, Some(3));
+
+        let page = contract.get_aggregators(None, None, None, Some(tenant()));
+        assert_eq!(
+            page.aggregators
+                .iter()
+                .map(|a| (a.end_block_timestamp_ms, a.item_count))
+                .collect::<Vec<_>>(),
+            vec![(Some(1000), 2), (None, 1)],
+        );
+        assert_eq!(page.aggregators[0].filter_parameters.capacity, 2);
+        assert_eq!(
+            contract
+                .get_aggregators(None, None, None, None)
+                .aggregators
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn namespace_quote_includes_publish_fee() {
+        let mut contract = namespaced_repository();
+        set_context(alice(), 0);
+        contract.storage_deposit(None, None);
+        let initial = contract.storage_balance_of(alice()).unwrap();
+
+        let quote = contract.quote_publish(16, alice(), None, Some(tenant()));
+        assert_eq!(quote.publish_fee, NearToken::from_millinear(1));
+        set_relayed_context(alice(), alice());
+        publish_namespaced(&mut contract, 1);
+
+        let charged = contract.storage_balance_of(alice()).unwrap();
+        assert_eq!(
+            initial.available.saturating_sub(charged.available),
+            quote.total
+        );
+        assert_eq!(contract.get_treasury(), NearToken::from_millinear(1));
+    }
+
+    #[test]
+    fn service_fee_goes_to_treasury() {
+        let mut contract = repository(None);
+        contract.set_service_fee(Some(ServiceFee {
+            per_message: NearToken::from_millinear(1),
+            per_byte: NearToken::from_yoctonear(1000),
+        }));
+        set_context(alice(), 0);
+        contract.storage_deposit(None, None);
+        let initial = contract.storage_balance_of(alice()).unwrap();
+
+        let quote = contract.quote_publish(16, alice(), None, None);
+        let service_fee =
+            NearToken::from_millinear(1).saturating_add(NearToken::from_yoctonear(16_000));
+        assert_eq!(quote.service_fee, service_fee);
+        set_relayed_context(alice(), alice());
+        publish(&mut contract, 1);
+
+        let charged = contract.storage_balance_of(alice()).unwrap();
+        assert_eq!(
+            initial.available.saturating_sub(charged.available),
+            quote.total
+        );
+        assert_eq!(contract.get_treasury(), service_fee);
+
+        set_context(owner(), 0);
+        contract.withdraw_treasury(service_fee);
+        assert!(contract.get_treasury().is_zero());
+    }
+
+    #[test]
+    fn storage_balance_fees_leave_the_balance() {
+        let mut contract = repository(None);
+        contract.set_service_fee(Some(ServiceFee {
+            per_message: NearToken::from_millinear(1),
+            per_byte: NearToken::from_yoctonear(1000),
+        }));
+        set_context(alice(), 0);
+        contract.storage_deposit(None, None);
+        let initial = contract.storage_balance_of(alice()).unwrap();
+
+        let quote = contract.quote_publish(16, alice(), Some(true), None);
+        set_relayed_context(alice(), alice());
+        publish_deletable(&mut contract, None);
+        contract.delete(vec![1; 32].into(), vec![7; 32].into(), None);
+
+        set_context(owner(), 0);
+        contract.withdraw_treasury(quote.service_fee);
+
+        // the fees were paid out of the balance, so they are not refunded
+        let balance = contract.storage_balance_of(alice()).unwrap();
+        assert_eq!(
+            balance.total,
+            initial
+                .total
+                .saturating_sub(quote.aggregator_fee)
+                .saturating_sub(quote.service_fee),
+        );
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("repository.near".parse().unwrap())
+            .predecessor_account_id(alice())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        // the tombstone of the deleted message stays locked
+        assert!(contract.storage_unregister(Some(true)));
+        assert_eq!(contract.storage_balance_of(alice()), None);
+    }
+
+    #[test]
+    #[should_panic = "Treasury is too small."]
+    fn treasury_withdrawal_is_capped() {
+        let mut contract = repository(None);
+
+        contract.withdraw_treasury(NearToken::from_yoctonear(1));
+    }
+
+    #[test]
+    #[should_panic = "Message length is not an allowed payload size class."]
+    fn namespace_payload_size_classes() {
+        let mut contract = namespaced_repository();
+        contract.update_namespace(tenant(), Some(vec![32]), None);
+
+        set_context(alice(), 0);
+        publish_namespaced(&mut contract, 1);
+    }
+
+    #[test]
+    #[should_panic = "Namespaced messages cannot be deleted."]
+    fn namespaced_messages_cannot_be_deleted() {
+        let mut contract = namespaced_repository();
+
+        set_context(alice(), 0);
+        contract.publish(
+            vec![1; 32].into(),
+            vec![0; 16].into(),
+            None,
+            None,
+            Some(env::sha256(&[7; 32]).into()),
+            Some(tenant()),
+        );
+    }
+
+    #[test]
+    #[should_panic = "Namespace not found."]
+    fn unknown_namespace() {
+        let mut contract = repository(None);
+
+        set_context(alice(), 0);
+        publish_namespaced(&mut contract, 1);
+    }
+
+    #[test]
+    fn borsh_views_match_json_views() {
+        let mut contract = repository(None);
+        set_context(alice(), 500);
+        publish(&mut contract, 1);
+
+        let message = contract.get_message_borsh(vec![1; 32].into(), None);
+        assert_eq!(
+            Option::<Message>::try_from_slice(&borsh::to_vec(&message).unwrap()).unwrap(),
+            contract.get_message(vec![1; 32].into(), None),
+        );
+        let page = contract.get_aggregators_borsh(None, None, None, None);
+        assert_eq!(
+            AggregatorPage::try_from_slice(&borsh::to_vec(&page).unwrap()).unwrap(),
+            contract.get_aggregators(None, None, None, None),
+        );
+    }
+
+    #[test]
+    fn merkle_proofs_of_sealed_aggregators() {
+        set_context(owner(), 0);
+        let mut contract = MessageRepository::new(None, None, None, None, Some(1000), None);
+
+        set_context(alice(), 500);
+        for sequence_hash in 1..=3 {
+            publish(&mut contract, sequence_hash);
+        }
+        assert_eq!(contract.get_merkle_root(0, None), None);
+        set_context(alice(), 1500);
+        publish(&mut contract, 4);
+
+        let leaves = (1..=3)
+            .map(|i| MerkleLeaf::new(&[i; 32], &[0; 16]))
+            .collect::<Vec<_>>();
+        let mut sorted = leaves.clone();
+        sorted.sort_unstable();
+        assert_eq!(
+            contract.get_merkle_root(0, None).unwrap().0,
+            merkle::root(&sorted).to_vec(),
+        );
+        assert_eq!(contract.get_merkle_root(1, None), None);
+
+        let Some(MerkleProof::Inclusion { leaf_count, leaf }) =
+            contract.get_merkle_proof(0, vec![2; 32].into(), None)
+        else {
+            panic!("expected an inclusion proof");
+        };
+        assert_eq!(leaf_count, 3);
+        assert_eq!(leaf.key.0, leaves[1].key.to_vec());
+        assert!(matches!(
+            contract.get_merkle_proof(0, vec![4; 32].into(), None),
+            Some(MerkleProof::NonInclusion { leaf_count: 3, .. }),
+        ));
+        assert_eq!(contract.get_merkle_proof(1, vec![4; 32].into(), None), None);
+    }
+
+    #[test]
+    fn merkle_gas_at_full_capacity() {
+        // both calls must leave room for the wasm execution that the mocked
+        // blockchain does not charge for
+        let budget = Gas::from_tgas(150);
+
+        set_context(owner(), 0);
+        let config = AggregatorConfig {
+            capacity: filter::MAX_AGGREGATOR_CAPACITY,
+            ..Default::default()
+        };
+        let mut contract = MessageRepository::new(None, None, None, None, None, Some(config));
+
+        let sequence_hash = |i: u64| Base64VecU8([&i.to_le_bytes()[..], &[0; 24]].concat());
+        let mut i = 0;
+        while contract
+            .get_aggregators(None, None, None, None)
+            .aggregators
+            .len()
+            == 1
+        {
+            set_context(alice(), 0);
+            contract.publish(sequence_hash(i), vec![0; 16].into(), None, None, None, None);
+            assert!(env::used_gas() < budget, "sealing used {}", env::used_gas());
+            i += 1;
+        }
+        let item_count = contract.get_aggregators(None, None, None, None).aggregators[0].item_count;
+        assert!(item_count * 10 > filter::MAX_AGGREGATOR_CAPACITY * 9);
+
+        set_context(alice(), 0);
+        assert!(matches!(
+            contract.get_merkle_proof(0, sequence_hash(0), None),
+            Some(MerkleProof::Inclusion { .. }),
+        ));
+        assert!(env::used_gas() < budget, "proving used {}", env::used_gas());
+    }
+
+    #[test]
+    #[should_panic = "Aggregator capacity is out of range."]
+    fn aggregator_capacity_is_capped() {
+        set_context(owner(), 0);
+        MessageRepository::new(
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(AggregatorConfig {
+                capacity: filter::MAX_AGGREGATOR_CAPACITY + 1,
+                ..Default::default()
+            }),
+        );
+    }
+
+    #[test]
+    fn publish_and_seal_events() {
+        set_context(owner(), 0);
+        let mut contract = MessageRepository::new(None, None, None, None, Some(1000), None);
+
+        set_context(alice(), 500);
+        publish(&mut contract, 1);
+        set_context(alice(), 1500);
+        publish(&mut contract, 2);
+        let merkle_root = merkle::root(&[MerkleLeaf::new(&[1; 32], &[0; 16])]);
+
+        let events = get_logs()
+            .iter()
+            .map(|log| {
+                serde_json::from_str::<serde_json::Value>(log.strip_prefix("EVENT_JSON:").unwrap())
+                    .unwrap()
+            })
+            .filter(|event| event["standard"] == "x-message-repository")
+            .map(|event| (event["event"].clone(), event["data"].clone()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            events,
+            vec![
+                (
+                    "aggregator_sealed".into(),
+                    json!({
+                        "index": 0,
+                        "end_block_timestamp_ms": 999,
+                        "merkle_root": Base64VecU8(merkle_root.to_vec()),
+                        "namespace": null,
+                    }),
+                ),
+                (
+                    "publish".into(),
+                    json!({
+                        "sequence_hash": Base64VecU8(vec![2; 32]),
+                        "block_height": 0,
+                        "message_length": 16,
+                        "aggregator_index": 1,
+                        "namespace": null,
+                    }),
+                ),
+            ],
+        );
+    }
+
+    fn solve_pow(contract: &MessageRepository, sequence_hash: &[u8], message: &[u8]) -> u64 {
+        let challenge = contract.get_pow_challenge().unwrap();
+        let challenge: [u8; 32] = challenge.challenge.0.try_into().unwrap();
+        let difficulty = contract.get_pow_challenge().unwrap().difficulty;
+        (0..)
+            .find(|nonce| {
+                pow::leading_zero_bits(&pow::pow_hash(&challenge, sequence_hash, message, *nonce))
+                    >= difficulty as u32
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn pow_publish_charges_sponsor_pool() {
+        let mut contract = repository(None);
+        contract.set_pow_difficulty(Some(8));
+        contract.fund_sponsor_pool();
+        assert_eq!(contract.get_sponsor_pool(), NearToken::from_near(1));
+
+        set_relayed_context(alice(), alice());
+        let nonce = solve_pow(&contract, &[1; 32], &[0; 16]);
+        contract.publish_with_pow(
+            vec![1; 32].into(),
+            vec![0; 16].into(),
+            None,
+            nonce.into(),
+            None,
+            None,
+        );
+
+        assert!(contract.get_message(vec![1; 32].into(), None).is_some());
+        assert!(contract.get_sponsor_pool() < NearToken::from_near(1));
+        assert_eq!(contract.storage_balance_of(alice()), None);
+    }
+
+    #[test]
+    #[should_panic = "Invalid proof of work."]
+    fn pow_publish_rejects_invalid_proof() {
+        let mut contract = repository(None);
+        contract.set_pow_difficulty(Some(64));
+        contract.fund_sponsor_pool();
+
+        set_relayed_context(alice(), alice());
+        contract.publish_with_pow(
+            vec![1; 32].into(),
+            vec![0; 16].into(),
+            None,
+            0.into(),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic = "Sponsor pool is exhausted."]
+    fn pow_publish_requires_sponsor_pool() {
+        let mut contract = repository(None);
+        contract.set_pow_difficulty(Some(1));
+
+        set_relayed_context(alice(), alice());
+        let nonce = solve_pow(&contract, &[1; 32], &[0; 16]);
+        contract.publish_with_pow(
+            vec![1; 32].into(),
+            vec![0; 16].into(),
+            None,
+            nonce.into(),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic = "Proof-of-work publishing is disabled."]
+    fn pow_publish_is_disabled_by_default() {
+        let mut contract = repository(None);
+        contract.fund_sponsor_pool();
+
+        set_relayed_context(alice(), alice());
+        contract.publish_with_pow(
+            vec![1; 32].into(),
+            vec![0; 16].into(),
+            None,
+            0.into(),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic = "Sequence hash belongs to another shard."]
+    fn shard_range_rejects_other_prefixes() {
+        let mut contract = repository(None);
+        contract.set_shard_range(Some(PrefixRange {
+            start: 0,
+            end: 0x7fff_ffff,
+        }));
+
+        set_context(alice(), 0);
+        publish(&mut contract, 0x7f);
+        publish(&mut contract, 0x80);
+    }
+
+    #[test]
+    fn configured_capacity_and_fingerprints() {
+        set_context(owner(), 0);
+        let config = AggregatorConfig {
+            capacity: 2,
+            fingerprint_bits: 16,
+            filter_kind: FilterKind::Xor,
+        };
+        let mut contract = MessageRepository::new(None, None, None, None, None, Some(config));
+        assert_eq!(contract.get_config(), config);
+
+        set_context(alice(), 0);
+        for i in 1..=3 {
+            publish(&mut contract, i);
+        }
+
+        let page = contract.get_aggregators(None, None, None, None);
+        assert_eq!(page.aggregators.len(), 2);
+        assert_eq!(page.aggregators[0].item_count, 2);
+        assert_eq!(page.aggregators[0].filter_parameters.fingerprint_bits, 16);
+        // a sealed 16-bit xor filter
+        assert_eq!(page.aggregators[0].filter.0[1], 4);
+    }
+
+    #[test]
+    #[should_panic = "Cuckoo filters only support 8-bit fingerprints."]
+    fn cuckoo_fingerprints_are_fixed() {
+        set_context(owner(), 0);
+        MessageRepository::new(
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(AggregatorConfig {
+                fingerprint_bits: 16,
+                ..Default::default()
+            }),
+        );
     }
 }
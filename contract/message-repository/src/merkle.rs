@@ -0,0 +1,241 @@
+//! Sorted Merkle trees over the messages of each sealed aggregator.
+//!
+//! Each message is a leaf `sha256(0x00 | key | message_hash)`, where `key`
+//! is `sha256(sequence_hash)` and `message_hash` is `sha256(message)`. Leaves
+//! are sorted by key, and each level pairs neighbours as
+//! `sha256(0x01 | left | right)`, promoting an unpaired last node unchanged.
+//! The committed root is `sha256(0x02 | leaf_count | tree_root)` with a
+//! little-endian `u32` count, and the tree root of an empty tree is zero.
+//!
+//! Since leaves are sorted, the two leaves around a key that is not in the
+//! tree prove that it is absent.
+
+use near_sdk::{env, json_types::Base64VecU8, near};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[near]
+pub struct MerkleLeaf {
+    pub key: [u8; 32],
+    pub message_hash: [u8; 32],
+}
+
+impl MerkleLeaf {
+    pub fn new(sequence_hash: &[u8], message: &[u8]) -> Self {
+        Self {
+            key: sha256(sequence_hash),
+            message_hash: sha256(message),
+        }
+    }
+
+    fn hash(&self) -> [u8; 32] {
+        sha256(&[&[0][..], &self.key, &self.message_hash].concat())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [json])]
+pub struct MerkleLeafProof {
+    /// The position of the leaf in key order.
+    pub position: u32,
+    pub key: Base64VecU8,
+    pub message_hash: Base64VecU8,
+    /// Bottom up, skipping the levels at which the path has no sibling.
+    pub siblings: Vec<Base64VecU8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[near(serializers = [json])]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MerkleProof {
+    Inclusion {
+        leaf_count: u32,
+        leaf: MerkleLeafProof,
+    },
+    /// The leaves on either side of the missing key, of which the first or
+    /// last leaf has only one.
+    NonInclusion {
+        leaf_count: u32,
+        predecessor: Option<MerkleLeafProof>,
+        successor: Option<MerkleLeafProof>,
+    },
+}
+
+fn sha256(bytes: &[u8]) -> [u8; 32] {
+    env::sha256_array(bytes)
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    sha256(&[&[1][..], left, right].concat())
+}
+
+fn root_hash(leaf_count: u32, tree_root: &[u8; 32]) -> [u8; 32] {
+    sha256(&[&[2][..], &leaf_count.to_le_bytes(), tree_root].concat())
+}
+
+/// Every level of the tree over `leaves`, which must be sorted, from the
+/// leaves up to the tree root.
+fn levels(leaves: &[MerkleLeaf]) -> Vec<Vec<[u8; 32]>> {
+    let mut levels = vec![leaves.iter().map(MerkleLeaf::hash).collect::<Vec<_>>()];
+    while levels.last().unwrap().len() > 1 {
+        let next = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+fn tree_root(levels: &[Vec<[u8; 32]>]) -> [u8; 32] {
+    levels.last().unwrap().first().copied().unwrap_or([0; 32])
+}
+
+/// The committed root of `leaves`, which must be sorted.
+pub fn root(leaves: &[MerkleLeaf]) -> [u8; 32] {
+    root_hash(leaves.len() as u32, &tree_root(&levels(leaves)))
+}
+
+fn leaf_proof(levels: &[Vec<[u8; 32]>], leaf: &MerkleLeaf, position: usize) -> MerkleLeafProof {
+    let mut siblings = vec![];
+    let mut index = position;
+    for level in &levels[..levels.len() - 1] {
+        if let Some(sibling) = level.get(index ^ 1) {
+            siblings.push(sibling.to_vec().into());
+        }
+        index /= 2;
+    }
+
+    MerkleLeafProof {
+        position: position as u32,
+        key: leaf.key.to_vec().into(),
+        message_hash: leaf.message_hash.to_vec().into(),
+        siblings,
+    }
+}
+
+/// Proves whether `sequence_hash` is among `leaves`, which must be sorted.
+pub fn prove(leaves: &[MerkleLeaf], sequence_hash: &[u8]) -> MerkleProof {
+    let levels = levels(leaves);
+    let leaf_count = leaves.len() as u32;
+    let key = sha256(sequence_hash);
+
+    match leaves.binary_search_by(|leaf| leaf.key.cmp(&key)) {
+        Ok(position) => MerkleProof::Inclusion {
+            leaf_count,
+            leaf: leaf_proof(&levels, &leaves[position], position),
+        },
+        Err(position) => MerkleProof::NonInclusion {
+            leaf_count,
+            predecessor: position
+                .checked_sub(1)
+                .map(|p| leaf_proof(&levels, &leaves[p], p)),
+            successor: leaves
+                .get(position)
+                .map(|leaf| leaf_proof(&levels, leaf, position)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn leaves(count: u8) -> Vec<MerkleLeaf> {
+        let mut leaves = (0..count)
+            .map(|i| MerkleLeaf::new(&[i; 32], &[i; 64]))
+            .collect::<Vec<_>>();
+        leaves.sort_unstable();
+        leaves
+    }
+
+    /// Recomputes the tree root from a leaf proof, as the client does.
+    fn path_root(leaf_count: u32, proof: &MerkleLeafProof) -> [u8; 32] {
+        let leaf = MerkleLeaf {
+            key: proof.key.0.clone().try_into().unwrap(),
+            message_hash: proof.message_hash.0.clone().try_into().unwrap(),
+        };
+        let mut hash = leaf.hash();
+        let mut siblings = proof.siblings.iter();
+        let (mut index, mut len) = (proof.position, leaf_count);
+        while len > 1 {
+            if index ^ 1 < len {
+                let sibling: [u8; 32] = siblings.next().unwrap().0.clone().try_into().unwrap();
+                hash = if index % 2 == 0 {
+                    node_hash(&hash, &sibling)
+                } else {
+                    node_hash(&sibling, &hash)
+                };
+            }
+            index /= 2;
+            len = len.div_ceil(2);
+        }
+        assert!(siblings.next().is_none());
+        root_hash(leaf_count, &hash)
+    }
+
+    // The client's verifier tests use the same tree.
+    #[test]
+    fn root_vector() {
+        assert_eq!(
+            hex(&root(&[])),
+            "22689034dd7977d019ec4fc2606eddaad978a0e5e7cba9a2712a549341c46ca9",
+        );
+        assert_eq!(
+            hex(&root(&leaves(5))),
+            "564e52baca2ebecc444e683283c8ea594556c501fbf517eadd9340f061fb95c4",
+        );
+    }
+
+    #[test]
+    fn inclusion_proofs() {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let root = root(&leaves);
+            for i in 0..count {
+                let MerkleProof::Inclusion { leaf_count, leaf } = prove(&leaves, &[i; 32]) else {
+                    panic!("expected an inclusion proof");
+                };
+                assert_eq!(leaf.message_hash.0, sha256(&[i; 64]));
+                assert_eq!(path_root(leaf_count, &leaf), root);
+            }
+        }
+    }
+
+    #[test]
+    fn non_inclusion_proofs() {
+        for count in 0..=9 {
+            let leaves = leaves(count);
+            let root = root(&leaves);
+            for missing in 100..120 {
+                let MerkleProof::NonInclusion {
+                    leaf_count,
+                    predecessor,
+                    successor,
+                } = prove(&leaves, &[missing; 32])
+                else {
+                    panic!("expected a non-inclusion proof");
+                };
+                let key = sha256(&[missing; 32]);
+                assert_eq!(predecessor.is_none() && successor.is_none(), count == 0);
+                if let Some(predecessor) = &predecessor {
+                    assert!(predecessor.key.0.as_slice() < key.as_slice());
+                    assert_eq!(path_root(leaf_count, predecessor), root);
+                }
+                if let Some(successor) = &successor {
+                    assert!(successor.key.0.as_slice() > key.as_slice());
+                    assert_eq!(path_root(leaf_count, successor), root);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1305 @@
+//! State layouts from earlier schema versions, and their conversions to the
+//! current [`MessageRepository`].
+
+use near_sdk::{
+    borsh::BorshDeserialize,
+    collections::{LookupMap, LookupSet, Vector},
+    env, near, AccountId, NearToken,
+};
+use near_sdk_contract_tools::{
+    owner::Owner,
+    standard::nep145::{Nep145Controller, StorageBalanceBounds},
+};
+use siphasher::sip::SipHasher;
+
+use crate::{
+    filter::{
+        AggregatorConfig, BorshCuckooFilter, Filter, FilterKind, DEFAULT_AGGREGATOR_CAPACITY,
+    },
+    get_lazy,
+    groth16::VerifyingKey,
+    pow::PowChallenge,
+    write, AccessMode, AggregatorRecord, Commitment, DeletionKey, Message, MessageRepository,
+    Namespace, PausableMethod, PrefixRange, RateLimit, RateLimitWindow, Role, ServiceFee,
+    StorageKey, MERKLE_LEAF_STORAGE_BYTES, MERKLE_ROOT_STORAGE_BYTES, STORAGE_REGISTRATION_BYTES,
+};
+
+/// A sealed aggregator before filters were versioned, when every aggregator
+/// was a bare cuckoo filter.
+#[near]
+pub struct AggregatorRecordV0 {
+    pub end_block_timestamp_ms: u64,
+    pub aggregator: BorshCuckooFilter<SipHasher>,
+}
+
+/// The layout first deployed, before the schema version was recorded. The
+/// current aggregator is a bare cuckoo filter under
+/// [`StorageKey::CurrentAggregator`].
+#[near]
+pub struct MessageRepositoryV0 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecordV0>,
+    pub aggregator_storage_usage: u64,
+}
+
+/// Version 1 recorded the schema version, and added an owner outside of the
+/// contract struct.
+#[near]
+pub struct MessageRepositoryV1 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub filter_kind: FilterKind,
+}
+
+/// Version 1 also added payload size classes, publish proofs, commit-reveal
+/// and epochs, all initially off, and wrapped every aggregator in a
+/// [`Filter`].
+impl From<MessageRepositoryV0> for MessageRepositoryV1 {
+    fn from(v0: MessageRepositoryV0) -> Self {
+        // the history keeps its prefix, so pushing each converted record
+        // overwrites the original after it has been read
+        let mut aggregator_history = Vector::new(StorageKey::AggregatorHistory);
+        for record in v0.aggregator_history.iter() {
+            aggregator_history.push(&AggregatorRecord {
+                end_block_timestamp_ms: record.end_block_timestamp_ms,
+                aggregator: Filter::Cuckoo(record.aggregator),
+            });
+        }
+
+        let current_aggregator: BorshCuckooFilter<SipHasher> =
+            get_lazy(StorageKey::CurrentAggregator)
+                .unwrap_or_else(|| env::panic_str("Failed to read the current aggregator."));
+        write(
+            StorageKey::CurrentAggregator,
+            Filter::Cuckoo(current_aggregator),
+        );
+
+        Self {
+            messages: v0.messages,
+            aggregator_history,
+            // the filter type takes one more byte
+            aggregator_storage_usage: v0.aggregator_storage_usage + 1,
+            payload_size_classes: vec![],
+            publish_verifying_key: None,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: false,
+            epoch_duration_ms: None,
+            current_epoch: 0,
+            filter_kind: FilterKind::Cuckoo,
+        }
+    }
+}
+
+#[near]
+pub struct MessageRepositoryV2 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub filter_kind: FilterKind,
+    pub paused: bool,
+    pub paused_methods: Vec<PausableMethod>,
+    pub rate_limit: Option<RateLimit>,
+    pub rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+}
+
+/// Version 2 added pausing and rate limits, both initially off.
+impl From<MessageRepositoryV1> for MessageRepositoryV2 {
+    fn from(v1: MessageRepositoryV1) -> Self {
+        Self {
+            messages: v1.messages,
+            aggregator_history: v1.aggregator_history,
+            aggregator_storage_usage: v1.aggregator_storage_usage,
+            payload_size_classes: v1.payload_size_classes,
+            publish_verifying_key: v1.publish_verifying_key,
+            commitments: v1.commitments,
+            require_commitments: v1.require_commitments,
+            epoch_duration_ms: v1.epoch_duration_ms,
+            current_epoch: v1.current_epoch,
+            filter_kind: v1.filter_kind,
+            paused: false,
+            paused_methods: vec![],
+            rate_limit: None,
+            rate_limit_windows: LookupMap::new(StorageKey::RateLimitWindows),
+        }
+    }
+}
+
+#[near]
+pub struct MessageRepositoryV3 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub filter_kind: FilterKind,
+    pub paused: bool,
+    pub paused_methods: Vec<PausableMethod>,
+    pub rate_limit: Option<RateLimit>,
+    pub rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+    pub pow_difficulty: Option<u8>,
+    pub pow_challenge: PowChallenge,
+    pub sponsor_pool: NearToken,
+}
+
+/// Version 3 added proof-of-work publishing, initially disabled, and an
+/// empty sponsor pool.
+impl From<MessageRepositoryV2> for MessageRepositoryV3 {
+    fn from(v2: MessageRepositoryV2) -> Self {
+        Self {
+            messages: v2.messages,
+            aggregator_history: v2.aggregator_history,
+            aggregator_storage_usage: v2.aggregator_storage_usage,
+            payload_size_classes: v2.payload_size_classes,
+            publish_verifying_key: v2.publish_verifying_key,
+            commitments: v2.commitments,
+            require_commitments: v2.require_commitments,
+            epoch_duration_ms: v2.epoch_duration_ms,
+            current_epoch: v2.current_epoch,
+            filter_kind: v2.filter_kind,
+            paused: v2.paused,
+            paused_methods: v2.paused_methods,
+            rate_limit: v2.rate_limit,
+            rate_limit_windows: v2.rate_limit_windows,
+            pow_difficulty: None,
+            pow_challenge: PowChallenge::new(),
+            sponsor_pool: NearToken::from_yoctonear(0),
+        }
+    }
+}
+
+#[near]
+pub struct MessageRepositoryV4 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub filter_kind: FilterKind,
+    pub paused: bool,
+    pub paused_methods: Vec<PausableMethod>,
+    pub rate_limit: Option<RateLimit>,
+    pub rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+    pub pow_difficulty: Option<u8>,
+    pub pow_challenge: PowChallenge,
+    pub sponsor_pool: NearToken,
+    pub shard_range: Option<PrefixRange>,
+}
+
+/// Version 4 added shard ranges. Existing repositories are not sharded.
+impl From<MessageRepositoryV3> for MessageRepositoryV4 {
+    fn from(v3: MessageRepositoryV3) -> Self {
+        Self {
+            messages: v3.messages,
+            aggregator_history: v3.aggregator_history,
+            aggregator_storage_usage: v3.aggregator_storage_usage,
+            payload_size_classes: v3.payload_size_classes,
+            publish_verifying_key: v3.publish_verifying_key,
+            commitments: v3.commitments,
+            require_commitments: v3.require_commitments,
+            epoch_duration_ms: v3.epoch_duration_ms,
+            current_epoch: v3.current_epoch,
+            filter_kind: v3.filter_kind,
+            paused: v3.paused,
+            paused_methods: v3.paused_methods,
+            rate_limit: v3.rate_limit,
+            rate_limit_windows: v3.rate_limit_windows,
+            pow_difficulty: v3.pow_difficulty,
+            pow_challenge: v3.pow_challenge,
+            sponsor_pool: v3.sponsor_pool,
+            shard_range: None,
+        }
+    }
+}
+
+#[near]
+pub struct MessageRepositoryV5 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub aggregator_config: AggregatorConfig,
+    pub paused: bool,
+    pub paused_methods: Vec<PausableMethod>,
+    pub rate_limit: Option<RateLimit>,
+    pub rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+    pub pow_difficulty: Option<u8>,
+    pub pow_challenge: PowChallenge,
+    pub sponsor_pool: NearToken,
+    pub shard_range: Option<PrefixRange>,
+}
+
+/// Version 5 made the aggregator capacity and fingerprint size
+/// configurable. Earlier versions were built with the defaults.
+impl From<MessageRepositoryV4> for MessageRepositoryV5 {
+    fn from(v4: MessageRepositoryV4) -> Self {
+        Self {
+            messages: v4.messages,
+            aggregator_history: v4.aggregator_history,
+            aggregator_storage_usage: v4.aggregator_storage_usage,
+            payload_size_classes: v4.payload_size_classes,
+            publish_verifying_key: v4.publish_verifying_key,
+            commitments: v4.commitments,
+            require_commitments: v4.require_commitments,
+            epoch_duration_ms: v4.epoch_duration_ms,
+            current_epoch: v4.current_epoch,
+            aggregator_config: AggregatorConfig {
+                capacity: DEFAULT_AGGREGATOR_CAPACITY,
+                fingerprint_bits: 8,
+                filter_kind: v4.filter_kind,
+            },
+            paused: v4.paused,
+            paused_methods: v4.paused_methods,
+            rate_limit: v4.rate_limit,
+            rate_limit_windows: v4.rate_limit_windows,
+            pow_difficulty: v4.pow_difficulty,
+            pow_challenge: v4.pow_challenge,
+            sponsor_pool: v4.sponsor_pool,
+            shard_range: v4.shard_range,
+        }
+    }
+}
+
+#[near]
+pub struct MessageRepositoryV6 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub aggregator_config: AggregatorConfig,
+    pub paused: bool,
+    pub paused_methods: Vec<PausableMethod>,
+    pub rate_limit: Option<RateLimit>,
+    pub rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+    pub pow_difficulty: Option<u8>,
+    pub pow_challenge: PowChallenge,
+    pub sponsor_pool: NearToken,
+    pub shard_range: Option<PrefixRange>,
+    pub merkle_roots: LookupMap<u64, [u8; 32]>,
+    pub first_merkle_aggregator: u64,
+}
+
+/// Version 6 added a Merkle root to every sealed aggregator. The current
+/// aggregator is missing the leaves published before the upgrade, so roots
+/// start with the one after it, and the aggregator fee grows to pay for the
+/// leaves.
+impl From<MessageRepositoryV5> for MessageRepositoryV6 {
+    fn from(v5: MessageRepositoryV5) -> Self {
+        let first_merkle_aggregator = v5.aggregator_history.len() + 1;
+        Self {
+            messages: v5.messages,
+            aggregator_history: v5.aggregator_history,
+            aggregator_storage_usage: v5.aggregator_storage_usage
+                + MERKLE_LEAF_STORAGE_BYTES * v5.aggregator_config.capacity
+                + MERKLE_ROOT_STORAGE_BYTES,
+            payload_size_classes: v5.payload_size_classes,
+            publish_verifying_key: v5.publish_verifying_key,
+            commitments: v5.commitments,
+            require_commitments: v5.require_commitments,
+            epoch_duration_ms: v5.epoch_duration_ms,
+            current_epoch: v5.current_epoch,
+            aggregator_config: v5.aggregator_config,
+            paused: v5.paused,
+            paused_methods: v5.paused_methods,
+            rate_limit: v5.rate_limit,
+            rate_limit_windows: v5.rate_limit_windows,
+            pow_difficulty: v5.pow_difficulty,
+            pow_challenge: v5.pow_challenge,
+            sponsor_pool: v5.sponsor_pool,
+            shard_range: v5.shard_range,
+            merkle_roots: LookupMap::new(StorageKey::MerkleRoots),
+            first_merkle_aggregator,
+        }
+    }
+}
+
+#[near]
+pub struct MessageRepositoryV7 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub aggregator_config: AggregatorConfig,
+    pub paused: bool,
+    pub paused_methods: Vec<PausableMethod>,
+    pub rate_limit: Option<RateLimit>,
+    pub rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+    pub pow_difficulty: Option<u8>,
+    pub pow_challenge: PowChallenge,
+    pub sponsor_pool: NearToken,
+    pub shard_range: Option<PrefixRange>,
+    pub merkle_roots: LookupMap<u64, [u8; 32]>,
+    pub first_merkle_aggregator: u64,
+    pub deletion_keys: LookupMap<Vec<u8>, DeletionKey>,
+    pub tombstones: LookupSet<Vec<u8>>,
+}
+
+/// Version 7 added deletion keys. Messages published before it have none,
+/// and are kept forever.
+impl From<MessageRepositoryV6> for MessageRepositoryV7 {
+    fn from(v6: MessageRepositoryV6) -> Self {
+        Self {
+            messages: v6.messages,
+            aggregator_history: v6.aggregator_history,
+            aggregator_storage_usage: v6.aggregator_storage_usage,
+            payload_size_classes: v6.payload_size_classes,
+            publish_verifying_key: v6.publish_verifying_key,
+            commitments: v6.commitments,
+            require_commitments: v6.require_commitments,
+            epoch_duration_ms: v6.epoch_duration_ms,
+            current_epoch: v6.current_epoch,
+            aggregator_config: v6.aggregator_config,
+            paused: v6.paused,
+            paused_methods: v6.paused_methods,
+            rate_limit: v6.rate_limit,
+            rate_limit_windows: v6.rate_limit_windows,
+            pow_difficulty: v6.pow_difficulty,
+            pow_challenge: v6.pow_challenge,
+            sponsor_pool: v6.sponsor_pool,
+            shard_range: v6.shard_range,
+            merkle_roots: v6.merkle_roots,
+            first_merkle_aggregator: v6.first_merkle_aggregator,
+            deletion_keys: LookupMap::new(StorageKey::DeletionKeys),
+            tombstones: LookupSet::new(StorageKey::Tombstones),
+        }
+    }
+}
+
+#[near]
+pub struct MessageRepositoryV8 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub aggregator_config: AggregatorConfig,
+    pub paused: bool,
+    pub paused_methods: Vec<PausableMethod>,
+    pub rate_limit: Option<RateLimit>,
+    pub rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+    pub pow_difficulty: Option<u8>,
+    pub pow_challenge: PowChallenge,
+    pub sponsor_pool: NearToken,
+    pub shard_range: Option<PrefixRange>,
+    pub merkle_roots: LookupMap<u64, [u8; 32]>,
+    pub first_merkle_aggregator: u64,
+    pub deletion_keys: LookupMap<Vec<u8>, DeletionKey>,
+    pub tombstones: LookupSet<Vec<u8>>,
+    pub message_count: Option<u64>,
+}
+
+/// Version 8 started counting messages. Counting those already stored would
+/// take reading every aggregator, so upgraded repositories go without.
+impl From<MessageRepositoryV7> for MessageRepositoryV8 {
+    fn from(v7: MessageRepositoryV7) -> Self {
+        Self {
+            messages: v7.messages,
+            aggregator_history: v7.aggregator_history,
+            aggregator_storage_usage: v7.aggregator_storage_usage,
+            payload_size_classes: v7.payload_size_classes,
+            publish_verifying_key: v7.publish_verifying_key,
+            commitments: v7.commitments,
+            require_commitments: v7.require_commitments,
+            epoch_duration_ms: v7.epoch_duration_ms,
+            current_epoch: v7.current_epoch,
+            aggregator_config: v7.aggregator_config,
+            paused: v7.paused,
+            paused_methods: v7.paused_methods,
+            rate_limit: v7.rate_limit,
+            rate_limit_windows: v7.rate_limit_windows,
+            pow_difficulty: v7.pow_difficulty,
+            pow_challenge: v7.pow_challenge,
+            sponsor_pool: v7.sponsor_pool,
+            shard_range: v7.shard_range,
+            merkle_roots: v7.merkle_roots,
+            first_merkle_aggregator: v7.first_merkle_aggregator,
+            deletion_keys: v7.deletion_keys,
+            tombstones: v7.tombstones,
+            message_count: None,
+        }
+    }
+}
+
+#[near]
+pub struct MessageRepositoryV9 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub aggregator_config: AggregatorConfig,
+    pub paused: bool,
+    pub paused_methods: Vec<PausableMethod>,
+    pub rate_limit: Option<RateLimit>,
+    pub rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+    pub pow_difficulty: Option<u8>,
+    pub pow_challenge: PowChallenge,
+    pub sponsor_pool: NearToken,
+    pub shard_range: Option<PrefixRange>,
+    pub merkle_roots: LookupMap<u64, [u8; 32]>,
+    pub first_merkle_aggregator: u64,
+    pub deletion_keys: LookupMap<Vec<u8>, DeletionKey>,
+    pub tombstones: LookupSet<Vec<u8>>,
+    pub message_count: Option<u64>,
+    pub namespaces: LookupMap<String, Namespace>,
+}
+
+/// Version 9 added namespaces, of which there are none yet.
+impl From<MessageRepositoryV8> for MessageRepositoryV9 {
+    fn from(v8: MessageRepositoryV8) -> Self {
+        Self {
+            messages: v8.messages,
+            aggregator_history: v8.aggregator_history,
+            aggregator_storage_usage: v8.aggregator_storage_usage,
+            payload_size_classes: v8.payload_size_classes,
+            publish_verifying_key: v8.publish_verifying_key,
+            commitments: v8.commitments,
+            require_commitments: v8.require_commitments,
+            epoch_duration_ms: v8.epoch_duration_ms,
+            current_epoch: v8.current_epoch,
+            aggregator_config: v8.aggregator_config,
+            paused: v8.paused,
+            paused_methods: v8.paused_methods,
+            rate_limit: v8.rate_limit,
+            rate_limit_windows: v8.rate_limit_windows,
+            pow_difficulty: v8.pow_difficulty,
+            pow_challenge: v8.pow_challenge,
+            sponsor_pool: v8.sponsor_pool,
+            shard_range: v8.shard_range,
+            merkle_roots: v8.merkle_roots,
+            first_merkle_aggregator: v8.first_merkle_aggregator,
+            deletion_keys: v8.deletion_keys,
+            tombstones: v8.tombstones,
+            message_count: v8.message_count,
+            namespaces: LookupMap::new(StorageKey::Namespaces),
+        }
+    }
+}
+
+#[near]
+pub struct MessageRepositoryV10 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub aggregator_config: AggregatorConfig,
+    pub paused: bool,
+    pub paused_methods: Vec<PausableMethod>,
+    pub rate_limit: Option<RateLimit>,
+    pub rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+    pub pow_difficulty: Option<u8>,
+    pub pow_challenge: PowChallenge,
+    pub sponsor_pool: NearToken,
+    pub shard_range: Option<PrefixRange>,
+    pub merkle_roots: LookupMap<u64, [u8; 32]>,
+    pub first_merkle_aggregator: u64,
+    pub deletion_keys: LookupMap<Vec<u8>, DeletionKey>,
+    pub tombstones: LookupSet<Vec<u8>>,
+    pub message_count: Option<u64>,
+    pub namespaces: LookupMap<String, Namespace>,
+    pub access_mode: AccessMode,
+    pub roles: LookupMap<AccountId, Vec<Role>>,
+}
+
+/// Version 10 added private publishing. Upgraded repositories stay public.
+impl From<MessageRepositoryV9> for MessageRepositoryV10 {
+    fn from(v9: MessageRepositoryV9) -> Self {
+        Self {
+            messages: v9.messages,
+            aggregator_history: v9.aggregator_history,
+            aggregator_storage_usage: v9.aggregator_storage_usage,
+            payload_size_classes: v9.payload_size_classes,
+            publish_verifying_key: v9.publish_verifying_key,
+            commitments: v9.commitments,
+            require_commitments: v9.require_commitments,
+            epoch_duration_ms: v9.epoch_duration_ms,
+            current_epoch: v9.current_epoch,
+            aggregator_config: v9.aggregator_config,
+            paused: v9.paused,
+            paused_methods: v9.paused_methods,
+            rate_limit: v9.rate_limit,
+            rate_limit_windows: v9.rate_limit_windows,
+            pow_difficulty: v9.pow_difficulty,
+            pow_challenge: v9.pow_challenge,
+            sponsor_pool: v9.sponsor_pool,
+            shard_range: v9.shard_range,
+            merkle_roots: v9.merkle_roots,
+            first_merkle_aggregator: v9.first_merkle_aggregator,
+            deletion_keys: v9.deletion_keys,
+            tombstones: v9.tombstones,
+            message_count: v9.message_count,
+            namespaces: v9.namespaces,
+            access_mode: AccessMode::Public,
+            roles: LookupMap::new(StorageKey::Roles),
+        }
+    }
+}
+
+/// Version 11 added service fees, which upgraded repositories do not charge
+/// until the owner sets one.
+impl From<MessageRepositoryV10> for MessageRepositoryV11 {
+    fn from(v10: MessageRepositoryV10) -> Self {
+        Self {
+            messages: v10.messages,
+            aggregator_history: v10.aggregator_history,
+            aggregator_storage_usage: v10.aggregator_storage_usage,
+            payload_size_classes: v10.payload_size_classes,
+            publish_verifying_key: v10.publish_verifying_key,
+            commitments: v10.commitments,
+            require_commitments: v10.require_commitments,
+            epoch_duration_ms: v10.epoch_duration_ms,
+            current_epoch: v10.current_epoch,
+            aggregator_config: v10.aggregator_config,
+            paused: v10.paused,
+            paused_methods: v10.paused_methods,
+            rate_limit: v10.rate_limit,
+            rate_limit_windows: v10.rate_limit_windows,
+            pow_difficulty: v10.pow_difficulty,
+            pow_challenge: v10.pow_challenge,
+            sponsor_pool: v10.sponsor_pool,
+            shard_range: v10.shard_range,
+            merkle_roots: v10.merkle_roots,
+            first_merkle_aggregator: v10.first_merkle_aggregator,
+            deletion_keys: v10.deletion_keys,
+            tombstones: v10.tombstones,
+            message_count: v10.message_count,
+            namespaces: v10.namespaces,
+            access_mode: v10.access_mode,
+            roles: v10.roles,
+            service_fee: None,
+            treasury: NearToken::from_yoctonear(0),
+        }
+    }
+}
+
+#[near]
+pub struct MessageRepositoryV11 {
+    pub messages: LookupMap<Vec<u8>, Message>,
+    pub aggregator_history: Vector<AggregatorRecord>,
+    pub aggregator_storage_usage: u64,
+    pub payload_size_classes: Vec<u32>,
+    pub publish_verifying_key: Option<VerifyingKey>,
+    pub commitments: LookupMap<Vec<u8>, Commitment>,
+    pub require_commitments: bool,
+    pub epoch_duration_ms: Option<u64>,
+    pub current_epoch: u64,
+    pub aggregator_config: AggregatorConfig,
+    pub paused: bool,
+    pub paused_methods: Vec<PausableMethod>,
+    pub rate_limit: Option<RateLimit>,
+    pub rate_limit_windows: LookupMap<AccountId, RateLimitWindow>,
+    pub pow_difficulty: Option<u8>,
+    pub pow_challenge: PowChallenge,
+    pub sponsor_pool: NearToken,
+    pub shard_range: Option<PrefixRange>,
+    pub merkle_roots: LookupMap<u64, [u8; 32]>,
+    pub first_merkle_aggregator: u64,
+    pub deletion_keys: LookupMap<Vec<u8>, DeletionKey>,
+    pub tombstones: LookupSet<Vec<u8>>,
+    pub message_count: Option<u64>,
+    pub namespaces: LookupMap<String, Namespace>,
+    pub access_mode: AccessMode,
+    pub roles: LookupMap<AccountId, Vec<Role>>,
+    pub service_fee: Option<ServiceFee>,
+    pub treasury: NearToken,
+}
+
+/// Version 12 keyed commitments by sequence hash. Commitments made before
+/// the upgrade stay keyed by their digest, and can only be removed.
+impl From<MessageRepositoryV11> for MessageRepository {
+    fn from(v11: MessageRepositoryV11) -> Self {
+        Self {
+            messages: v11.messages,
+            aggregator_history: v11.aggregator_history,
+            aggregator_storage_usage: v11.aggregator_storage_usage,
+            payload_size_classes: v11.payload_size_classes,
+            publish_verifying_key: v11.publish_verifying_key,
+            commitments: v11.commitments,
+            require_commitments: v11.require_commitments,
+            epoch_duration_ms: v11.epoch_duration_ms,
+            current_epoch: v11.current_epoch,
+            aggregator_config: v11.aggregator_config,
+            paused: v11.paused,
+            paused_methods: v11.paused_methods,
+            rate_limit: v11.rate_limit,
+            rate_limit_windows: v11.rate_limit_windows,
+            pow_difficulty: v11.pow_difficulty,
+            pow_challenge: v11.pow_challenge,
+            sponsor_pool: v11.sponsor_pool,
+            shard_range: v11.shard_range,
+            merkle_roots: v11.merkle_roots,
+            first_merkle_aggregator: v11.first_merkle_aggregator,
+            deletion_keys: v11.deletion_keys,
+            tombstones: v11.tombstones,
+            message_count: v11.message_count,
+            namespaces: v11.namespaces,
+            access_mode: v11.access_mode,
+            roles: v11.roles,
+            service_fee: v11.service_fee,
+            treasury: v11.treasury,
+            sequence_commitments: LookupMap::new(StorageKey::SequenceCommitments),
+        }
+    }
+}
+
+fn read_state<T: BorshDeserialize>(schema_version: u32) -> T {
+    env::state_read().unwrap_or_else(|| {
+        env::panic_str(&format!("Failed to read version {schema_version} state."))
+    })
+}
+
+/// The contract account itself becomes the owner, since it is the only
+/// account that could have deployed the new code. Storage registration costs
+/// the same as in a new repository.
+pub fn from_v0() -> MessageRepository {
+    let mut contract = from_v1_state(read_state::<MessageRepositoryV0>(0).into());
+    Owner::init(&mut contract, &env::current_account_id());
+    contract.set_storage_balance_bounds(&StorageBalanceBounds {
+        min: env::storage_byte_cost().saturating_mul(STORAGE_REGISTRATION_BYTES),
+        max: None,
+    });
+
+    contract
+}
+
+pub fn from_v1() -> MessageRepository {
+    from_v1_state(read_state(1))
+}
+
+fn from_v1_state(v1: MessageRepositoryV1) -> MessageRepository {
+    let v3 = MessageRepositoryV3::from(MessageRepositoryV2::from(v1));
+    let v5 = MessageRepositoryV5::from(MessageRepositoryV4::from(v3));
+    let v7 = MessageRepositoryV7::from(MessageRepositoryV6::from(v5));
+    let v9 = MessageRepositoryV9::from(MessageRepositoryV8::from(v7));
+    MessageRepositoryV11::from(MessageRepositoryV10::from(v9)).into()
+}
+
+pub fn from_v2() -> MessageRepository {
+    let v3 = MessageRepositoryV3::from(read_state::<MessageRepositoryV2>(2));
+    let v5 = MessageRepositoryV5::from(MessageRepositoryV4::from(v3));
+    let v7 = MessageRepositoryV7::from(MessageRepositoryV6::from(v5));
+    let v9 = MessageRepositoryV9::from(MessageRepositoryV8::from(v7));
+    MessageRepositoryV11::from(MessageRepositoryV10::from(v9)).into()
+}
+
+pub fn from_v3() -> MessageRepository {
+    let v4 = MessageRepositoryV4::from(read_state::<MessageRepositoryV3>(3));
+    let v6 = MessageRepositoryV6::from(MessageRepositoryV5::from(v4));
+    let v8 = MessageRepositoryV8::from(MessageRepositoryV7::from(v6));
+    let v10 = MessageRepositoryV10::from(MessageRepositoryV9::from(v8));
+    MessageRepositoryV11::from(v10).into()
+}
+
+pub fn from_v4() -> MessageRepository {
+    let v5 = MessageRepositoryV5::from(read_state::<MessageRepositoryV4>(4));
+    let v7 = MessageRepositoryV7::from(MessageRepositoryV6::from(v5));
+    let v9 = MessageRepositoryV9::from(MessageRepositoryV8::from(v7));
+    MessageRepositoryV11::from(MessageRepositoryV10::from(v9)).into()
+}
+
+pub fn from_v5() -> MessageRepository {
+    let v6 = MessageRepositoryV6::from(read_state::<MessageRepositoryV5>(5));
+    let v8 = MessageRepositoryV8::from(MessageRepositoryV7::from(v6));
+    let v10 = MessageRepositoryV10::from(MessageRepositoryV9::from(v8));
+    MessageRepositoryV11::from(v10).into()
+}
+
+pub fn from_v6() -> MessageRepository {
+    let v7 = MessageRepositoryV7::from(read_state::<MessageRepositoryV6>(6));
+    let v9 = MessageRepositoryV9::from(MessageRepositoryV8::from(v7));
+    MessageRepositoryV11::from(MessageRepositoryV10::from(v9)).into()
+}
+
+pub fn from_v7() -> MessageRepository {
+    let v8 = MessageRepositoryV8::from(read_state::<MessageRepositoryV7>(7));
+    let v10 = MessageRepositoryV10::from(MessageRepositoryV9::from(v8));
+    MessageRepositoryV11::from(v10).into()
+}
+
+pub fn from_v8() -> MessageRepository {
+    let v9 = MessageRepositoryV9::from(read_state::<MessageRepositoryV8>(8));
+    MessageRepositoryV11::from(MessageRepositoryV10::from(v9)).into()
+}
+
+pub fn from_v9() -> MessageRepository {
+    let v10 = MessageRepositoryV10::from(read_state::<MessageRepositoryV9>(9));
+    MessageRepositoryV11::from(v10).into()
+}
+
+pub fn from_v10() -> MessageRepository {
+    MessageRepositoryV11::from(read_state::<MessageRepositoryV10>(10)).into()
+}
+
+pub fn from_v11() -> MessageRepository {
+    read_state::<MessageRepositoryV11>(11).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use cuckoofilter::CuckooFilter;
+    use near_sdk::{json_types::Base64VecU8, test_utils::VMContextBuilder, testing_env};
+    use near_sdk_contract_tools::{owner::OwnerExternal, standard::nep145::Nep145};
+
+    use super::*;
+    use crate::{new_aggregator, write, SCHEMA_VERSION};
+
+    fn repository_account() -> AccountId {
+        "repository.near".parse().unwrap()
+    }
+
+    fn set_context() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(repository_account())
+            .predecessor_account_id(repository_account())
+            .block_timestamp(5_000_000_000)
+            .build());
+    }
+
+    #[test]
+    fn migrate_from_v0() {
+        set_context();
+
+        let mut v0 = MessageRepositoryV0 {
+            messages: LookupMap::new(StorageKey::Messages),
+            aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            aggregator_storage_usage: 1000,
+        };
+        v0.messages.insert(
+            &vec![1; 32],
+            &Message {
+                message: vec![2; 64].into(),
+                block_timestamp_ms: 4000,
+            },
+        );
+        let mut sealed =
+            CuckooFilter::<SipHasher>::with_capacity(DEFAULT_AGGREGATOR_CAPACITY as usize);
+        sealed.add(&[1u8; 32][..]).unwrap();
+        v0.aggregator_history.push(&AggregatorRecordV0 {
+            end_block_timestamp_ms: 4500,
+            aggregator: sealed.into(),
+        });
+        let mut current =
+            CuckooFilter::<SipHasher>::with_capacity(DEFAULT_AGGREGATOR_CAPACITY as usize);
+        current.add(&[3u8; 32][..]).unwrap();
+        current.add(&[4u8; 32][..]).unwrap();
+        write(
+            StorageKey::CurrentAggregator,
+            BorshCuckooFilter::from(current),
+        );
+        env::state_write(&v0);
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert_eq!(contract.own_get_owner(), Some(repository_account()));
+        assert_eq!(
+            contract.storage_balance_bounds().min,
+            env::storage_byte_cost().saturating_mul(STORAGE_REGISTRATION_BYTES),
+        );
+        assert_eq!(contract.get_config(), AggregatorConfig::default());
+        assert_eq!(
+            contract
+                .get_message(Base64VecU8(vec![1; 32]), None)
+                .unwrap()
+                .block_timestamp_ms,
+            4000,
+        );
+
+        let page = contract.get_aggregators(None, None, None, None);
+        assert_eq!(page.aggregators.len(), 2);
+        assert_eq!(page.aggregators[0].end_block_timestamp_ms, Some(4500));
+        for (view, sequence_hashes) in page.aggregators.iter().zip([&[1u8][..], &[3, 4]]) {
+            assert_eq!(view.item_count, sequence_hashes.len() as u64);
+            let Filter::Cuckoo(filter) = Filter::try_from_slice(&view.filter.0[1..]).unwrap()
+            else {
+                panic!("expected a cuckoo filter");
+            };
+            for i in sequence_hashes {
+                assert!(filter.0.contains(&[*i; 32][..]));
+            }
+        }
+    }
+
+    #[test]
+    fn migrate_from_v1() {
+        set_context();
+
+        let mut v1 = MessageRepositoryV1 {
+            messages: LookupMap::new(StorageKey::Messages),
+            aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            aggregator_storage_usage: 1000,
+            payload_size_classes: vec![64],
+            publish_verifying_key: None,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: true,
+            epoch_duration_ms: Some(1000),
+            current_epoch: 5,
+            filter_kind: FilterKind::Xor,
+        };
+        v1.messages.insert(
+            &vec![1; 32],
+            &Message {
+                message: vec![2; 64].into(),
+                block_timestamp_ms: 5000,
+            },
+        );
+        write(
+            StorageKey::CurrentAggregator,
+            new_aggregator(&AggregatorConfig {
+                filter_kind: FilterKind::Xor,
+                ..Default::default()
+            }),
+        );
+        env::state_write(&v1);
+        write(StorageKey::SchemaVersion, 1u32);
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert!(!contract.get_paused());
+        assert_eq!(contract.get_paused_methods(), vec![]);
+        assert_eq!(contract.get_rate_limit(), None);
+        assert_eq!(contract.get_payload_size_classes(), vec![64]);
+        assert!(contract.get_require_commitments());
+        assert_eq!(contract.get_epochs(None).unwrap().first_epoch, 5);
+        assert_eq!(
+            contract
+                .get_message(Base64VecU8(vec![1; 32]), None)
+                .unwrap()
+                .message
+                .0,
+            vec![2; 64],
+        );
+        assert_eq!(
+            contract.get_config(),
+            AggregatorConfig {
+                capacity: DEFAULT_AGGREGATOR_CAPACITY,
+                fingerprint_bits: 8,
+                filter_kind: FilterKind::Xor,
+            },
+        );
+    }
+
+    #[test]
+    fn migrate_from_v2() {
+        set_context();
+
+        let v2 = MessageRepositoryV2 {
+            messages: LookupMap::new(StorageKey::Messages),
+            aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            aggregator_storage_usage: 1000,
+            payload_size_classes: vec![],
+            publish_verifying_key: None,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: false,
+            epoch_duration_ms: None,
+            current_epoch: 0,
+            filter_kind: FilterKind::Cuckoo,
+            paused: true,
+            paused_methods: vec![],
+            rate_limit: None,
+            rate_limit_windows: LookupMap::new(StorageKey::RateLimitWindows),
+        };
+        env::state_write(&v2);
+        write(StorageKey::SchemaVersion, 2u32);
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert!(contract.get_paused());
+        assert_eq!(contract.get_pow_challenge(), None);
+        assert_eq!(contract.get_sponsor_pool(), NearToken::from_yoctonear(0));
+    }
+
+    #[test]
+    fn migrate_from_v5() {
+        set_context();
+
+        let aggregator_config = AggregatorConfig::default();
+        let v5 = MessageRepositoryV5 {
+            messages: LookupMap::new(StorageKey::Messages),
+            aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            aggregator_storage_usage: 1000,
+            payload_size_classes: vec![],
+            publish_verifying_key: None,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: false,
+            epoch_duration_ms: None,
+            current_epoch: 0,
+            aggregator_config,
+            paused: false,
+            paused_methods: vec![],
+            rate_limit: None,
+            rate_limit_windows: LookupMap::new(StorageKey::RateLimitWindows),
+            pow_difficulty: None,
+            pow_challenge: PowChallenge::new(),
+            sponsor_pool: NearToken::from_yoctonear(0),
+            shard_range: None,
+        };
+        env::state_write(&v5);
+        write(StorageKey::SchemaVersion, 5u32);
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        // the current aggregator predates its leaves
+        assert_eq!(contract.first_merkle_aggregator, 1);
+        assert_eq!(
+            contract.aggregator_storage_usage,
+            1000 + MERKLE_LEAF_STORAGE_BYTES * aggregator_config.capacity
+                + MERKLE_ROOT_STORAGE_BYTES,
+        );
+    }
+
+    #[test]
+    fn migrate_from_v6() {
+        set_context();
+
+        let mut v6 = MessageRepositoryV6 {
+            messages: LookupMap::new(StorageKey::Messages),
+            aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            aggregator_storage_usage: 1000,
+            payload_size_classes: vec![],
+            publish_verifying_key: None,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: false,
+            epoch_duration_ms: None,
+            current_epoch: 0,
+            aggregator_config: AggregatorConfig::default(),
+            paused: false,
+            paused_methods: vec![],
+            rate_limit: None,
+            rate_limit_windows: LookupMap::new(StorageKey::RateLimitWindows),
+            pow_difficulty: None,
+            pow_challenge: PowChallenge::new(),
+            sponsor_pool: NearToken::from_yoctonear(0),
+            shard_range: None,
+            merkle_roots: LookupMap::new(StorageKey::MerkleRoots),
+            first_merkle_aggregator: 3,
+        };
+        v6.messages.insert(
+            &vec![1; 32],
+            &Message {
+                message: vec![2; 64].into(),
+                block_timestamp_ms: 5000,
+            },
+        );
+        env::state_write(&v6);
+        write(StorageKey::SchemaVersion, 6u32);
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert_eq!(contract.first_merkle_aggregator, 3);
+        assert!(contract.get_message(vec![1; 32].into(), None).is_some());
+        assert_eq!(contract.get_deletion_key(vec![1; 32].into()), None);
+        assert!(!contract.is_deleted(vec![1; 32].into()));
+    }
+
+    #[test]
+    fn migrate_from_v7() {
+        set_context();
+
+        let v7 = MessageRepositoryV7 {
+            messages: LookupMap::new(StorageKey::Messages),
+            aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            aggregator_storage_usage: 1000,
+            payload_size_classes: vec![],
+            publish_verifying_key: None,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: false,
+            epoch_duration_ms: None,
+            current_epoch: 0,
+            aggregator_config: AggregatorConfig::default(),
+            paused: false,
+            paused_methods: vec![],
+            rate_limit: None,
+            rate_limit_windows: LookupMap::new(StorageKey::RateLimitWindows),
+            pow_difficulty: None,
+            pow_challenge: PowChallenge::new(),
+            sponsor_pool: NearToken::from_yoctonear(0),
+            shard_range: None,
+            merkle_roots: LookupMap::new(StorageKey::MerkleRoots),
+            first_merkle_aggregator: 0,
+            deletion_keys: LookupMap::new(StorageKey::DeletionKeys),
+            tombstones: LookupSet::new(StorageKey::Tombstones),
+        };
+        env::state_write(&v7);
+        write(StorageKey::SchemaVersion, 7u32);
+        write(
+            StorageKey::CurrentAggregator,
+            new_aggregator(&v7.aggregator_config),
+        );
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert_eq!(contract.get_stats(None).message_count, None);
+        assert_eq!(contract.get_stats(None).aggregator_storage_usage, 1000);
+    }
+
+    #[test]
+    fn migrate_from_v8() {
+        set_context();
+
+        let v8 = MessageRepositoryV8 {
+            messages: LookupMap::new(StorageKey::Messages),
+            aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            aggregator_storage_usage: 1000,
+            payload_size_classes: vec![],
+            publish_verifying_key: None,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: false,
+            epoch_duration_ms: None,
+            current_epoch: 0,
+            aggregator_config: AggregatorConfig::default(),
+            paused: false,
+            paused_methods: vec![],
+            rate_limit: None,
+            rate_limit_windows: LookupMap::new(StorageKey::RateLimitWindows),
+            pow_difficulty: None,
+            pow_challenge: PowChallenge::new(),
+            sponsor_pool: NearToken::from_yoctonear(0),
+            shard_range: None,
+            merkle_roots: LookupMap::new(StorageKey::MerkleRoots),
+            first_merkle_aggregator: 0,
+            deletion_keys: LookupMap::new(StorageKey::DeletionKeys),
+            tombstones: LookupSet::new(StorageKey::Tombstones),
+            message_count: Some(4),
+        };
+        env::state_write(&v8);
+        write(StorageKey::SchemaVersion, 8u32);
+        write(
+            StorageKey::CurrentAggregator,
+            new_aggregator(&v8.aggregator_config),
+        );
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert_eq!(contract.get_stats(None).message_count, Some(4));
+        assert_eq!(contract.get_namespace_config("tenant".to_string()), None);
+    }
+
+    #[test]
+    fn migrate_from_v9() {
+        set_context();
+
+        let v9 = MessageRepositoryV9 {
+            messages: LookupMap::new(StorageKey::Messages),
+            aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            aggregator_storage_usage: 1000,
+            payload_size_classes: vec![],
+            publish_verifying_key: None,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: false,
+            epoch_duration_ms: None,
+            current_epoch: 0,
+            aggregator_config: AggregatorConfig::default(),
+            paused: false,
+            paused_methods: vec![],
+            rate_limit: None,
+            rate_limit_windows: LookupMap::new(StorageKey::RateLimitWindows),
+            pow_difficulty: None,
+            pow_challenge: PowChallenge::new(),
+            sponsor_pool: NearToken::from_yoctonear(0),
+            shard_range: None,
+            merkle_roots: LookupMap::new(StorageKey::MerkleRoots),
+            first_merkle_aggregator: 0,
+            deletion_keys: LookupMap::new(StorageKey::DeletionKeys),
+            tombstones: LookupSet::new(StorageKey::Tombstones),
+            message_count: Some(4),
+            namespaces: LookupMap::new(StorageKey::Namespaces),
+        };
+        env::state_write(&v9);
+        write(StorageKey::SchemaVersion, 9u32);
+        write(
+            StorageKey::CurrentAggregator,
+            new_aggregator(&v9.aggregator_config),
+        );
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert_eq!(contract.get_access_mode(), AccessMode::Public);
+        assert!(contract.can_publish("alice.near".parse().unwrap()));
+    }
+
+    #[test]
+    fn migrate_from_v10() {
+        set_context();
+
+        let v10 = MessageRepositoryV10 {
+            messages: LookupMap::new(StorageKey::Messages),
+            aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            aggregator_storage_usage: 1000,
+            payload_size_classes: vec![],
+            publish_verifying_key: None,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: false,
+            epoch_duration_ms: None,
+            current_epoch: 0,
+            aggregator_config: AggregatorConfig::default(),
+            paused: false,
+            paused_methods: vec![],
+            rate_limit: None,
+            rate_limit_windows: LookupMap::new(StorageKey::RateLimitWindows),
+            pow_difficulty: None,
+            pow_challenge: PowChallenge::new(),
+            sponsor_pool: NearToken::from_yoctonear(0),
+            shard_range: None,
+            merkle_roots: LookupMap::new(StorageKey::MerkleRoots),
+            first_merkle_aggregator: 0,
+            deletion_keys: LookupMap::new(StorageKey::DeletionKeys),
+            tombstones: LookupSet::new(StorageKey::Tombstones),
+            message_count: Some(4),
+            namespaces: LookupMap::new(StorageKey::Namespaces),
+            access_mode: AccessMode::Private,
+            roles: LookupMap::new(StorageKey::Roles),
+        };
+        env::state_write(&v10);
+        write(StorageKey::SchemaVersion, 10u32);
+        write(
+            StorageKey::CurrentAggregator,
+            new_aggregator(&v10.aggregator_config),
+        );
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert_eq!(contract.get_access_mode(), AccessMode::Private);
+        assert_eq!(contract.get_service_fee(), None);
+        assert!(contract.get_treasury().is_zero());
+    }
+
+    #[test]
+    fn migrate_from_v11() {
+        set_context();
+
+        let mut v11 = MessageRepositoryV11 {
+            messages: LookupMap::new(StorageKey::Messages),
+            aggregator_history: Vector::new(StorageKey::AggregatorHistory),
+            aggregator_storage_usage: 1000,
+            payload_size_classes: vec![],
+            publish_verifying_key: None,
+            commitments: LookupMap::new(StorageKey::Commitments),
+            require_commitments: false,
+            epoch_duration_ms: None,
+            current_epoch: 0,
+            aggregator_config: AggregatorConfig::default(),
+            paused: false,
+            paused_methods: vec![],
+            rate_limit: None,
+            rate_limit_windows: LookupMap::new(StorageKey::RateLimitWindows),
+            pow_difficulty: None,
+            pow_challenge: PowChallenge::new(),
+            sponsor_pool: NearToken::from_yoctonear(0),
+            shard_range: None,
+            merkle_roots: LookupMap::new(StorageKey::MerkleRoots),
+            first_merkle_aggregator: 0,
+            deletion_keys: LookupMap::new(StorageKey::DeletionKeys),
+            tombstones: LookupSet::new(StorageKey::Tombstones),
+            message_count: Some(4),
+            namespaces: LookupMap::new(StorageKey::Namespaces),
+            access_mode: AccessMode::Private,
+            roles: LookupMap::new(StorageKey::Roles),
+            service_fee: None,
+            treasury: NearToken::from_yoctonear(7),
+        };
+        v11.commitments.insert(
+            &vec![1; 32],
+            &Commitment {
+                account_id: "alice.near".parse().unwrap(),
+                block_height: 0,
+                block_timestamp_ms: 5000,
+                storage_deposit: NearToken::from_yoctonear(0),
+            },
+        );
+        env::state_write(&v11);
+        write(StorageKey::SchemaVersion, 11u32);
+        write(
+            StorageKey::CurrentAggregator,
+            new_aggregator(&v11.aggregator_config),
+        );
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert_eq!(contract.get_treasury(), NearToken::from_yoctonear(7));
+        // commitments made before the upgrade stay keyed by their digest
+        assert!(contract.get_commitment(vec![1; 32].into(), None).is_some());
+    }
+
+    #[test]
+    fn migrate_current_version_is_a_no_op() {
+        set_context();
+
+        let contract = MessageRepository::new(None, None, None, None, None, None);
+        env::state_write(&contract);
+
+        let contract = MessageRepository::migrate();
+
+        assert_eq!(contract.get_schema_version(), SCHEMA_VERSION);
+        assert_eq!(contract.own_get_owner(), Some(repository_account()));
+    }
+
+    #[test]
+    #[should_panic = "State was written by a newer schema version."]
+    fn migrate_from_newer_version() {
+        set_context();
+
+        let contract = MessageRepository::new(None, None, None, None, None, None);
+        env::state_write(&contract);
+        write(StorageKey::SchemaVersion, SCHEMA_VERSION + 1);
+
+        MessageRepository::migrate();
+    }
+}
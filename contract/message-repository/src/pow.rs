@@ -0,0 +1,104 @@
+//! Hashcash-style proofs of work for publishing without a deposit.
+//!
+//! A proof is a nonce for which
+//! `sha256(challenge | len(sequence_hash) | sequence_hash | message | nonce)`
+//! starts with at least the required number of zero bits, where the length is
+//! a little-endian `u32` and the nonce a little-endian `u64`. Contracts
+//! cannot read block hashes, so the challenge plays the part of a recent
+//! block hash: the random seed of the block that last rotated it.
+
+use near_sdk::{env, near};
+
+/// How many blocks a challenge stays current for. It is rotated by the first
+/// proof-of-work publish after that, and stays valid for one more rotation so
+/// that proofs in flight are not lost.
+pub const POW_CHALLENGE_ROTATION_BLOCKS: u64 = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+#[near]
+pub struct PowChallenge {
+    pub block_height: u64,
+    pub current: [u8; 32],
+    pub previous: [u8; 32],
+}
+
+impl PowChallenge {
+    pub fn new() -> Self {
+        let seed = env::random_seed_array();
+        Self {
+            block_height: env::block_height(),
+            current: seed,
+            previous: seed,
+        }
+    }
+
+    pub fn rotate_if_stale(&mut self) {
+        if env::block_height() >= self.block_height + POW_CHALLENGE_ROTATION_BLOCKS {
+            self.previous = self.current;
+            self.current = env::random_seed_array();
+            self.block_height = env::block_height();
+        }
+    }
+
+    pub fn is_solved_by(
+        &self,
+        difficulty: u8,
+        sequence_hash: &[u8],
+        message: &[u8],
+        nonce: u64,
+    ) -> bool {
+        [self.current, self.previous].iter().any(|challenge| {
+            leading_zero_bits(&pow_hash(challenge, sequence_hash, message, nonce))
+                >= difficulty as u32
+        })
+    }
+}
+
+pub fn pow_hash(challenge: &[u8; 32], sequence_hash: &[u8], message: &[u8], nonce: u64) -> Vec<u8> {
+    env::sha256(
+        &[
+            &challenge[..],
+            &(sequence_hash.len() as u32).to_le_bytes(),
+            sequence_hash,
+            message,
+            &nonce.to_le_bytes(),
+        ]
+        .concat(),
+    )
+}
+
+pub fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn count_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0x80]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x01]), 15);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    // The client's solver tests hash the same inputs.
+    #[test]
+    fn hash_vector() {
+        assert_eq!(
+            hex(&pow_hash(&[7; 32], &[1; 32], b"ciphertext", 42)),
+            "8a333572adf22a77d56dc4e7f9a08d1170cd5126eb3c30691f7a85007409ef6a",
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Xor filters (Graf and Lemire, 2020) for sealed aggregators.
+//!
+//! An xor filter can only be built from a complete set of keys, so while an
+//! aggregator is still accepting messages it is a [`KeySet`] of keyed
+//! SipHash-1-3 digests, which is converted once the aggregator is sealed.
+
+use std::ops::BitXor;
+
+use near_sdk::{
+    borsh::{BorshDeserialize, BorshSchema, BorshSerialize},
+    env,
+};
+
+use crate::filter::keyed_hash;
+
+const MAX_BUILD_ATTEMPTS: u32 = 1000;
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct KeySet {
+    seed: u64,
+    keys: Vec<u64>,
+}
+
+impl KeySet {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, keys: vec![] }
+    }
+
+    pub fn insert(&mut self, bytes: &[u8]) {
+        self.keys.push(keyed_hash(self.seed, bytes));
+    }
+
+    pub fn contains(&self, bytes: &[u8]) -> bool {
+        self.keys.contains(&keyed_hash(self.seed, bytes))
+    }
+
+    pub fn len(&self) -> u64 {
+        self.keys.len() as u64
+    }
+
+    pub fn into_xor_filter<F: Fingerprint>(self) -> XorFilter<F> {
+        XorFilter::build(self.seed, self.keys)
+    }
+}
+
+/// The width of an xor filter's fingerprints. 8-bit filters have a false
+/// positive rate of about 1/256 at 9.84 bits per key, 16-bit filters about
+/// 1/65536 at 19.7 bits per key.
+pub trait Fingerprint: Copy + Default + PartialEq + BitXor<Output = Self> {
+    fn from_hash(hash: u64) -> Self;
+}
+
+impl Fingerprint for u8 {
+    fn from_hash(hash: u64) -> Self {
+        (hash ^ (hash >> 32)) as u8
+    }
+}
+
+impl Fingerprint for u16 {
+    fn from_hash(hash: u64) -> Self {
+        (hash ^ (hash >> 32)) as u16
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct XorFilter<F> {
+    key_seed: u64,
+    seed: u64,
+    len: u32,
+    block_length: u32,
+    fingerprints: Vec<F>,
+}
+
+/// The MurmurHash3 64-bit finalizer.
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// One slot in each of the three blocks.
+fn slots(hash: u64, block_length: u32) -> [usize; 3] {
+    let reduce = |rotation: u32| {
+        ((hash.rotate_left(rotation) as u32 as u64 * block_length as u64) >> 32) as usize
+    };
+    let block_length = block_length as usize;
+
+    [
+        reduce(0),
+        reduce(21) + block_length,
+        reduce(42) + 2 * block_length,
+    ]
+}
+
+impl<F: Fingerprint> XorFilter<F> {
+    pub fn build(key_seed: u64, mut keys: Vec<u64>) -> Self {
+        keys.sort_unstable();
+        keys.dedup();
+
+        let block_length = ((32 + (keys.len() * 123).div_ceil(100)) / 3) as u32;
+        let size = 3 * block_length as usize;
+        let mut seed = mix(key_seed);
+
+        for _ in 0..MAX_BUILD_ATTEMPTS {
+            let mut counts = vec![0u32; size];
+            let mut masks = vec![0u64; size];
+            for &key in &keys {
+                let hash = mix(key.wrapping_add(seed));
+                for slot in slots(hash, block_length) {
+                    counts[slot] += 1;
+                    masks[slot] ^= hash;
+                }
+            }
+
+            // peel off the keys that are alone in one of their slots
+            let mut queue = (0..size).filter(|&i| counts[i] == 1).collect::<Vec<_>>();
+            let mut stack = Vec::with_capacity(keys.len());
+            while let Some(i) = queue.pop() {
+                if counts[i] != 1 {
+                    continue;
+                }
+                let hash = masks[i];
+                stack.push((i, hash));
+                for slot in slots(hash, block_length) {
+                    counts[slot] -= 1;
+                    masks[slot] ^= hash;
+                    if counts[slot] == 1 {
+                        queue.push(slot);
+                    }
+                }
+            }
+
+            if stack.len() == keys.len() {
+                let mut fingerprints = vec![F::default(); size];
+                for &(i, hash) in stack.iter().rev() {
+                    let [a, b, c] = slots(hash, block_length);
+                    fingerprints[i] =
+                        F::from_hash(hash) ^ fingerprints[a] ^ fingerprints[b] ^ fingerprints[c];
+                }
+
+                return Self {
+                    key_seed,
+                    seed,
+                    len: keys.len() as u32,
+                    block_length,
+                    fingerprints,
+                };
+            }
+
+            seed = mix(seed.wrapping_add(GOLDEN_GAMMA));
+        }
+
+        env::panic_str("Failed to build xor filter.");
+    }
+
+    pub fn contains(&self, bytes: &[u8]) -> bool {
+        let hash = mix(keyed_hash(self.key_seed, bytes).wrapping_add(self.seed));
+        let [a, b, c] = slots(hash, self.block_length);
+        F::from_hash(hash) == self.fingerprints[a] ^ self.fingerprints[b] ^ self.fingerprints[c]
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_key_set_has_no_false_negatives() {
+        let mut key_set = KeySet::new(7);
+        for i in 0u32..1000 {
+            key_set.insert(&i.to_le_bytes());
+        }
+        key_set.insert(&0u32.to_le_bytes());
+        assert!(key_set.contains(&999u32.to_le_bytes()));
+
+        let filter = key_set.into_xor_filter::<u8>();
+        assert_eq!(filter.len(), 1000);
+        assert!((0u32..1000).all(|i| filter.contains(&i.to_le_bytes())));
+
+        let false_positives = (1000u32..11000)
+            .filter(|i| filter.contains(&i.to_le_bytes()))
+            .count();
+        assert!(false_positives < 100, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn empty_filter() {
+        let filter = KeySet::new(7).into_xor_filter::<u8>();
+        assert_eq!(filter.len(), 0);
+        assert_eq!(filter.fingerprints.len(), 30);
+    }
+
+    #[test]
+    fn wider_fingerprints_have_fewer_false_positives() {
+        let mut key_set = KeySet::new(7);
+        for i in 0u32..1000 {
+            key_set.insert(&i.to_le_bytes());
+        }
+
+        let filter = key_set.into_xor_filter::<u16>();
+        assert!((0u32..1000).all(|i| filter.contains(&i.to_le_bytes())));
+
+        let false_positives = (1000u32..101000)
+            .filter(|i| filter.contains(&i.to_le_bytes()))
+            .count();
+        assert!(false_positives < 10, "{false_positives} false positives");
+    }
+}